@@ -0,0 +1,48 @@
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::process::Command;
+use zip::ZipArchive;
+
+static LANGUAGE_ZIP: &str = "assets/language/language.zip";
+static HANS_PREFIX: &str = "language/zh_cn_hans/";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", LANGUAGE_ZIP);
+    let bytes = std::fs::read(LANGUAGE_ZIP)
+        .unwrap_or_else(|error| panic!("Failed to read [{}]: {}", LANGUAGE_ZIP, error));
+    if let Err(error) = validate_language_zip(&bytes) {
+        panic!("Invalid language pack [{}]: {}", LANGUAGE_ZIP, error);
+    }
+    let sha256 = Sha256::digest(&bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    println!("cargo:rustc-env=LANGUAGE_ZIP_SHA256={}", sha256);
+    let target = std::env::var("TARGET").unwrap_or_default();
+    println!("cargo:rustc-env=ALIEN_TARGET={}", target);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rustc-env=ALIEN_GIT_COMMIT={}", git_commit());
+}
+
+/// Short hash of the checked out commit, `unknown` outside a git checkout
+fn git_commit() -> String {
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn validate_language_zip(bytes: &[u8]) -> Result<usize, String> {
+    let archive = ZipArchive::new(Cursor::new(bytes)).map_err(|error| error.to_string())?;
+    let count = archive
+        .file_names()
+        .filter(|name| name.to_lowercase().starts_with(HANS_PREFIX))
+        .count();
+    if count == 0 {
+        return Err(format!("no entries under [{}]", HANS_PREFIX));
+    }
+    Ok(count)
+}
@@ -0,0 +1,841 @@
+use crate::error::AlienError;
+use crate::installer::{BackupMode, Installer, OnMissing};
+use crate::manifest::{self, FileState, ManifestItem};
+use crate::metadata::{self, BackupMetadata};
+use crate::report::{PhaseTimer, Timings};
+use crate::{
+    backups, chunked, descriptors, durability, hashing, passphrase, path_structure, permissions,
+    tasks,
+};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use futures::StreamExt;
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use zip::write::{FileOptions, SimpleFileOptions};
+use zip::{AesMode, CompressionMethod, ZipArchive, ZipWriter};
+
+impl Installer {
+    /// Backs up the game's copy of every pack file, as `options.backup_mode` says
+    pub async fn backup(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+        pack_version: &str,
+        timings: &mut Timings,
+    ) -> Result<(PathBuf, BackupMetadata), AlienError> {
+        match self.options.backup_mode {
+            BackupMode::Clonefile if self.options.encrypt => {
+                tracing::warn!("Clone tree backups cannot be encrypted, falling back to zip")
+            }
+            BackupMode::Clonefile => {
+                match self.backup_to_clone_tree(filtered, pack_version).await {
+                    Ok(backup) => return Ok(backup),
+                    Err(error) => tracing::warn!(
+                        "Clonefile backup is unsupported here, falling back to zip: {:#}",
+                        error
+                    ),
+                }
+            }
+            BackupMode::Zip => {}
+        }
+        Ok(self.backup_to_zip(filtered, pack_version, timings).await?)
+    }
+
+    pub(crate) fn new_backup_metadata(
+        &self,
+        encrypted: bool,
+        pack_version: &str,
+    ) -> Result<BackupMetadata> {
+        Ok(BackupMetadata {
+            encrypted,
+            game_dir: Some(self.game_dir.as_path().to_path_buf()),
+            taken_at: Some(SystemTime::now()),
+            pack_version: Some(pack_version.to_string()),
+            original: backups::list_backups(self.backup_dir.as_path())?.is_empty(),
+            ..Default::default()
+        })
+    }
+
+    pub(crate) async fn backup_to_clone_tree(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+        pack_version: &str,
+    ) -> Result<(PathBuf, BackupMetadata)> {
+        let instant = std::time::Instant::now();
+        let jobs = self.options.jobs;
+        let alien_isolation_dir = self.game_dir.as_path();
+        let clone_tree = backups::new_clone_tree_path(self.backup_dir.as_path());
+        let partial_tree = backups::partial_path(&clone_tree);
+        tracing::info!(
+            "Cloning [{}] to [{}] with {} workers",
+            alien_isolation_dir
+                .join(path_structure::data_dir())
+                .display(),
+            clone_tree.display(),
+            jobs,
+        );
+        let mut metadata = self.new_backup_metadata(false, pack_version)?;
+        tokio::fs::create_dir_all(&partial_tree).await?;
+
+        let mut entries =
+            futures::stream::iter(filtered.iter().filter(|(item, _)| item.is_file).map(
+                |(item, striped)| {
+                    let missing = if item.optional {
+                        OnMissing::Skip
+                    } else {
+                        self.options.on_missing
+                    };
+                    let partial_tree = partial_tree.as_path();
+                    async move {
+                        let entry = self
+                            .backup_entry(
+                                item,
+                                self.game_path(striped),
+                                striped,
+                                missing,
+                                None,
+                                Some(partial_tree),
+                            )
+                            .await?;
+                        Ok::<_, color_eyre::Report>((striped, entry))
+                    }
+                },
+            ))
+            .buffered(jobs.max(1));
+        while let Some(entry) = entries.next().await {
+            let (striped, entry) = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    let _ = tokio::fs::remove_dir_all(&partial_tree).await;
+                    return Err(error);
+                }
+            };
+            match entry {
+                BackupEntry::Created => metadata.created.push(striped.to_path_buf()),
+                BackupEntry::AlreadyPatched => metadata.already_patched.push(striped.to_path_buf()),
+                BackupEntry::Cloned
+                | BackupEntry::Overwritten { .. }
+                | BackupEntry::Spooled { .. } => metadata.overwritten.push(striped.to_path_buf()),
+            }
+            self.reporter.on_file_done("backup", striped, 0);
+        }
+        drop(entries);
+        metadata.checksums = self.checksum_originals(&metadata.overwritten).await?;
+        tokio::fs::write(
+            partial_tree.join(metadata::METADATA_ENTRY),
+            metadata.to_bytes()?,
+        )
+        .await?;
+        tokio::fs::rename(&partial_tree, &clone_tree)
+            .await
+            .with_context(|| format!("Failed to move backup into [{}]", clone_tree.display()))?;
+        durability::sync_parent(&clone_tree, self.options.durable).await?;
+        self.write_listing(&clone_tree, &metadata).await;
+
+        tracing::info!(
+            "Cloned {} overwritten files, recorded {} created files take {:?}",
+            metadata.overwritten.len(),
+            metadata.created.len(),
+            instant.elapsed()
+        );
+        Ok((clone_tree, metadata))
+    }
+
+    /// Each entry is deflated into its own single-entry archive on the blocking pool, `jobs` at
+    /// a time, and raw-copied in manifest order into the backup, which is written to its partial
+    /// file as it goes. Files over `chunk_size` go to a spool file on disk rather than memory.
+    /// Compression thus scales with the cores while the backup stays a single zip that is never
+    /// held in memory.
+    pub(crate) async fn backup_to_zip(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+        pack_version: &str,
+        timings: &mut Timings,
+    ) -> Result<(PathBuf, BackupMetadata)> {
+        let jobs = self.options.jobs;
+        let password = if self.options.encrypt {
+            Some(passphrase::backup_passphrase(true)?)
+        } else {
+            None
+        };
+        let instant = std::time::Instant::now();
+        let alien_isolation_dir = self.game_dir.as_path();
+        let backup_dir = self.backup_dir.as_path();
+        let backup_zip = backups::new_backup_path(backup_dir);
+        tracing::info!(
+            "Backing up [{}] to [{}] with {} workers",
+            alien_isolation_dir
+                .join(path_structure::data_dir())
+                .display(),
+            backup_zip.display(),
+            jobs,
+        );
+        let mut metadata = self.new_backup_metadata(password.is_some(), pack_version)?;
+        let archive = self.create_partial(&backup_zip)?;
+        let archive = self
+            .write_backup_entries(archive, filtered, password, &mut metadata, timings)
+            .await;
+        let phase = PhaseTimer::start();
+        let written = match archive {
+            Ok(archive) => self.commit_partial(archive, &metadata, &backup_zip).await?,
+            Err(error) => {
+                self.discard_partial(&backup_zip).await;
+                return Err(error);
+            }
+        };
+        timings.backup_write = Some(phase.finish(written));
+        self.write_listing(&backup_zip, &metadata).await;
+
+        tracing::info!(
+            "Backed up {} overwritten files, recorded {} created files take {:?}",
+            metadata.overwritten.len(),
+            metadata.created.len(),
+            instant.elapsed()
+        );
+        if !metadata.already_patched.is_empty() {
+            tracing::warn!(
+                "{} files already match the language pack and were not backed up, restoring will delete them",
+                metadata.already_patched.len()
+            );
+            for path in &metadata.already_patched {
+                tracing::warn!("Already patched [{}]", path.display());
+            }
+        }
+        Ok((backup_zip, metadata))
+    }
+
+    /// Backs up the game's copy of every file of `filtered` into `archive` in manifest order,
+    /// recording each in `metadata`
+    pub(crate) async fn write_backup_entries(
+        &self,
+        mut archive: BackupWriter,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+        password: Option<String>,
+        metadata: &mut BackupMetadata,
+        timings: &mut Timings,
+    ) -> Result<BackupWriter> {
+        let phase = PhaseTimer::start();
+        let mut read = 0;
+        let mut entries =
+            futures::stream::iter(filtered.iter().filter(|(item, _)| item.is_file).map(
+                |(item, striped)| {
+                    let missing = if item.optional {
+                        OnMissing::Skip
+                    } else {
+                        self.options.on_missing
+                    };
+                    let password = password.clone();
+                    async move {
+                        let entry = self
+                            .backup_entry(
+                                item,
+                                self.game_path(striped),
+                                striped,
+                                missing,
+                                password,
+                                None,
+                            )
+                            .await?;
+                        Ok::<_, color_eyre::Report>((striped, entry))
+                    }
+                },
+            ))
+            .buffered(self.options.jobs.max(1));
+        while let Some(entry) = entries.next().await {
+            let (striped, entry) = entry?;
+            let before = read;
+            match entry {
+                BackupEntry::Created => metadata.created.push(striped.to_path_buf()),
+                BackupEntry::AlreadyPatched => metadata.already_patched.push(striped.to_path_buf()),
+                BackupEntry::Cloned => metadata.overwritten.push(striped.to_path_buf()),
+                BackupEntry::Overwritten {
+                    compressed,
+                    modified,
+                } => {
+                    let mut single = ZipArchive::new(Cursor::new(compressed))?;
+                    let entry = single.by_index_raw(0)?;
+                    read += entry.size();
+                    archive.raw_copy_file(entry).with_context(|| {
+                        format!("Failed to write [{}] to archive", striped.display())
+                    })?;
+                    metadata.record_modified(striped, modified);
+                    metadata.overwritten.push(striped.to_path_buf());
+                }
+                BackupEntry::Spooled {
+                    spool,
+                    size,
+                    modified,
+                } => {
+                    read += size;
+                    archive = self.copy_spooled(archive, spool).await.with_context(|| {
+                        format!("Failed to write [{}] to archive", striped.display())
+                    })?;
+                    metadata.record_modified(striped, modified);
+                    metadata.overwritten.push(striped.to_path_buf());
+                }
+            }
+            self.reporter.on_file_done("backup", striped, read - before);
+        }
+        drop(entries);
+        timings.backup_compress = Some(phase.finish(read));
+        metadata.checksums = self.checksum_originals(&metadata.overwritten).await?;
+        Ok(archive)
+    }
+
+    /// Writes the listing next to the backup, a failure only costs the listing
+    pub(crate) async fn write_listing(&self, backup: &Path, metadata: &BackupMetadata) {
+        let mut sizes = BTreeMap::new();
+        for striped in &metadata.overwritten {
+            if let Ok(file) = tokio::fs::metadata(self.game_path(striped)).await {
+                sizes.insert(striped.clone(), file.len());
+            }
+        }
+        let listing_path = backups::listing_path(backup);
+        match tokio::fs::write(&listing_path, metadata.listing(&sizes)).await {
+            Ok(()) => tracing::info!("Wrote backup listing [{}]", listing_path.display()),
+            Err(error) => tracing::warn!(
+                "Failed to write backup listing [{}]: {}",
+                listing_path.display(),
+                error
+            ),
+        }
+    }
+
+    pub(crate) async fn checksum_originals(
+        &self,
+        overwritten: &[PathBuf],
+    ) -> Result<BTreeMap<PathBuf, String>> {
+        let files = overwritten
+            .iter()
+            .map(|striped| (striped.clone(), self.game_path(striped)))
+            .collect();
+        let hashes = hashing::hash_files(files, self.options.jobs).await?;
+        Ok(hashes
+            .into_iter()
+            .map(|(striped, hash)| (striped, hashing::to_hex(&hash)))
+            .collect())
+    }
+
+    /// Creates the partial file of `backup_zip`, entries are written to it as they come
+    pub(crate) fn create_partial(&self, backup_zip: &Path) -> Result<BackupWriter> {
+        let partial_zip = backups::partial_path(backup_zip);
+        let file = std::fs::File::create(&partial_zip)
+            .with_context(|| format!("Failed to create backup [{}]", partial_zip.display()))?;
+        let buffer_size = self.options.io_buffer_size.max(1);
+        Ok(ZipWriter::new(BufWriter::with_capacity(buffer_size, file)))
+    }
+
+    /// Adds `metadata` to a backup from [Installer::create_partial], syncs it and moves it into
+    /// place as `backup_zip`. Returns the size of the backup.
+    pub(crate) async fn commit_partial(
+        &self,
+        mut archive: BackupWriter,
+        metadata: &BackupMetadata,
+        backup_zip: &Path,
+    ) -> Result<u64> {
+        let partial_zip = backups::partial_path(backup_zip);
+        let metadata = metadata.to_bytes()?;
+        let cancel = self.cancel.clone();
+        let finished = tasks::blocking("finish the backup", move || {
+            if cancel.is_cancelled() {
+                return Err(AlienError::Cancelled {
+                    what: "writing the backup".to_string(),
+                }
+                .into());
+            }
+            archive
+                .start_file(metadata::METADATA_ENTRY, SimpleFileOptions::default())
+                .context("Failed to start metadata in archive")?;
+            archive
+                .write_all(&metadata)
+                .context("Failed to write metadata to archive")?;
+            let file = archive
+                .finish()?
+                .into_inner()
+                .map_err(|error| error.into_error())?;
+            file.sync_all()?;
+            Ok(file.metadata()?.len())
+        })
+        .await;
+        let written = match finished {
+            Ok(written) => written,
+            Err(error) => {
+                self.discard_partial(backup_zip).await;
+                return Err(error).with_context(|| {
+                    format!("Failed to write backup [{}]", partial_zip.display())
+                });
+            }
+        };
+        tokio::fs::rename(&partial_zip, backup_zip)
+            .await
+            .with_context(|| format!("Failed to move backup into [{}]", backup_zip.display()))?;
+        durability::sync_parent(backup_zip, self.options.durable)
+            .await
+            .with_context(|| {
+                format!("Failed to sync the directory of [{}]", backup_zip.display())
+            })?;
+        Ok(written)
+    }
+
+    /// Removes the partial file of `backup_zip` after a failed backup
+    pub(crate) async fn discard_partial(&self, backup_zip: &Path) {
+        let _ = tokio::fs::remove_file(backups::partial_path(backup_zip)).await;
+    }
+
+    pub(crate) async fn backup_entry(
+        &self,
+        item: &ManifestItem,
+        path: PathBuf,
+        striped: &Path,
+        missing: OnMissing,
+        password: Option<String>,
+        clone_tree: Option<&Path>,
+    ) -> Result<BackupEntry> {
+        let not_found = |error: &std::io::Error| error.kind() == std::io::ErrorKind::NotFound;
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(error) if not_found(&error) && missing == OnMissing::Skip => {
+                return Ok(BackupEntry::Created)
+            }
+            Err(error) if not_found(&error) && missing == OnMissing::Empty => {
+                tracing::warn!("Backing up missing [{}] as an empty file", path.display());
+                return self.placeholder_entry(striped, password, clone_tree).await;
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .filter(|_| self.options.preserve_timestamps);
+        if metadata.len() == item.size && item.check_file(&path).await? == FileState::Matches {
+            return Ok(BackupEntry::AlreadyPatched);
+        }
+        if let Some(clone_tree) = clone_tree {
+            let cloned = clone_tree.join(striped);
+            if let Some(parent) = cloned.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let permit = descriptors::acquire_many(2).await?;
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                let copied = reflink_copy::reflink_or_copy(&path, &cloned).with_context(|| {
+                    format!(
+                        "Failed to clone [{}] to [{}]",
+                        path.display(),
+                        cloned.display()
+                    )
+                })?;
+                if copied.is_some() {
+                    tracing::debug!(
+                        "Copied [{}], reflinks are unsupported there",
+                        path.display()
+                    );
+                }
+                if let Some(modified) = modified {
+                    let modified = filetime::FileTime::from_system_time(modified);
+                    filetime::set_file_mtime(&cloned, modified)?;
+                }
+                Ok::<_, color_eyre::Report>(())
+            })
+            .await??;
+            return Ok(BackupEntry::Cloned);
+        }
+        let name = striped.display().to_string();
+        let method = self.compression_method(striped);
+        let mode = permissions::mode(&metadata).filter(|_| self.options.preserve_permissions);
+        if metadata.len() > self.options.chunk_size as u64 {
+            let spool = Spool(backups::spool_path(&self.backup_dir));
+            let entry = StreamedEntry {
+                path,
+                name,
+                size: metadata.len(),
+                method,
+                modified,
+                mode,
+                password,
+            };
+            self.compress_streamed(&spool, entry).await?;
+            return Ok(BackupEntry::Spooled {
+                spool,
+                size: metadata.len(),
+                modified,
+            });
+        }
+        let permit = descriptors::acquire().await?;
+        let bytes = tokio::fs::read(&path).await?;
+        drop(permit);
+        let compressed = tokio::task::spawn_blocking(move || {
+            compress_entry(name, &bytes, method, modified, mode, password)
+        })
+        .await??;
+        Ok(BackupEntry::Overwritten {
+            compressed,
+            modified,
+        })
+    }
+
+    /// Compresses a large file into a single-entry archive at `spool`, a chunk at a time on the
+    /// blocking pool, stopping at the next chunk once cancelled
+    pub(crate) async fn compress_streamed(
+        &self,
+        spool: &Spool,
+        entry: StreamedEntry,
+    ) -> Result<()> {
+        let permit = descriptors::acquire_many(2).await?;
+        let chunk_size = self.options.chunk_size;
+        let buffer_size = self.options.io_buffer_size.max(1);
+        let cancel = self.cancel.clone();
+        let spool = spool.0.clone();
+        tasks::blocking("back up a large file", move || {
+            let _permit = permit;
+            let mut file = std::fs::File::open(&entry.path)
+                .with_context(|| format!("Failed to open [{}]", entry.path.display()))?;
+            let output = std::fs::File::create(&spool)
+                .with_context(|| format!("Failed to create [{}]", spool.display()))?;
+            let mut archive = ZipWriter::new(BufWriter::with_capacity(buffer_size, output));
+            let options = entry_options(
+                entry.method,
+                entry.modified,
+                entry.mode,
+                entry.password.as_deref(),
+            );
+            archive
+                .start_file(entry.name.as_str(), options)
+                .with_context(|| format!("Failed to start file [{}] in archive", entry.name))?;
+            chunked::copy(
+                &mut file,
+                &mut archive,
+                &entry.path,
+                entry.size,
+                chunk_size,
+                &cancel,
+            )
+            .with_context(|| format!("Failed to compress [{}]", entry.name))?;
+            archive.finish()?.flush()?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Raw-copies the entry of `spool` into `archive` on the blocking pool, removing the spool
+    pub(crate) async fn copy_spooled(
+        &self,
+        mut archive: BackupWriter,
+        spool: Spool,
+    ) -> Result<BackupWriter> {
+        let permit = descriptors::acquire().await?;
+        tasks::blocking("copy a large file into the backup", move || {
+            let _permit = permit;
+            let file = std::fs::File::open(&spool.0)
+                .with_context(|| format!("Failed to open [{}]", spool.0.display()))?;
+            let mut single = ZipArchive::new(BufReader::new(file))?;
+            archive.raw_copy_file(single.by_index_raw(0)?)?;
+            Ok(archive)
+        })
+        .await
+    }
+
+    pub(crate) async fn placeholder_entry(
+        &self,
+        striped: &Path,
+        password: Option<String>,
+        clone_tree: Option<&Path>,
+    ) -> Result<BackupEntry> {
+        if let Some(clone_tree) = clone_tree {
+            let cloned = clone_tree.join(striped);
+            if let Some(parent) = cloned.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&cloned, b"").await?;
+            return Ok(BackupEntry::Cloned);
+        }
+        let compressed = compress_entry(
+            striped.display().to_string(),
+            &[],
+            self.compression_method(striped),
+            None,
+            None,
+            password,
+        )?;
+        Ok(BackupEntry::Overwritten {
+            compressed,
+            modified: None,
+        })
+    }
+
+    pub(crate) fn compression_method(&self, path: &Path) -> CompressionMethod {
+        backups::compression_method(path, self.options.stored_extensions.as_deref())
+    }
+}
+
+/// A backup zip being written to its partial file
+pub(crate) type BackupWriter = ZipWriter<BufWriter<std::fs::File>>;
+
+enum BackupEntry {
+    Created,
+    AlreadyPatched,
+    Cloned,
+    Overwritten {
+        compressed: Vec<u8>,
+        modified: Option<SystemTime>,
+    },
+    /// Larger than a chunk, compressed into `spool` and copied into the backup when its turn
+    /// comes
+    Spooled {
+        spool: Spool,
+        size: u64,
+        modified: Option<SystemTime>,
+    },
+}
+
+/// Spool file of a large file's backup entry, removed once dropped
+pub(crate) struct Spool(PathBuf);
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// A file backed up a chunk at a time by [Installer::compress_streamed]
+pub(crate) struct StreamedEntry {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    method: CompressionMethod,
+    modified: Option<SystemTime>,
+    mode: Option<u32>,
+    password: Option<String>,
+}
+
+pub(crate) fn entry_options<'a>(
+    method: CompressionMethod,
+    modified: Option<SystemTime>,
+    mode: Option<u32>,
+    password: Option<&'a str>,
+) -> FileOptions<'a, ()> {
+    let mut options = SimpleFileOptions::default().compression_method(method);
+    if let Some(datetime) = modified.and_then(manifest::to_zip_datetime) {
+        options = options.last_modified_time(datetime);
+    }
+    if let Some(mode) = mode {
+        options = options.unix_permissions(mode);
+    }
+    match password {
+        Some(password) => options.with_aes_encryption(AesMode::Aes256, password),
+        None => options,
+    }
+}
+
+/// Single-entry archive of `bytes`, for raw-copying into a backup
+pub(crate) fn compress_entry(
+    name: String,
+    bytes: &[u8],
+    method: CompressionMethod,
+    modified: Option<SystemTime>,
+    mode: Option<u32>,
+    password: Option<String>,
+) -> Result<Vec<u8>> {
+    let mut buffer = Cursor::new(Vec::new());
+    let mut archive = ZipWriter::new(&mut buffer);
+    archive
+        .start_file(
+            name.as_str(),
+            entry_options(method, modified, mode, password.as_deref()),
+        )
+        .with_context(|| format!("Failed to start file [{}] in archive", name))?;
+    archive
+        .write_all(bytes)
+        .with_context(|| format!("Failed to compress [{}]", name))?;
+    archive.finish()?;
+    Ok(buffer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::installer::tests::{fixture_installer, original, original_name, stage_and_install};
+    use crate::installer::BackupMode;
+    use crate::manifest::Manifest;
+    use crate::metadata::BackupMetadata;
+    use crate::progress::ProgressReporter;
+    use crate::{backups, hashing};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn streams_files_larger_than_a_chunk_into_the_backup() {
+        let mut installer = fixture_installer("streamed-backup", 2, 64);
+        installer.options.chunk_size = 4096;
+        let large = (0..300_000).map(|i| (i % 241) as u8).collect::<Vec<_>>();
+        std::fs::write(installer.game_dir().join(original_name(0)), &large).unwrap();
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+
+        let (backup, _) = installer.index_backup(None).await.unwrap();
+        let backup = Manifest::read_from_backup_path(&backup).unwrap();
+        let entry = |name: &str| {
+            backup
+                .iter()
+                .find(|item| item.lowercase_name == Path::new(name))
+                .unwrap()
+        };
+        assert_eq!(entry(&original_name(0)).bytes, large);
+        assert_eq!(entry(&original_name(1)).bytes, original(1));
+        let spools = std::fs::read_dir(&installer.backup_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "partial")
+            })
+            .collect::<Vec<_>>();
+        assert!(spools.is_empty(), "{:?}", spools);
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_file_already_patched_is_recorded_rather_than_backed_up() {
+        let installer = fixture_installer("already-patched", 4, 64);
+        let patched = PathBuf::from(original_name(2));
+        let mut manifest = installer.load_pack().await.unwrap();
+        let translated = manifest
+            .filter_hans_dir(installer.prefix())
+            .into_iter()
+            .find(|(_, striped)| *striped == patched)
+            .map(|(item, _)| item.bytes.clone())
+            .unwrap();
+        std::fs::write(installer.game_dir().join(&patched), &translated).unwrap();
+
+        let summary = stage_and_install(&installer, &mut manifest).await.unwrap();
+        assert!(
+            summary.contains("WARNING: 1 files were already Chinese at backup time"),
+            "{}",
+            summary
+        );
+        let (backup, index) = installer.index_backup(None).await.unwrap();
+        assert!(!index.iter().any(|item| item.lowercase_name == patched));
+        let metadata = BackupMetadata::read_from_path(&backup).unwrap().unwrap();
+        assert_eq!(metadata.already_patched, [patched.clone()]);
+        assert!(!metadata.overwritten.contains(&patched));
+
+        let manifest = installer.load_pack().await.unwrap();
+        let report = installer.restore(manifest, None).await.unwrap();
+        assert_eq!(report.removed, [patched.clone()]);
+        assert!(!installer.game_dir().join(&patched).exists());
+        for index in [0, 1, 3] {
+            let restored = std::fs::read(installer.game_dir().join(original_name(index))).unwrap();
+            assert_eq!(restored, original(index));
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_clone_tree_backup_restores_the_originals() {
+        let mut installer = fixture_installer("clone-tree", 4, 64);
+        installer.options.backup_mode = BackupMode::Clonefile;
+        let scratch = installer.game_dir().parent().unwrap().to_path_buf();
+        // Reflinks where the scratch dir supports them, otherwise the plain copies they fall
+        // back to, the tree restores the same either way
+        std::fs::write(scratch.join("probe"), "probe").unwrap();
+        if reflink_copy::reflink(scratch.join("probe"), scratch.join("probe.clone")).is_err() {
+            eprintln!(
+                "[{}] has no reflinks, testing plain copies",
+                scratch.display()
+            );
+        }
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+
+        let (backup, _) = installer.index_backup(None).await.unwrap();
+        assert!(backups::is_clone_tree(&backup));
+        for index in 0..4 {
+            let cloned = std::fs::read(backup.join(original_name(index))).unwrap();
+            assert_eq!(cloned, original(index));
+        }
+        let manifest = installer.load_pack().await.unwrap();
+        installer.restore(manifest, None).await.unwrap();
+        for index in 0..4 {
+            let restored = std::fs::read(installer.game_dir().join(original_name(index))).unwrap();
+            assert_eq!(restored, original(index));
+        }
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    /// Moves the game dir away once `after` files are backed up, like a volume unplugged
+    /// mid-backup
+    struct Unplug {
+        game_dir: PathBuf,
+        after: usize,
+        done: AtomicUsize,
+    }
+
+    impl ProgressReporter for Unplug {
+        fn on_file_done(&self, phase: &'static str, _path: &Path, _bytes: u64) {
+            if phase == "backup" && self.done.fetch_add(1, Ordering::Relaxed) + 1 == self.after {
+                std::fs::rename(&self.game_dir, self.game_dir.with_extension("unplugged")).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn a_game_volume_gone_mid_backup_leaves_no_partial_backup() {
+        let mut installer = fixture_installer("volume-gone", 64, 64);
+        installer.options.jobs = 2;
+        let game_dir = installer.game_dir().to_path_buf();
+        let installer = installer.with_reporter(Arc::new(Unplug {
+            game_dir: game_dir.clone(),
+            after: 4,
+            done: AtomicUsize::new(0),
+        }));
+        let mut manifest = installer.load_pack().await.unwrap();
+
+        stage_and_install(&installer, &mut manifest)
+            .await
+            .unwrap_err();
+        assert!(!game_dir.exists());
+        let left = std::fs::read_dir(installer.backup_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "partial" || extension == "zip")
+            })
+            .collect::<Vec<_>>();
+        assert!(left.is_empty(), "{:?}", left);
+        let _ = std::fs::remove_dir_all(game_dir.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn the_backup_listing_names_every_entry() {
+        let mut installer = fixture_installer("backup-listing", 6, 64);
+        installer.options.on_missing = crate::installer::OnMissing::Skip;
+        std::fs::remove_file(installer.game_dir().join(original_name(5))).unwrap();
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+
+        let (backup, _) = installer.index_backup(None).await.unwrap();
+        let listing = std::fs::read_to_string(backups::listing_path(&backup)).unwrap();
+        assert!(listing.starts_with("# Backup "), "{}", listing);
+        for index in 0..5 {
+            let hash = hashing::to_hex(&hashing::hash_bytes(&original(index)));
+            let line = format!(
+                "[F] {}  {} bytes  sha256 {}",
+                original_name(index),
+                original(index).len(),
+                hash
+            );
+            assert!(listing.lines().any(|listed| listed == line), "{}", listing);
+        }
+        let created = format!("[C] {}  created by the pack", original_name(5));
+        assert!(
+            listing.lines().any(|listed| listed == created),
+            "{}",
+            listing
+        );
+        assert_eq!(listing.lines().count(), 7);
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+}
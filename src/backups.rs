@@ -0,0 +1,363 @@
+use crate::config::BackupConfig;
+use crate::manifest;
+use crate::metadata::{BackupMetadata, METADATA_ENTRY};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::{CompressionMethod, ZipArchive};
+
+pub const NO_BACKUP_EXIT_CODE: i32 = 3;
+
+pub static CLONE_EXTENSION: &str = "clone";
+
+/// Game assets that are compressed already, deflating them again costs time and saves nothing
+pub static STORED_EXTENSIONS: &[&str] = &["bik", "bnk", "pck", "wem", "ogg", "mp3", "png", "jpg"];
+
+/// Files larger than this are compressed into the backup a chunk at a time
+pub const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Where backups go unless --backup-dir says otherwise, under the platform's data dir
+pub fn default_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "alien").map(|dirs| dirs.data_dir().join("backups"))
+}
+
+/// Stored for the extensions in `stored`, or [STORED_EXTENSIONS] without one, else deflated
+pub fn compression_method(path: &Path, stored: Option<&[String]>) -> CompressionMethod {
+    let extension = match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension,
+        None => return CompressionMethod::Deflated,
+    };
+    let stored = match stored {
+        Some(extensions) => extensions
+            .iter()
+            .any(|stored| stored.eq_ignore_ascii_case(extension)),
+        None => STORED_EXTENSIONS
+            .iter()
+            .any(|stored| stored.eq_ignore_ascii_case(extension)),
+    };
+    if stored {
+        CompressionMethod::Stored
+    } else {
+        CompressionMethod::Deflated
+    }
+}
+
+#[derive(Debug)]
+pub struct NoBackupError {
+    pub location: PathBuf,
+}
+
+impl Display for NoBackupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No backup found at [{}] — nothing to restore. Did you patch on this machine? Run `alien en --from-steam` to have Steam re-download the originals or `alien en --delete-only` to remove the installed files instead",
+            self.location.display()
+        )
+    }
+}
+
+impl std::error::Error for NoBackupError {}
+
+#[derive(Debug, Serialize)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub file_name: String,
+    pub created_at: Option<u64>,
+    pub entries: usize,
+    pub archive_size: u64,
+    pub total_size: u64,
+    pub pack_version: Option<String>,
+    pub original: bool,
+    pub error: Option<String>,
+}
+
+impl BackupInfo {
+    pub fn read(path: &Path) -> Self {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let metadata = std::fs::metadata(path).ok();
+        let mut info = BackupInfo {
+            path: path.to_path_buf(),
+            file_name,
+            created_at: metadata
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(unix_secs),
+            entries: 0,
+            archive_size: metadata.map(|metadata| metadata.len()).unwrap_or(0),
+            total_size: 0,
+            pack_version: None,
+            original: false,
+            error: None,
+        };
+        if let Err(error) = info.inspect(path) {
+            info.error = Some(format!("{:#}", error));
+        }
+        info
+    }
+
+    fn inspect(&mut self, path: &Path) -> Result<()> {
+        if is_clone_tree(path) {
+            if let Some(metadata) = BackupMetadata::read_from_path(path)? {
+                self.created_at = metadata.taken_at.and_then(unix_secs).or(self.created_at);
+                self.pack_version = metadata.pack_version;
+                self.original = metadata.original;
+            }
+            for (_, size) in entry_sizes(path)? {
+                self.entries += 1;
+                self.total_size += size;
+            }
+            self.archive_size = self.total_size;
+            return Ok(());
+        }
+        let file = std::fs::File::open(path)?;
+        let mut archive = ZipArchive::new(std::io::BufReader::new(file))?;
+        if let Some(metadata) = BackupMetadata::read_from_archive(&mut archive)? {
+            self.created_at = metadata.taken_at.and_then(unix_secs).or(self.created_at);
+            self.pack_version = metadata.pack_version;
+            self.original = metadata.original;
+        }
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i)?;
+            if file.is_file() {
+                self.entries += 1;
+                self.total_size += file.size();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for BackupInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let created_at = self
+            .created_at
+            .and_then(|secs| time::OffsetDateTime::from_unix_timestamp(secs as i64).ok())
+            .map(|created_at| created_at.to_string())
+            .unwrap_or_else(|| "unknown date".to_string());
+        write!(f, "{}  {}", self.file_name, created_at)?;
+        if let Some(error) = &self.error {
+            return write!(f, "  CORRUPT: {}", error);
+        }
+        write!(
+            f,
+            "  {} entries  {} bytes  pack {}",
+            self.entries,
+            self.total_size,
+            self.pack_version.as_deref().unwrap_or("unknown")
+        )?;
+        if self.original {
+            write!(f, "  original")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "zip" || extension == CLONE_EXTENSION)
+    });
+    paths.sort();
+    Ok(paths.iter().map(|path| BackupInfo::read(path)).collect())
+}
+
+pub fn entry_sizes(path: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    if is_clone_tree(path) {
+        return clone_tree_files(path)?
+            .into_iter()
+            .map(|striped| {
+                let size = std::fs::metadata(path.join(&striped))?.len();
+                Ok((striped, size))
+            })
+            .collect();
+    }
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open backup [{}]", path.display()))?;
+    let mut archive = ZipArchive::new(std::io::BufReader::new(file))?;
+    let mut sizes = Vec::new();
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        if file.is_file() && file.name() != METADATA_ENTRY {
+            sizes.push((manifest::path_key(Path::new(file.name())), file.size()));
+        }
+    }
+    Ok(sizes)
+}
+
+pub fn new_backup_path(dir: &Path) -> PathBuf {
+    let secs = unix_secs(SystemTime::now()).unwrap_or_default();
+    dir.join(format!("language-{}.zip", secs))
+}
+
+pub fn new_clone_tree_path(dir: &Path) -> PathBuf {
+    new_backup_path(dir).with_extension(CLONE_EXTENSION)
+}
+
+pub fn is_clone_tree(path: &Path) -> bool {
+    path.is_dir()
+}
+
+/// Backed up files of a clone tree relative to its root, without the metadata entry
+pub fn clone_tree_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read [{}]", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if let Ok(striped) = path.strip_prefix(root) {
+                if striped != Path::new(METADATA_ENTRY) {
+                    files.push(striped.to_path_buf());
+                }
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn remove_backup(path: &Path) -> std::io::Result<()> {
+    if is_clone_tree(path) {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    match std::fs::remove_file(listing_path(path)) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Human readable listing written next to a backup, `<backup>.txt`
+pub fn listing_path(backup: &Path) -> PathBuf {
+    let mut file_name = backup.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".txt");
+    backup.with_file_name(file_name)
+}
+
+pub fn partial_path(backup: &Path) -> PathBuf {
+    let mut file_name = backup.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".partial");
+    backup.with_file_name(file_name)
+}
+
+/// Single-entry archive a large file is compressed into before it is copied into a backup,
+/// named as a partial so a crash leaves nothing [clean_partials] misses
+pub fn spool_path(dir: &Path) -> PathBuf {
+    static SPOOLS: AtomicUsize = AtomicUsize::new(0);
+    let spool = SPOOLS.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!("spool-{}-{}.partial", std::process::id(), spool))
+}
+
+pub fn clean_partials(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "partial")
+        {
+            tracing::warn!("Removing stale partial backup [{}]", path.display());
+            remove_backup(&path)
+                .with_context(|| format!("Failed to remove [{}]", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn default_backup(dir: &Path) -> Result<Option<PathBuf>> {
+    let mut backups = list_backups(dir)?;
+    backups.retain(|backup| backup.error.is_none());
+    backups.sort_by_key(|backup| (backup.original, backup.created_at));
+    Ok(backups.pop().map(|backup| backup.path))
+}
+
+pub fn apply_retention(dir: &Path, config: &BackupConfig) -> Result<()> {
+    let keep_last = match config.keep_last {
+        Some(keep_last) => keep_last,
+        None => return Ok(()),
+    };
+    let instant = std::time::Instant::now();
+    let mut candidates = list_backups(dir)?
+        .into_iter()
+        .filter(|backup| !(config.keep_original && backup.original))
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|backup| std::cmp::Reverse(backup.created_at));
+    let mut reclaimed = 0;
+    for backup in candidates.into_iter().skip(keep_last) {
+        remove_backup(&backup.path)
+            .with_context(|| format!("Failed to remove backup [{}]", backup.path.display()))?;
+        tracing::info!(
+            "Removed backup [{}] reclaiming {} bytes",
+            backup.path.display(),
+            backup.archive_size
+        );
+        reclaimed += backup.archive_size;
+    }
+    tracing::info!(
+        "Applied retention keep_last={} reclaimed {} bytes take {:?}",
+        keep_last,
+        reclaimed,
+        instant.elapsed()
+    );
+    Ok(())
+}
+
+fn unix_secs(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compression_method;
+    use std::path::Path;
+    use zip::CompressionMethod;
+
+    #[test]
+    fn compressed_assets_are_stored_and_the_rest_deflated() {
+        for (name, method) in [
+            ("data/sound/music.BNK", CompressionMethod::Stored),
+            ("data/movies/intro.bik", CompressionMethod::Stored),
+            ("data/ui/logo.png", CompressionMethod::Stored),
+            ("data/text/english.txt", CompressionMethod::Deflated),
+            ("data/ui/menu.xml", CompressionMethod::Deflated),
+            ("data/README", CompressionMethod::Deflated),
+        ] {
+            assert_eq!(
+                compression_method(Path::new(name), None),
+                method,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn configured_extensions_replace_the_defaults() {
+        let stored = ["TXT".to_string()];
+        let method = |name: &str| compression_method(Path::new(name), Some(&stored[..]));
+        assert_eq!(method("data/text/english.txt"), CompressionMethod::Stored);
+        assert_eq!(method("data/movies/intro.bik"), CompressionMethod::Deflated);
+    }
+}
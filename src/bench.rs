@@ -0,0 +1,111 @@
+use crate::archive::compress_entry;
+use crate::installer::{Installer, Options, PackSource};
+use crate::report::{PhaseTimer, Timings};
+use crate::{descriptors, tasks};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use futures::StreamExt;
+use std::io::{Cursor, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// Language zip of `files` files of `size` bytes below the language dir `prefix`. The bytes
+/// are pseudo-random lowercase letters, so deflate does about as well as on the pack's text.
+pub fn fixture(prefix: &Path, files: usize, size: usize) -> Result<Vec<u8>> {
+    let instant = std::time::Instant::now();
+    let mut buffer = Cursor::new(Vec::new());
+    let mut archive = ZipWriter::new(&mut buffer);
+    let mut state = 0x2545_f491_u32;
+    for index in 0..files {
+        let name = format!("{}/data/bench/{:06}.txt", prefix.display(), index);
+        let bytes = (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                b'a' + (state % 26) as u8
+            })
+            .collect::<Vec<_>>();
+        archive
+            .start_file(name.as_str(), SimpleFileOptions::default())
+            .with_context(|| format!("Failed to start file [{}] in fixture", name))?;
+        archive
+            .write_all(&bytes)
+            .with_context(|| format!("Failed to write [{}] to fixture", name))?;
+    }
+    archive.finish()?;
+    tracing::info!(
+        "Generated fixture of {} files of {} bytes take {:?}",
+        files,
+        size,
+        instant.elapsed()
+    );
+    Ok(buffer.into_inner())
+}
+
+/// Loads, filters, backs up and writes a generated pack of `files` files of `size` bytes,
+/// timing each phase. Only a scratch directory is written, never the game.
+pub async fn run(options: Options, files: usize, size: usize) -> Result<Timings> {
+    let fixture = fixture(&options.language_prefix, files, size)?;
+    let scratch = std::env::temp_dir().join("alien-bench");
+    let installer = Installer::new(
+        scratch.clone(),
+        scratch.join("backups"),
+        PackSource::Memory(fixture.into()),
+    )
+    .with_options(options);
+    let jobs = installer.options().jobs.max(1);
+    let mut timings = Timings::default();
+
+    let phase = PhaseTimer::start();
+    let mut manifest = installer.read_pack().await?;
+    timings.read_manifest = Some(phase.finish(manifest.bytes()));
+
+    let phase = PhaseTimer::start();
+    let mut filtered = manifest.filter_hans_dir(installer.prefix());
+    timings.filter = Some(phase.finish(0));
+
+    let phase = PhaseTimer::start();
+    let mut compressed =
+        futures::stream::iter(filtered.iter().filter(|(item, _)| item.is_file).map(
+            |(item, striped)| {
+                let name = striped.display().to_string();
+                let method = installer.compression_method(striped);
+                let installer = &installer;
+                async move {
+                    let bytes = installer.item_bytes(item).await?.into_owned();
+                    tasks::blocking("compress a bench entry", move || {
+                        compress_entry(name, &bytes, method, None, None, None)?;
+                        Ok(bytes.len() as u64)
+                    })
+                    .await
+                }
+            },
+        ))
+        .buffer_unordered(jobs);
+    let mut read = 0;
+    while let Some(entry) = compressed.next().await {
+        read += entry?;
+    }
+    drop(compressed);
+    timings.backup_compress = Some(phase.finish(read));
+
+    let phase = PhaseTimer::start();
+    let mut writes = futures::stream::iter(filtered.iter_mut().map(|(item, striped)| {
+        let path = scratch.join(striped);
+        let installer = &installer;
+        async move { installer.write_file(item, path).await }
+    }))
+    .buffer_unordered(jobs);
+    let mut written = Ok(());
+    while let Some(result) = writes.next().await {
+        written = written.and(result);
+    }
+    drop(writes);
+    timings.write = Some(phase.finish(read));
+    let _ = tokio::fs::remove_dir_all(&scratch).await;
+    written.with_context(|| format!("Failed to write the fixture to [{}]", scratch.display()))?;
+    timings.open_files_wait = Some(descriptors::waited());
+    Ok(timings)
+}
@@ -0,0 +1,87 @@
+use crate::manifest::{self, Manifest};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Maps lowercase paths relative to the game dir onto their casing on disk,
+/// so lookups work on case-sensitive filesystems where installs use `DATA/UI/...`.
+pub struct GameTree {
+    root: PathBuf,
+    actual: HashMap<PathBuf, PathBuf>,
+    preferred: HashMap<PathBuf, PathBuf>,
+}
+
+impl GameTree {
+    /// Scans the game dirs the files of the language dir `prefix` of `manifest` go into
+    pub fn scan(root: &Path, manifest: &Manifest, prefix: &Path) -> GameTree {
+        let instant = std::time::Instant::now();
+        let hans_depth = prefix.components().count();
+        let mut preferred = HashMap::new();
+        let mut wanted_dirs = HashSet::new();
+        for (index, striped) in manifest.hans_plan(prefix) {
+            let original = manifest[index]
+                .path
+                .components()
+                .skip(hans_depth)
+                .collect::<PathBuf>();
+            wanted_dirs.extend(striped.ancestors().skip(1).map(Path::to_path_buf));
+            preferred.insert(striped, original);
+        }
+
+        let mut actual = HashMap::new();
+        let mut pending = vec![PathBuf::new()];
+        while let Some(dir) = pending.pop() {
+            let entries = match std::fs::read_dir(root.join(&dir)) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let relative = dir.join(entry.file_name());
+                let key = lowercase(&relative);
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir())
+                    && wanted_dirs.contains(&key)
+                {
+                    pending.push(relative.clone());
+                }
+                actual.insert(key, relative);
+            }
+        }
+        tracing::info!(
+            "Scanned {} game paths take {:?}",
+            actual.len(),
+            instant.elapsed()
+        );
+        GameTree {
+            root: root.to_path_buf(),
+            actual,
+            preferred,
+        }
+    }
+
+    /// Resolves a lowercase relative path to the casing on disk, new paths take
+    /// the casing of the pack below their deepest existing ancestor.
+    pub fn resolve(&self, striped: &Path) -> PathBuf {
+        let key = lowercase(striped);
+        if let Some(actual) = self.actual.get(&key) {
+            return self.root.join(actual);
+        }
+        let preferred = self
+            .preferred
+            .get(&key)
+            .map(PathBuf::as_path)
+            .unwrap_or(striped);
+        let preferred = manifest::platform_form(preferred);
+        let components = preferred.components().collect::<Vec<_>>();
+        for depth in (1..components.len()).rev() {
+            let ancestor = components[..depth].iter().collect::<PathBuf>();
+            if let Some(actual) = self.actual.get(&lowercase(&ancestor)) {
+                let rest = components[depth..].iter().collect::<PathBuf>();
+                return self.root.join(actual).join(rest);
+            }
+        }
+        self.root.join(preferred)
+    }
+}
+
+fn lowercase(path: &Path) -> PathBuf {
+    manifest::path_key(path)
+}
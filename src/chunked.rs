@@ -0,0 +1,100 @@
+use crate::error::AlienError;
+use color_eyre::Result;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Bytes per write by default, small enough for network filesystems and SMR drives
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Stops every chunked write of an installer at its next chunk, clones share the flag
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// Stops every chunked write at its next chunk, returns whether it was stopped already
+    pub fn cancel(&self) -> bool {
+        self.0.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Writes `bytes` to the file at `path` `buffer_size` bytes at a time, logging the progress of
+/// writes spanning several buffers at debug level and checking `cancel` between them
+pub async fn write_all<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    bytes: &[u8],
+    path: &Path,
+    buffer_size: usize,
+    cancel: &Cancel,
+) -> Result<()> {
+    let buffer_size = buffer_size.max(1);
+    let mut written = 0;
+    for chunk in bytes.chunks(buffer_size) {
+        if cancel.is_cancelled() {
+            return Err(AlienError::Cancelled {
+                what: format!(
+                    "writing [{}] after {}/{} bytes",
+                    path.display(),
+                    written,
+                    bytes.len()
+                ),
+            }
+            .into());
+        }
+        writer.write_all(chunk).await?;
+        written += chunk.len();
+        if bytes.len() > buffer_size {
+            tracing::debug!(
+                "Wrote {}/{} bytes of [{}]",
+                written,
+                bytes.len(),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Copies `reader` of `size` bytes to `writer`, the file at `path`, `buffer_size` bytes at a
+/// time on a blocking thread, like [write_all]. Returns the bytes copied and their CRC32.
+pub fn copy(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    path: &Path,
+    size: u64,
+    buffer_size: usize,
+    cancel: &Cancel,
+) -> Result<(u64, u32)> {
+    let mut buffer = vec![0; buffer_size.max(1)];
+    let mut hasher = crc32fast::Hasher::new();
+    let mut copied = 0;
+    loop {
+        if cancel.is_cancelled() {
+            return Err(AlienError::Cancelled {
+                what: format!(
+                    "writing [{}] after {}/{} bytes",
+                    path.display(),
+                    copied,
+                    size
+                ),
+            }
+            .into());
+        }
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok((copied, hasher.finalize()));
+        }
+        writer.write_all(&buffer[..read])?;
+        hasher.update(&buffer[..read]);
+        copied += read as u64;
+        if size > buffer.len() as u64 {
+            tracing::debug!("Wrote {}/{} bytes of [{}]", copied, size, path.display());
+        }
+    }
+}
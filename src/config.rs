@@ -0,0 +1,55 @@
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use directories::ProjectDirs;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub game_dir: Option<PathBuf>,
+    pub backup_dir: Option<PathBuf>,
+    pub jobs: Option<usize>,
+    pub durable: bool,
+    /// Hex encoded ed25519 public keys trusted to sign external packs, besides the built-in ones
+    pub trusted_keys: Vec<String>,
+    pub backup: BackupConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    pub keep_last: Option<usize>,
+    pub keep_original: bool,
+    /// Extensions stored without compression, replacing the built-in list of compressed assets
+    pub stored_extensions: Option<Vec<String>>,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            keep_last: None,
+            keep_original: true,
+            stored_extensions: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "alien").map(|dirs| dirs.config_dir().join("config.toml"))
+    }
+
+    pub fn load() -> Result<Config> {
+        let path = match Config::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config [{}]", path.display()))?;
+        let config = toml::from_str(&text)
+            .with_context(|| format!("Failed to parse config [{}]", path.display()))?;
+        tracing::info!("Loaded config from [{}]", path.display());
+        Ok(config)
+    }
+}
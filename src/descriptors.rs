@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Descriptors left for stdio, the log file, the runtime and the archives being read
+const HEADROOM: usize = 16;
+
+/// Smallest budget, enough for both ends of a copy
+const MIN_BUDGET: usize = 2;
+
+/// Budget where the process limit cannot be queried
+const FALLBACK_LIMIT: usize = 256;
+
+static BUDGET: OnceLock<Semaphore> = OnceLock::new();
+
+static WAITED_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// Caps the game files open at once, the soft RLIMIT_NOFILE minus headroom by default
+pub fn set_max_open_files(max: Option<usize>) {
+    let max = max
+        .unwrap_or_else(default_budget)
+        .clamp(MIN_BUDGET, Semaphore::MAX_PERMITS);
+    tracing::debug!("Keeping at most {} files open", max);
+    let _ = BUDGET.set(Semaphore::new(max));
+}
+
+fn default_budget() -> usize {
+    process_limit()
+        .saturating_sub(HEADROOM)
+        .clamp(MIN_BUDGET, Semaphore::MAX_PERMITS)
+}
+
+#[cfg(unix)]
+fn process_limit() -> usize {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes the struct it is handed
+    match unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } {
+        0 => usize::try_from(limit.rlim_cur).unwrap_or(usize::MAX),
+        _ => FALLBACK_LIMIT,
+    }
+}
+
+#[cfg(not(unix))]
+fn process_limit() -> usize {
+    FALLBACK_LIMIT
+}
+
+/// Waits until a file may be opened, the file must be closed before the permit is dropped
+pub async fn acquire() -> std::io::Result<SemaphorePermit<'static>> {
+    acquire_many(1).await
+}
+
+/// Waits until `files` files may be opened together, e.g. both ends of a copy. Callers never
+/// hold two permits at once and ask for at most `MIN_BUDGET` files, so this cannot deadlock.
+pub async fn acquire_many(files: u32) -> std::io::Result<SemaphorePermit<'static>> {
+    let instant = Instant::now();
+    let budget = BUDGET.get_or_init(|| Semaphore::new(default_budget()));
+    let permit = budget
+        .acquire_many(files)
+        .await
+        .map_err(std::io::Error::other)?;
+    WAITED_NANOS.fetch_add(instant.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    Ok(permit)
+}
+
+/// Time all tasks together spent waiting for the budget
+pub fn waited() -> Duration {
+    Duration::from_nanos(WAITED_NANOS.load(Ordering::Relaxed))
+}
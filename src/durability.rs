@@ -0,0 +1,23 @@
+use std::path::Path;
+
+/// Flushes the directory entry of a rename into `path`, so the new name survives a power loss.
+/// Does nothing unless `durable`.
+pub async fn sync_parent(path: &Path, durable: bool) -> std::io::Result<()> {
+    if !durable {
+        return Ok(());
+    }
+    match path.parent() {
+        Some(parent) => sync_dir(parent).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
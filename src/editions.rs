@@ -0,0 +1,197 @@
+use crate::error::AlienError;
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use directories::BaseDirs;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Store the game was bought from, each installs it under its own roots and folder name
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edition {
+    Steam,
+    Gog,
+    Epic,
+}
+
+impl Display for Edition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Edition::Steam => "Steam",
+            Edition::Gog => "GOG",
+            Edition::Epic => "Epic",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Edition {
+    pub const ALL: [Edition; 3] = [Edition::Steam, Edition::Gog, Edition::Epic];
+
+    fn folder_names(self) -> &'static [&'static str] {
+        match self {
+            Edition::Steam => &["Alien Isolation"],
+            Edition::Gog => &["Alien_ Isolation", "Alien Isolation"],
+            Edition::Epic => &["AlienIsolation", "Alien Isolation"],
+        }
+    }
+
+    fn library_roots(self, home: &Path) -> Vec<PathBuf> {
+        match self {
+            Edition::Steam if cfg!(target_os = "macos") => {
+                vec![home.join("Library/Application Support/Steam/steamapps/common")]
+            }
+            Edition::Steam if cfg!(windows) => {
+                vec![PathBuf::from(
+                    r"C:\Program Files (x86)\Steam\steamapps\common",
+                )]
+            }
+            Edition::Steam => vec![
+                home.join(".steam/steam/steamapps/common"),
+                home.join(".local/share/Steam/steamapps/common"),
+            ],
+            Edition::Gog if cfg!(target_os = "macos") => vec![PathBuf::from("/Applications")],
+            Edition::Gog if cfg!(windows) => vec![
+                PathBuf::from(r"C:\GOG Games"),
+                PathBuf::from(r"C:\Program Files (x86)\GOG Galaxy\Games"),
+            ],
+            Edition::Gog => vec![home.join("GOG Games"), home.join("Games/Heroic")],
+            Edition::Epic if cfg!(target_os = "macos") => {
+                vec![PathBuf::from("/Users/Shared/Epic Games")]
+            }
+            Edition::Epic if cfg!(windows) => vec![PathBuf::from(r"C:\Program Files\Epic Games")],
+            Edition::Epic => vec![home.join("Games/Heroic")],
+        }
+    }
+
+    /// Game data dirs this edition would install to below `home`
+    pub fn candidates(self, home: &Path) -> Vec<PathBuf> {
+        self.library_roots(home)
+            .into_iter()
+            .flat_map(|root| {
+                self.folder_names()
+                    .iter()
+                    .map(move |folder| data_dir(&root.join(folder)))
+            })
+            .collect()
+    }
+}
+
+/// The data dir inside an install, the install itself except on macOS
+fn data_dir(install: &Path) -> PathBuf {
+    if cfg!(target_os = "macos") {
+        install.join("AlienIsolationData")
+    } else {
+        install.to_path_buf()
+    }
+}
+
+/// Every installed edition found in the default locations
+pub fn detect_all() -> Vec<(Edition, PathBuf)> {
+    match BaseDirs::new() {
+        Some(dirs) => detect_all_in(dirs.home_dir()),
+        None => Vec::new(),
+    }
+}
+
+fn detect_all_in(home: &Path) -> Vec<(Edition, PathBuf)> {
+    Edition::ALL
+        .into_iter()
+        .flat_map(|edition| {
+            edition
+                .candidates(home)
+                .into_iter()
+                .find(|dir| dir.is_dir())
+                .map(|dir| (edition, dir))
+        })
+        .collect()
+}
+
+/// Game data dir of `edition`, or of the only installed edition when none is given.
+/// Several installed editions without a choice are an error listing them.
+pub fn detect(edition: Option<Edition>) -> Result<Option<PathBuf>> {
+    choose(detect_all(), edition)
+}
+
+fn choose(found: Vec<(Edition, PathBuf)>, edition: Option<Edition>) -> Result<Option<PathBuf>> {
+    match edition {
+        Some(edition) => match found.into_iter().find(|(found, _)| *found == edition) {
+            Some((_, dir)) => Ok(Some(dir)),
+            None => Err(AlienError::GameDirNotFound {
+                edition: edition.to_string(),
+            }
+            .into()),
+        },
+        None if found.len() > 1 => {
+            let mut error = Err(eyre!(
+                "Found {} editions of Alien Isolation, choose one with --edition or --game-dir",
+                found.len()
+            ));
+            for (edition, dir) in &found {
+                error = error.with_context(|| format!("{}: [{}]", edition, dir.display()));
+            }
+            error
+        }
+        None => Ok(found.into_iter().next().map(|(_, dir)| dir)),
+    }
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::{choose, detect_all_in, Edition};
+    use crate::installer::tests::scratch_dir;
+    use std::path::PathBuf;
+
+    /// A home dir with the game installed in each of `installs`
+    fn home(name: &str, installs: &[&str]) -> PathBuf {
+        let home = scratch_dir(name);
+        for install in installs {
+            std::fs::create_dir_all(home.join(install)).unwrap();
+        }
+        home
+    }
+
+    #[test]
+    fn finds_each_edition_in_its_own_layout() {
+        for (name, edition, install) in [
+            (
+                "edition-steam",
+                Edition::Steam,
+                ".local/share/Steam/steamapps/common/Alien Isolation",
+            ),
+            ("edition-gog", Edition::Gog, "GOG Games/Alien_ Isolation"),
+            ("edition-epic", Edition::Epic, "Games/Heroic/AlienIsolation"),
+        ] {
+            let home = home(name, &[install]);
+            let found = detect_all_in(&home);
+            assert_eq!(found, [(edition, home.join(install))], "{}", edition);
+            assert_eq!(
+                choose(found.clone(), Some(edition)).unwrap(),
+                Some(home.join(install))
+            );
+            assert_eq!(choose(found, None).unwrap(), Some(home.join(install)));
+            let _ = std::fs::remove_dir_all(&home);
+        }
+    }
+
+    #[test]
+    fn several_editions_need_a_choice() {
+        let steam = ".steam/steam/steamapps/common/Alien Isolation";
+        let gog = "GOG Games/Alien_ Isolation";
+        let home = home("edition-several", &[steam, gog]);
+        let found = detect_all_in(&home);
+        assert_eq!(found.len(), 2);
+
+        let error = format!("{:#}", choose(found.clone(), None).unwrap_err());
+        assert!(error.contains("choose one with --edition"), "{}", error);
+        assert!(error.contains("GOG: ["), "{}", error);
+        assert_eq!(
+            choose(found.clone(), Some(Edition::Gog)).unwrap(),
+            Some(home.join(gog))
+        );
+        assert!(choose(found, Some(Edition::Epic)).is_err());
+        let _ = std::fs::remove_dir_all(&home);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::manifest::{self, Manifest};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+static UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+static UTF16_LE_BOM: &[u8] = &[0xFF, 0xFE];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum EncodingIssue {
+    Utf8Bom,
+    InvalidUtf8,
+    InvalidUtf16,
+    MixedLineEndings,
+}
+
+impl Display for EncodingIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncodingIssue::Utf8Bom => write!(f, "UTF-8 BOM"),
+            EncodingIssue::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            EncodingIssue::InvalidUtf16 => write!(f, "invalid UTF-16"),
+            EncodingIssue::MixedLineEndings => write!(f, "mixed CRLF and LF line endings"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TextEncoding {
+    Utf8,
+    Utf16Le,
+}
+
+/// Text entries of the pack, the game reads them as UTF-16LE with a BOM or as plain UTF-8
+pub fn is_text(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("txt") || extension.eq_ignore_ascii_case("xml")
+        })
+}
+
+pub fn check(bytes: &[u8]) -> Vec<EncodingIssue> {
+    let mut issues = Vec::new();
+    if bytes.starts_with(UTF8_BOM) {
+        issues.push(EncodingIssue::Utf8Bom);
+    }
+    match decode(bytes) {
+        Ok((_, text)) => {
+            if has_mixed_line_endings(&text) {
+                issues.push(EncodingIssue::MixedLineEndings);
+            }
+        }
+        Err(issue) => issues.push(issue),
+    }
+    issues
+}
+
+/// Strip a UTF-8 BOM and convert line endings to the dominant style, keeping the encoding
+pub fn normalize(bytes: &[u8]) -> Option<Vec<u8>> {
+    let (encoding, text) = decode(bytes).ok()?;
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    let unified = text.replace("\r\n", "\n");
+    let text = if crlf >= lf {
+        unified.replace('\n', "\r\n")
+    } else {
+        unified
+    };
+    let normalized = match encoding {
+        TextEncoding::Utf8 => text.into_bytes(),
+        TextEncoding::Utf16Le => UTF16_LE_BOM
+            .iter()
+            .copied()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect(),
+    };
+    if normalized == bytes {
+        None
+    } else {
+        Some(normalized)
+    }
+}
+
+/// Number of text files in `pack` and the issues of each that has any
+pub fn check_pack(pack: &Manifest) -> (usize, Vec<(PathBuf, Vec<EncodingIssue>)>) {
+    let texts = pack
+        .iter()
+        .filter(|item| item.is_file && is_text(&item.path))
+        .collect::<Vec<_>>();
+    let issues = texts
+        .iter()
+        .map(|item| (item.path.clone(), check(&item.bytes)))
+        .filter(|(_, issues)| !issues.is_empty())
+        .collect();
+    (texts.len(), issues)
+}
+
+/// Writes a copy of `pack` to the zip `path` with fixable issues normalized, returning how many
+/// files were normalized
+pub fn write_normalized(pack: &Manifest, path: &Path) -> Result<usize> {
+    let instant = std::time::Instant::now();
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create [{}]", path.display()))?;
+    let mut archive = ZipWriter::new(std::io::BufWriter::new(file));
+    let mut fixed = 0;
+    for item in pack.iter() {
+        let name = item.path.display().to_string();
+        let mut options = SimpleFileOptions::default();
+        if let Some(datetime) = item.modified.and_then(manifest::to_zip_datetime) {
+            options = options.last_modified_time(datetime);
+        }
+        if item.is_dir {
+            archive.add_directory(name.as_str(), options)?;
+            continue;
+        }
+        let normalized = is_text(&item.path)
+            .then(|| normalize(&item.bytes))
+            .flatten();
+        if normalized.is_some() {
+            fixed += 1;
+        }
+        archive
+            .start_file(name.as_str(), options)
+            .with_context(|| format!("Failed to start file [{}] in archive", name))?;
+        archive.write_all(normalized.as_deref().unwrap_or(&item.bytes))?;
+    }
+    archive.finish()?;
+    tracing::info!(
+        "Wrote [{}] with {} normalized files take {:?}",
+        path.display(),
+        fixed,
+        instant.elapsed()
+    );
+    Ok(fixed)
+}
+
+fn decode(bytes: &[u8]) -> Result<(TextEncoding, String), EncodingIssue> {
+    if let Some(bytes) = bytes.strip_prefix(UTF16_LE_BOM) {
+        if bytes.len() % 2 != 0 {
+            return Err(EncodingIssue::InvalidUtf16);
+        }
+        let units = bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+        let text = char::decode_utf16(units)
+            .collect::<Result<String, _>>()
+            .map_err(|_| EncodingIssue::InvalidUtf16)?;
+        return Ok((TextEncoding::Utf16Le, text));
+    }
+    let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+    let text = std::str::from_utf8(bytes).map_err(|_| EncodingIssue::InvalidUtf8)?;
+    Ok((TextEncoding::Utf8, text.to_string()))
+}
+
+fn has_mixed_line_endings(text: &str) -> bool {
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    crlf > 0 && lf > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_pack, write_normalized, EncodingIssue};
+    use crate::installer::tests::scratch_dir;
+    use crate::languages;
+    use crate::manifest::Manifest;
+    use std::io::{Cursor, Write};
+    use std::path::{Path, PathBuf};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn prefix() -> &'static Path {
+        languages::LANGUAGES[0].prefix()
+    }
+
+    fn pack(files: &[(&str, &[u8])]) -> Manifest {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut archive = ZipWriter::new(&mut buffer);
+        for (name, bytes) in files {
+            let name = prefix().join(name).display().to_string();
+            archive
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            archive.write_all(bytes).unwrap();
+        }
+        archive.finish().unwrap();
+        Manifest::read_from_bytes(&buffer.into_inner(), prefix(), u64::MAX).unwrap()
+    }
+
+    fn fixtures() -> Manifest {
+        pack(&[
+            ("data/text/bom.txt", b"\xEF\xBB\xBFline\r\nline\r\n"),
+            ("data/text/mixed.txt", b"line\r\nline\nline\r\n"),
+            ("data/text/clean.txt", b"line\r\nline\r\n"),
+            ("data/text/invalid.txt", b"line\xFF\n"),
+            ("data/ui/font.bin", b"\xEF\xBB\xBFbinary\r\n\n"),
+        ])
+    }
+
+    #[test]
+    fn reports_a_bom_and_mixed_line_endings() {
+        let (texts, issues) = check_pack(&fixtures());
+        assert_eq!(texts, 4);
+        let issues = issues
+            .into_iter()
+            .map(|(path, issues)| (path.strip_prefix(prefix()).unwrap().to_path_buf(), issues))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            issues,
+            [
+                (
+                    PathBuf::from("data/text/bom.txt"),
+                    vec![EncodingIssue::Utf8Bom]
+                ),
+                (
+                    PathBuf::from("data/text/mixed.txt"),
+                    vec![EncodingIssue::MixedLineEndings]
+                ),
+                (
+                    PathBuf::from("data/text/invalid.txt"),
+                    vec![EncodingIssue::InvalidUtf8]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn fix_strips_the_bom_and_unifies_line_endings() {
+        let scratch = scratch_dir("encoding-fix");
+        let output = scratch.join("fixed.zip");
+        assert_eq!(write_normalized(&fixtures(), &output).unwrap(), 2);
+
+        let fixed = Manifest::read_from_bytes(&std::fs::read(&output).unwrap(), prefix(), u64::MAX)
+            .unwrap();
+        let bytes = |name: &str| {
+            let path = prefix().join(name);
+            fixed
+                .iter()
+                .find(|item| item.path == path)
+                .unwrap()
+                .bytes
+                .clone()
+        };
+        assert_eq!(bytes("data/text/bom.txt"), b"line\r\nline\r\n");
+        assert_eq!(bytes("data/text/mixed.txt"), b"line\r\nline\r\nline\r\n");
+        assert_eq!(bytes("data/text/clean.txt"), b"line\r\nline\r\n");
+        assert_eq!(bytes("data/text/invalid.txt"), b"line\xFF\n");
+        assert_eq!(bytes("data/ui/font.bin"), b"\xEF\xBB\xBFbinary\r\n\n");
+        assert!(check_pack(&fixed)
+            .1
+            .iter()
+            .all(|(path, _)| path.ends_with("invalid.txt")));
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::backups::{NoBackupError, NO_BACKUP_EXIT_CODE};
+use crate::report;
+use std::path::PathBuf;
+
+/// Exit code after Ctrl-C, as shells report a process killed by SIGINT
+pub const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// Failures a caller may want to tell apart. The [Installer](crate::installer::Installer)
+/// returns them directly, inside an eyre report find one with [`AlienError::find`], it may be
+/// wrapped in any number of contexts.
+#[derive(Debug, thiserror::Error)]
+pub enum AlienError {
+    #[error("No {edition} install of Alien Isolation found, pass --game-dir")]
+    GameDirNotFound { edition: String },
+    #[error(
+        "[{}] does not look like the AlienIsolationData directory, it lacks DATA/UI and DATA/ENV. Check --game-dir or pass --no-safe-mode",
+        .dir.display()
+    )]
+    NotAGameDir { dir: PathBuf },
+    #[error("[{}] is not a directory", .path.display())]
+    NotADirectory { path: PathBuf },
+    #[error("{}", report::describe_missing(.0))]
+    MissingFiles(Vec<PathBuf>),
+    #[error(
+        "No translatable files found in language pack [{}], the pack may be empty or use an unexpected layout",
+        .prefix.display()
+    )]
+    EmptyPack { prefix: PathBuf },
+    #[error(
+        "Embedded language pack has SHA-256 {sha256} instead of {expected}, binary corrupted, please re-download"
+    )]
+    EmbeddedPackCorrupt {
+        sha256: String,
+        expected: &'static str,
+    },
+    #[error("Entry [{}] of the language pack is corrupt", .entry.display())]
+    PackCorrupt {
+        entry: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Paths in the file list are not in the language pack, pass --ignore-missing to skip them:{}", list(.0))]
+    NotInPack(Vec<PathBuf>),
+    #[error(transparent)]
+    BackupMissing(#[from] NoBackupError),
+    #[error("[{}] does not look like an alien backup", .path.display())]
+    NotABackup { path: PathBuf },
+    #[error("Backup entry [{}] is corrupt", .entry.display())]
+    BackupCorrupt {
+        entry: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{reason}:{}", list(.paths))]
+    BackupIncomplete {
+        reason: &'static str,
+        paths: Vec<PathBuf>,
+    },
+    #[error(
+        "{} files drifted from backup [{}], rerun without --strict to patch anyway",
+        .drifted,
+        .backup.display()
+    )]
+    BackupDrift { drifted: usize, backup: PathBuf },
+    #[error("Wrong backup passphrase")]
+    WrongPassphrase,
+    #[error("Files were changed by another mod, pass --overwrite-foreign to patch over them:{}", list(.0))]
+    ForeignMods(Vec<PathBuf>),
+    #[error(
+        "Paths exceed the Windows MAX_PATH limit of {max} characters, enable long path support (LongPathsEnabled) or move the game to a shorter directory:{}",
+        list(.paths)
+    )]
+    PathsTooLong { max: usize, paths: Vec<PathBuf> },
+    #[error("Nothing is staged in [{}], run `alien zh --stage` first", .dir.display())]
+    NothingStaged { dir: PathBuf },
+    #[error("[{}] was staged from another pack, run `alien zh --stage` again", .dir.display())]
+    StagedFromOtherPack { dir: PathBuf },
+    #[error("Staged files do not match the language pack, the game directory was not touched:{}", list(.0))]
+    StageMismatch(Vec<PathBuf>),
+    #[error("[{}] is inside the game directory, pick an output elsewhere", .output.display())]
+    OutputInsideGame { output: PathBuf },
+    #[error("[{}] has no parent directory", .path.display())]
+    NoParent { path: PathBuf },
+    #[error("Failed to write [{}]", .path.display())]
+    WriteFailed {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(
+        "[{}] reads back as {read} bytes with CRC32 {read_crc32:08x}, {written} bytes with CRC32 {written_crc32:08x} were written",
+        .path.display()
+    )]
+    ReadBackMismatch {
+        path: PathBuf,
+        read: u64,
+        read_crc32: u32,
+        written: u64,
+        written_crc32: u32,
+    },
+    #[error("Restore stopped before the backup was read")]
+    RestoreStopped,
+    #[error("Cancelled {what}")]
+    Cancelled { what: String },
+    #[error("{question} Pass --yes to confirm non-interactively")]
+    ConfirmationRequired { question: String },
+    #[error("Aborted")]
+    Aborted,
+    #[error("Unable to resolve the {what} directory")]
+    NoDefaultDir { what: &'static str },
+    /// Any other failure, with the context chain that led to it
+    #[error(transparent)]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl AlienError {
+    pub fn find(report: &color_eyre::Report) -> Option<&AlienError> {
+        report.chain().find_map(|cause| cause.downcast_ref())
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AlienError::BackupMissing(_) => NO_BACKUP_EXIT_CODE,
+            AlienError::Cancelled { .. } => CANCELLED_EXIT_CODE,
+            _ => 1,
+        }
+    }
+}
+
+impl From<color_eyre::Report> for AlienError {
+    /// Keeps the typed error the report carries, anything else becomes [AlienError::Other]
+    fn from(report: color_eyre::Report) -> Self {
+        match report.downcast::<AlienError>() {
+            Ok(error) => error,
+            Err(report) => AlienError::Other(report.into()),
+        }
+    }
+}
+
+/// `paths` one per line below the message
+fn list(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("\n  {}", path.display()))
+        .collect()
+}
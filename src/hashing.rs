@@ -0,0 +1,162 @@
+use crate::manifest;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+static POOL: OnceLock<Option<rayon::ThreadPool>> = OnceLock::new();
+
+pub fn hash_bytes(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytes).into()
+}
+
+/// SHA-256 of everything `reader` yields, read in chunks
+pub fn hash_reader(mut reader: impl Read) -> std::io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(hasher.finalize().into());
+        }
+        hasher.update(&chunk[..read]);
+    }
+}
+
+pub fn to_hex(hash: &[u8; 32]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reads expected hashes in `sha256sum` format, `<hex>  <path>` per line, keyed by the
+/// lowercase path so they line up with manifest entries
+pub fn read_hash_list(path: &Path) -> Result<HashMap<PathBuf, String>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read hash list [{}]", path.display()))?;
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (hash, file) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| eyre!("Malformed line in [{}]: {}", path.display(), line))?;
+            let file = file.trim_start().trim_start_matches('*').replace('\\', "/");
+            Ok((manifest::path_key(Path::new(&file)), hash.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Hashes the `(key, file)` pairs on a rayon pool of `threads` workers, off the async runtime,
+/// logging progress every tenth of the files. Files that do not exist are left out.
+pub async fn hash_files(
+    files: Vec<(PathBuf, PathBuf)>,
+    threads: usize,
+) -> Result<HashMap<PathBuf, [u8; 32]>> {
+    let instant = std::time::Instant::now();
+    let count = files.len();
+    let step = (count / 10).max(1);
+    let done = AtomicUsize::new(0);
+    let hashes = tokio::task::spawn_blocking(move || {
+        let hash = || {
+            files
+                .into_par_iter()
+                .filter_map(|(path, file)| {
+                    let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    if done % step == 0 {
+                        tracing::info!("Hashing {}/{} files", done, count);
+                    }
+                    match std::fs::File::open(&file).and_then(hash_reader) {
+                        Ok(hash) => Some(Ok((path, hash))),
+                        Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+                        Err(error) => Some(
+                            Err(error)
+                                .with_context(|| format!("Failed to hash [{}]", file.display())),
+                        ),
+                    }
+                })
+                .collect::<Result<HashMap<_, _>>>()
+        };
+        match pool(threads) {
+            Some(pool) => pool.install(hash),
+            None => hash(),
+        }
+    })
+    .await??;
+    tracing::info!("Hashed {} files take {:?}", count, instant.elapsed());
+    Ok(hashes)
+}
+
+fn pool(threads: usize) -> Option<&'static rayon::ThreadPool> {
+    POOL.get_or_init(|| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .thread_name(|index| format!("alien-hash-{}", index))
+            .build();
+        if let Err(error) = &pool {
+            tracing::warn!("Falling back to the global rayon pool: {}", error);
+        }
+        pool.ok()
+    })
+    .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::installer::tests::scratch_dir;
+
+    #[test]
+    fn matches_the_known_sha256_vectors() {
+        assert_eq!(
+            to_hex(&hash_bytes(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            to_hex(&hash_bytes(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn streaming_hashes_agree_with_the_in_memory_ones() {
+        // Spans several read chunks with a partial one at the end
+        let bytes = (0..200 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        assert_eq!(hash_reader(&bytes[..]).unwrap(), hash_bytes(&bytes));
+        assert_eq!(hash_reader(&b""[..]).unwrap(), hash_bytes(b""));
+    }
+
+    #[tokio::test]
+    async fn hashes_files_and_leaves_out_missing_ones() {
+        let scratch = scratch_dir("hash-files");
+        std::fs::write(scratch.join("abc"), b"abc").unwrap();
+        let files = vec![
+            (PathBuf::from("data/abc"), scratch.join("abc")),
+            (PathBuf::from("data/missing"), scratch.join("missing")),
+        ];
+
+        let hashes = hash_files(files, 2).await.unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes[Path::new("data/abc")], hash_bytes(b"abc"));
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[test]
+    fn reads_sha256sum_lines_keyed_by_lowercase_path() {
+        let scratch = scratch_dir("hash-list");
+        let list = scratch.join("SHA256SUMS");
+        std::fs::write(
+            &list,
+            "# pack\nABCDEF  DATA\\UI\\Font.ttf\n012345 *data/a.txt\n",
+        )
+        .unwrap();
+
+        let hashes = read_hash_list(&list).unwrap();
+        assert_eq!(hashes[Path::new("data/ui/font.ttf")], "abcdef");
+        assert_eq!(hashes[Path::new("data/a.txt")], "012345");
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+}
@@ -0,0 +1,526 @@
+use crate::error::AlienError;
+use crate::installer::{Installer, PackSource};
+use crate::manifest::{self, FileState, Manifest, ManifestItem};
+use crate::metadata::BackupMetadata;
+use crate::report::{
+    BackupVerifyReport, DiffEntry, InstallClassification, PhaseTimer, StatusReport, Timings,
+    TreeDiff, ValidationReport, WhichReport,
+};
+use crate::state::StateFile;
+use crate::{backups, hashing, tasks};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+impl Installer {
+    /// Unpacks the pack, or the zip `language_zip`, below `dest`, only the subtree `only` if
+    /// given. Returns the number of files written.
+    pub async fn extract(
+        &self,
+        dest: &Path,
+        language_zip: Option<&Path>,
+        only: Option<&Path>,
+    ) -> Result<usize, AlienError> {
+        let pack = match language_zip {
+            Some(language_zip) => PackSource::Zip(language_zip.to_path_buf()),
+            None => self.pack.clone(),
+        };
+        let manifest = self
+            .read_pack_from(&pack, self.options.stream_threshold)
+            .await?;
+        let only = only.map(manifest::path_key);
+        tracing::info!("Extracting to [{}]", dest.display());
+        let pack = &pack;
+        let mut writes = futures::stream::iter(
+            manifest
+                .iter()
+                .filter(|item| match &only {
+                    Some(only) => item.lowercase_name.starts_with(only),
+                    None => true,
+                })
+                .map(|item| async move {
+                    let path = dest.join(&item.path);
+                    self.write_from(pack, item, path, &self.cancel).await?;
+                    Ok::<_, color_eyre::Report>(item.is_file)
+                }),
+        )
+        .buffer_unordered(self.options.jobs.max(1));
+        let mut extracted = 0;
+        while let Some(is_file) = writes.next().await {
+            if is_file? {
+                extracted += 1;
+            }
+        }
+        Ok(extracted)
+    }
+
+    pub async fn status(&self) -> Result<StatusReport, AlienError> {
+        let state = StateFile::load(&self.state_dir)?
+            .get(&self.game_dir)
+            .cloned();
+        let manifest = self.load_pack().await?;
+        let files = match &state {
+            Some(_) => Some(self.validate_cached(&manifest).await?),
+            None => None,
+        };
+        let backup = match backups::default_backup(self.backup_dir.as_path())? {
+            Some(backup_zip) => match self.read_backup(Some(backup_zip.as_path())).await {
+                Ok(backup) => Some(backup),
+                Err(error) => {
+                    tracing::warn!("Classifying without the backup: {:#}", error);
+                    None
+                }
+            },
+            None => None,
+        };
+        let classification = self.classify_install(&manifest, backup.as_ref()).await?;
+        Ok(StatusReport {
+            game_dir: self.game_dir.clone(),
+            state,
+            files,
+            classification,
+        })
+    }
+
+    /// Sorts every pack file on disk into matching the pack, matching the backup, or neither.
+    /// Without a backup only files the pack would create can be recognized as English.
+    pub(crate) async fn classify_install(
+        &self,
+        pack: &Manifest,
+        backup: Option<&Manifest>,
+    ) -> Result<InstallClassification> {
+        let instant = std::time::Instant::now();
+        let originals = backup
+            .map(|backup| {
+                backup
+                    .iter()
+                    .filter(|item| item.is_file)
+                    .map(|item| (item.lowercase_name.clone(), item))
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+        let mut classification = InstallClassification::default();
+        for (index, striped) in pack.hans_plan(self.prefix()) {
+            let item = &pack[index];
+            if !item.is_file {
+                continue;
+            }
+            let path = self.game_path(&striped);
+            let state = item
+                .check_file(&path)
+                .await
+                .with_context(|| format!("Failed to read [{}]", path.display()))?;
+            let original = originals.get(&striped);
+            let english = match (state, original) {
+                (FileState::Matches, _) => {
+                    classification.chinese.push(striped);
+                    continue;
+                }
+                (FileState::Missing, None) => true,
+                (FileState::Missing, Some(_)) => false,
+                (FileState::Differs, None) => false,
+                (FileState::Differs, Some(original)) => {
+                    original.check_file(&path).await? == FileState::Matches
+                }
+            };
+            if english {
+                classification.english.push(striped);
+            } else {
+                classification.neither.push(striped);
+            }
+        }
+        tracing::info!("Classified the install take {:?}", instant.elapsed());
+        Ok(classification)
+    }
+
+    /// Compares the installed files against the pack
+    pub async fn verify(&self, timings: &mut Timings) -> Result<ValidationReport, AlienError> {
+        let manifest = self.load_pack().await?;
+        let phase = PhaseTimer::start();
+        let report = self.validate_cached(&manifest).await?;
+        timings.validate = Some(phase.finish(manifest.bytes()));
+        Ok(report)
+    }
+
+    /// Hashes the game files and compares them with `hashes` in `sha256sum` format, or the pack
+    pub async fn verify_install(
+        &self,
+        hashes: Option<&Path>,
+    ) -> Result<ValidationReport, AlienError> {
+        let instant = std::time::Instant::now();
+        let manifest = self.load_pack().await?;
+        let expected = match hashes {
+            Some(hashes) => hashing::read_hash_list(hashes)?,
+            None => {
+                let mut expected = HashMap::new();
+                for (index, striped) in manifest.hans_plan(self.prefix()) {
+                    if manifest[index].is_file {
+                        let hash = self.item_hash(&manifest[index]).await?;
+                        expected.insert(striped, hashing::to_hex(&hash));
+                    }
+                }
+                expected
+            }
+        };
+        let targets = expected
+            .keys()
+            .map(|striped| (striped.clone(), self.game_path(striped)))
+            .collect();
+        let current = hashing::hash_files(targets, self.options.jobs).await?;
+        let mut report = ValidationReport::default();
+        for (striped, hash) in &expected {
+            match current.get(striped) {
+                Some(current) if hashing::to_hex(current) == *hash => report.matched += 1,
+                Some(_) => report.mismatched.push(striped.clone()),
+                None => report.missing.push(striped.clone()),
+            }
+        }
+        report.mismatched.sort();
+        report.missing.sort();
+        tracing::info!("Verified the install take {:?}", instant.elapsed());
+        Ok(report)
+    }
+
+    /// Compares the pack's files with the game, or with the tree `compare_with`
+    pub async fn diff(&self, compare_with: Option<&Path>) -> Result<TreeDiff, AlienError> {
+        let instant = std::time::Instant::now();
+        let manifest = self.load_pack().await?;
+        let mut reference_files = match compare_with {
+            Some(dir) if !dir.is_dir() => {
+                return Err(AlienError::NotADirectory {
+                    path: dir.to_path_buf(),
+                })
+            }
+            Some(dir) => Some(
+                backups::clone_tree_files(dir)?
+                    .into_iter()
+                    .map(|striped| (manifest::path_key(&striped), striped))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+            None => None,
+        };
+        let mut tree_diff = TreeDiff::default();
+        for (index, striped) in manifest.hans_plan(self.prefix()) {
+            let item = &manifest[index];
+            if !item.is_file {
+                continue;
+            }
+            let size = Some(item.size);
+            let path = match (&mut reference_files, compare_with) {
+                (Some(files), Some(dir)) => files.remove(&striped).map(|found| dir.join(found)),
+                _ => Some(self.game_path(&striped)),
+            };
+            let state = match &path {
+                Some(path) => item
+                    .check_file(path)
+                    .await
+                    .with_context(|| format!("Failed to read [{}]", path.display()))?,
+                None => FileState::Missing,
+            };
+            match (state, path) {
+                (FileState::Matches, _) => tree_diff.identical += 1,
+                (FileState::Differs, Some(path)) => tree_diff.changed.push(DiffEntry {
+                    path: striped,
+                    size,
+                    reference_size: std::fs::metadata(path).ok().map(|metadata| metadata.len()),
+                }),
+                (FileState::Missing, _) | (FileState::Differs, None) => {
+                    tree_diff.added.push(DiffEntry {
+                        path: striped,
+                        size,
+                        reference_size: None,
+                    })
+                }
+            }
+        }
+        if let (Some(files), Some(dir)) = (reference_files, compare_with) {
+            for striped in files.into_values() {
+                let reference_size = std::fs::metadata(dir.join(&striped))
+                    .ok()
+                    .map(|metadata| metadata.len());
+                tree_diff.removed.push(DiffEntry {
+                    path: striped,
+                    size: None,
+                    reference_size,
+                });
+            }
+        }
+        tracing::info!("Diffed the pack take {:?}", instant.elapsed());
+        Ok(tree_diff)
+    }
+
+    /// Lowercase game-relative form of a user supplied path, whichever separators it uses
+    fn normalize_game_path(&self, path: &Path) -> PathBuf {
+        let path = path.strip_prefix(self.game_dir.as_path()).unwrap_or(path);
+        let text = path.display().to_string().replace('\\', "/");
+        let striped = text
+            .split('/')
+            .filter(|part| !part.is_empty() && *part != ".")
+            .collect::<PathBuf>();
+        manifest::path_key(&striped)
+    }
+
+    /// Whether the pack covers the game file `path` and whether it is patched
+    pub async fn which(&self, path: &Path) -> Result<WhichReport, AlienError> {
+        let manifest = self.load_pack().await?;
+        let striped = self.normalize_game_path(path);
+        let item = manifest
+            .hans_plan(self.prefix())
+            .into_iter()
+            .find(|(index, candidate)| manifest[*index].is_file && *candidate == striped)
+            .map(|(index, _)| &manifest[index]);
+        let game_path = self.game_path(&striped);
+        let on_disk = game_path.is_file();
+        let patched = match item {
+            Some(item) if on_disk => Some(
+                item.check_file(&game_path)
+                    .await
+                    .with_context(|| format!("Failed to read [{}]", game_path.display()))?
+                    == FileState::Matches,
+            ),
+            _ => None,
+        };
+        let report = WhichReport {
+            path: striped,
+            in_pack: item.is_some(),
+            optional: item.is_some_and(|item| item.optional),
+            on_disk,
+            patched,
+        };
+        Ok(report)
+    }
+
+    /// Classifies the entries of the default backup by what a restore would do with them
+    pub async fn verify_backup(&self) -> Result<BackupVerifyReport, AlienError> {
+        let instant = std::time::Instant::now();
+        let mut pack = self.load_pack().await?;
+        let pack = pack
+            .filter_hans_dir(self.prefix())
+            .into_iter()
+            .filter(|(item, _)| item.is_file)
+            .map(|(item, striped)| (striped, item.crc32))
+            .collect::<HashMap<_, _>>();
+        let (backup, mut index) = self.index_backup(None).await?;
+        BackupMetadata::take_from(&mut index)?;
+        let targets = index
+            .iter()
+            .filter(|item| item.is_file)
+            .map(|item| {
+                (
+                    item.lowercase_name.clone(),
+                    self.game_path(&item.lowercase_name),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        // Each entry is decompressed, which checks its CRC32, and compared as it streams by
+        let report = tasks::blocking("verify the backup", move || {
+            let mut report = BackupVerifyReport::default();
+            let mut classify = |item: ManifestItem| -> Result<()> {
+                let striped = item.lowercase_name;
+                let crc32 = crc32fast::hash(&item.bytes);
+                if pack.get(&striped) == Some(&crc32) {
+                    report.poisoned.push(striped.clone());
+                }
+                let current = std::fs::File::open(&targets[&striped]).and_then(|file| {
+                    let len = file.metadata()?.len();
+                    Ok((len, manifest::crc32_reader(file)?))
+                });
+                match current {
+                    Ok(current) if current == (item.bytes.len() as u64, crc32) => {
+                        report.identical.push(striped)
+                    }
+                    Ok(_) => report.will_restore.push(striped),
+                    Err(_) => report.missing.push(striped),
+                }
+                Ok(())
+            };
+            if backups::is_clone_tree(&backup) {
+                for item in index.drain(..).filter(|item| item.is_file) {
+                    classify(item)?;
+                }
+            } else {
+                let names = targets.keys().cloned().collect();
+                Manifest::stream_backup(&backup, &names, &mut classify)?;
+            }
+            Ok(report)
+        })
+        .await?;
+        tracing::info!("Verified the backup take {:?}", instant.elapsed());
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hashing;
+    use crate::installer::tests::{
+        fixture_installer, original, original_name, scratch_dir, stage_and_install,
+    };
+    use crate::installer::{Installer, Options, PackSource};
+    use crate::report::DiffEntry;
+    use std::io::{Cursor, Write};
+    use std::path::{Path, PathBuf};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    #[tokio::test]
+    async fn extracting_reproduces_the_pack_entries() {
+        let mut installer = fixture_installer("extract", 4, 256);
+        installer.options.stream_threshold = 0;
+        let dest = installer.game_dir().parent().unwrap().join("extracted");
+        let pack = installer.read_pack_whole().await.unwrap();
+
+        let extracted = installer.extract(&dest, None, None).await.unwrap();
+        assert_eq!(extracted, 4);
+        for item in pack.iter().filter(|item| item.is_file) {
+            assert!(item.path.starts_with(installer.prefix()));
+            assert_eq!(std::fs::read(dest.join(&item.path)).unwrap(), item.bytes);
+        }
+
+        let only = installer.prefix().join("data/bench/000002.txt");
+        let subset = installer.game_dir().parent().unwrap().join("subset");
+        let extracted = installer.extract(&subset, None, Some(&only)).await.unwrap();
+        assert_eq!(extracted, 1);
+        assert!(subset.join(&only).is_file());
+        assert!(!subset
+            .join(installer.prefix().join("data/bench/000001.txt"))
+            .exists());
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn diffs_a_pack_dir_against_a_reference_dir() {
+        let installer = fixture_installer("compare-with", 4, 256);
+        let scratch = installer.game_dir().parent().unwrap().to_path_buf();
+        let ours = scratch.join("ours");
+        installer.extract(&ours, None, None).await.unwrap();
+        let pack = |index: usize| {
+            let striped = format!("data/bench/{:06}.txt", index);
+            (ours.join(installer.prefix()).join(&striped), striped)
+        };
+        // Another version of the pack in other casing, one file edited, one dropped and one new
+        let theirs = scratch.join("theirs");
+        std::fs::create_dir_all(theirs.join("DATA/BENCH")).unwrap();
+        std::fs::copy(pack(0).0, theirs.join("DATA/BENCH/000000.txt")).unwrap();
+        std::fs::copy(pack(1).0, theirs.join("DATA/BENCH/000001.txt")).unwrap();
+        std::fs::write(theirs.join("DATA/BENCH/000002.txt"), b"edited").unwrap();
+        std::fs::write(theirs.join("DATA/BENCH/extra.txt"), b"only theirs").unwrap();
+        let installer = Installer::new(
+            installer.game_dir().to_path_buf(),
+            installer.backup_dir().to_path_buf(),
+            PackSource::Dir(ours.clone()),
+        )
+        .with_options(installer.options.clone());
+
+        let tree_diff = installer.diff(Some(&theirs)).await.unwrap();
+        assert_eq!(tree_diff.identical, 2);
+        let paths = |entries: &[DiffEntry]| {
+            entries
+                .iter()
+                .map(|entry| entry.path.display().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(&tree_diff.changed), [pack(2).1]);
+        assert_eq!(tree_diff.changed[0].reference_size, Some(6));
+        assert_eq!(paths(&tree_diff.added), [pack(3).1]);
+        assert_eq!(paths(&tree_diff.removed), ["DATA/BENCH/extra.txt"]);
+        assert_eq!(tree_diff.removed[0].size, None);
+        assert!(installer
+            .diff(Some(&scratch.join("nowhere")))
+            .await
+            .is_err());
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[tokio::test]
+    async fn which_tells_covered_uncovered_and_white_listed_paths_apart() {
+        let scratch = scratch_dir("which");
+        let options = Options {
+            safe_mode: false,
+            ..Options::default()
+        };
+        let mut pack = Cursor::new(Vec::new());
+        let mut archive = ZipWriter::new(&mut pack);
+        for (name, bytes) in [
+            ("DATA/UI/Menu.txt", "menu"),
+            ("data/ui/fonts_cn.gfx", "font"),
+        ] {
+            let name = options.language_prefix.join(name);
+            archive
+                .start_file(name.display().to_string(), SimpleFileOptions::default())
+                .unwrap();
+            archive.write_all(bytes.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap();
+        let game_dir = scratch.join("game");
+        std::fs::create_dir_all(game_dir.join("DATA/UI")).unwrap();
+        std::fs::write(game_dir.join("DATA/UI/Menu.txt"), "menu").unwrap();
+        std::fs::write(game_dir.join("DATA/UI/Other.txt"), "english").unwrap();
+        let installer = Installer::new(
+            game_dir.clone(),
+            scratch.join("backups"),
+            PackSource::Memory(pack.into_inner().into()),
+        )
+        .with_options(options);
+
+        let covered = installer
+            .which(&game_dir.join("DATA\\UI\\MENU.TXT"))
+            .await
+            .unwrap();
+        assert_eq!(covered.path, Path::new("data/ui/menu.txt"));
+        assert!(covered.in_pack && covered.on_disk && !covered.optional);
+        assert_eq!(covered.patched, Some(true));
+
+        let uncovered = installer
+            .which(Path::new("data/ui/other.txt"))
+            .await
+            .unwrap();
+        assert!(!uncovered.in_pack && uncovered.on_disk);
+        assert_eq!(uncovered.patched, None);
+
+        let white_listed = installer
+            .which(Path::new("./Data/UI/fonts_cn.gfx"))
+            .await
+            .unwrap();
+        assert!(white_listed.in_pack && white_listed.optional && !white_listed.on_disk);
+        assert_eq!(white_listed.patched, None);
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[tokio::test]
+    async fn verify_install_finds_the_corrupted_file() {
+        let installer = fixture_installer("verify-install", 4, 64);
+        let scratch = installer.game_dir().parent().unwrap().to_path_buf();
+        let corrupt = |index: usize| {
+            std::fs::write(
+                installer.game_dir().join(original_name(index)),
+                b"corrupted",
+            )
+            .unwrap()
+        };
+        let list = (0..4)
+            .map(|index| {
+                let hash = hashing::to_hex(&hashing::hash_bytes(&original(index)));
+                format!("{}  {}\n", hash, original_name(index).to_uppercase())
+            })
+            .collect::<String>();
+        let hashes = scratch.join("english.sha256");
+        std::fs::write(&hashes, list).unwrap();
+        corrupt(2);
+
+        let english = installer.verify_install(Some(&hashes)).await.unwrap();
+        assert_eq!(english.matched, 3);
+        assert_eq!(english.mismatched, [PathBuf::from(original_name(2))]);
+        assert!(english.missing.is_empty());
+
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        corrupt(1);
+        let patched = installer.verify_install(None).await.unwrap();
+        assert_eq!(patched.matched, 3);
+        assert_eq!(patched.mismatched, [PathBuf::from(original_name(1))]);
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+}
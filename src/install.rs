@@ -0,0 +1,1975 @@
+use crate::archive::entry_options;
+use crate::backups::NoBackupError;
+use crate::error::AlienError;
+use crate::installer::{temporary_path, Installer, OnMissing};
+use crate::manifest::{self, FileState, Manifest, ManifestItem};
+use crate::metadata::{self, BackupMetadata};
+use crate::report::{PhaseTimer, SyncReport, Timings};
+use crate::state::{InstallState, ProgressJournal, StateFile};
+use crate::{
+    backups, chunked, descriptors, durability, hashing, passphrase, path_structure, permissions,
+    steam, tasks,
+};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+static STAGE_VERSION_ENTRY: &str = ".alien-pack-version";
+
+impl Installer {
+    /// Commits the staged pack into the game dir after backing up the files it replaces,
+    /// resuming an interrupted install and patching incrementally over a previous one
+    pub async fn install(
+        &self,
+        manifest: &mut Manifest,
+        timings: &mut Timings,
+    ) -> Result<String, AlienError> {
+        self.check()?;
+        steam::ensure_installed(&self.game_dir)?;
+        let alien_isolation_dir = self.game_dir.as_path();
+        let pack_version = manifest.version();
+        let phase = PhaseTimer::start();
+        let mut filtered = manifest.filter_hans_dir(self.prefix());
+        if !filtered.iter().any(|(item, _)| item.is_file) {
+            return Err(AlienError::EmptyPack {
+                prefix: self.prefix().to_path_buf(),
+            });
+        }
+        if let Some(selection) = &self.selection {
+            filtered.retain(|(_, striped)| selection.contains(striped));
+        }
+        let oversized = match self.options.max_file_size {
+            Some(max_file_size) => self.oversized_files(&filtered, max_file_size).await,
+            None => Vec::new(),
+        };
+        filtered.retain(|(_, striped)| !oversized.contains(striped));
+        timings.filter = Some(phase.finish(0));
+        let stage_dir = path_structure::stage_dir(&self.game_dir);
+        check_stage(&stage_dir, &pack_version)?;
+        let phase = PhaseTimer::start();
+        self.check_manifest_for_game_data(
+            &filtered,
+            self.options.on_missing,
+            self.options.tolerate_missing_dirs,
+        )?;
+        self.check_backup_drift(&filtered)?;
+        let state_file = StateFile::load(&self.state_dir)?;
+        let interrupted = state_file
+            .get(alien_isolation_dir)
+            .filter(|state| state.in_progress && state.pack_version.as_ref() == Some(&pack_version))
+            .cloned();
+        let previous = state_file
+            .get(alien_isolation_dir)
+            .filter(|state| !state.in_progress && !state.files.is_empty())
+            .cloned();
+        if interrupted.is_none() && previous.is_none() {
+            self.check_foreign_mods(&filtered).await?;
+        }
+        timings.check = Some(phase.finish(0));
+        let mut completed = HashSet::new();
+        if interrupted.is_some() {
+            if self.options.restart {
+                tracing::info!("Restarting the interrupted install");
+            } else {
+                self.confirm("An interrupted install was found, resume it?")?;
+                completed = ProgressJournal::read(&self.state_dir)?
+                    .into_iter()
+                    .collect();
+                if !self.verify_completed_sample(&filtered, &completed).await? {
+                    tracing::warn!(
+                        "Completed files no longer match the pack, reinstalling all files"
+                    );
+                    completed.clear();
+                }
+                tracing::info!("Resuming install, {} files already done", completed.len());
+            }
+        }
+        let files = filtered
+            .iter()
+            .filter(|(item, _)| item.is_file)
+            .map(|(item, striped)| (striped.clone(), item.crc32))
+            .collect::<BTreeMap<_, _>>();
+        let mut unchanged = HashSet::new();
+        let mut modified = HashSet::new();
+        let mut added = Vec::new();
+        let mut dropped = Vec::new();
+        if let (None, Some(previous)) = (&interrupted, &previous) {
+            tracing::info!(
+                "Patching incrementally since pack {}",
+                previous.pack_version.as_deref().unwrap_or("unknown")
+            );
+            for (striped, crc32) in &files {
+                match previous.files.get(striped) {
+                    Some(previous_crc32) if previous_crc32 == crc32 => {
+                        unchanged.insert(striped.clone());
+                    }
+                    Some(previous_crc32) if !self.options.overwrite_modified => {
+                        let path = self.game_path(striped);
+                        if let Ok(current) = manifest::crc32_file(path).await {
+                            if current != *previous_crc32 && current != *crc32 {
+                                tracing::warn!(
+                                    "Skipping [{}], it was modified after the last install",
+                                    striped.display()
+                                );
+                                modified.insert(striped.clone());
+                            }
+                        }
+                    }
+                    Some(_) => {}
+                    None => added.push(striped.clone()),
+                }
+            }
+            dropped = previous
+                .files
+                .keys()
+                .filter(|striped| !files.contains_key(*striped))
+                .cloned()
+                .collect::<Vec<_>>();
+        }
+        let prior = interrupted.clone().or(previous);
+        let incremental = interrupted.is_none() && prior.is_some();
+        let no_backup =
+            self.options.no_backup || prior.as_ref().is_some_and(|state| state.no_backup);
+        let recorded = prior
+            .as_ref()
+            .map(|state| (state.created.clone(), state.overwritten.clone()));
+        let mut created_dirs = prior
+            .as_ref()
+            .map(|state| state.created_dirs.clone())
+            .unwrap_or_default();
+        created_dirs.extend(
+            filtered
+                .iter()
+                .filter(|(item, striped)| item.is_dir && !self.game_path(striped).exists())
+                .map(|(_, striped)| striped.clone()),
+        );
+        let phase = PhaseTimer::start();
+        self.reporter.on_phase_start(
+            "backup",
+            filtered.iter().filter(|(item, _)| item.is_file).count(),
+        );
+        let mut backup = match prior {
+            Some(InstallState {
+                backup: Some(backup_zip),
+                ..
+            }) => {
+                tracing::info!(
+                    "Reusing backup [{}] of the previous install",
+                    backup_zip.display()
+                );
+                let metadata = BackupMetadata::read_from_path(&backup_zip)?.unwrap_or_default();
+                Some((backup_zip, metadata))
+            }
+            Some(_) => None,
+            None if no_backup => {
+                self.confirm(
+                    "Installing without a backup, alien en will not be able to restore English. Continue?")?;
+                None
+            }
+            None => {
+                let backup = self.backup(&filtered, &pack_version, timings).await?;
+                backups::apply_retention(self.backup_dir.as_path(), &self.options.retention)?;
+                Some(backup)
+            }
+        };
+        let backed_up = phase.finish(
+            timings
+                .backup_compress
+                .map(|backup_compress| backup_compress.bytes)
+                .unwrap_or_default(),
+        );
+        self.reporter.on_phase_end("backup", &backed_up);
+        timings.backup = Some(backed_up);
+        let (mut created, mut overwritten) = match (recorded, &backup) {
+            (Some(recorded), _) => recorded,
+            (None, Some((_, metadata))) => (
+                metadata.created.clone(),
+                metadata
+                    .overwritten
+                    .iter()
+                    .chain(&metadata.already_patched)
+                    .cloned()
+                    .collect(),
+            ),
+            (None, None) => filtered
+                .iter()
+                .filter(|(item, _)| item.is_file)
+                .map(|(_, striped)| striped.clone())
+                .partition(|striped| !self.game_path(striped).exists()),
+        };
+        if incremental {
+            let (added_overwritten, added_created): (Vec<_>, Vec<_>) = added
+                .into_iter()
+                .partition(|striped| self.game_path(striped).exists());
+            if let Some((backup_zip, metadata)) = &mut backup {
+                let mut originals = Vec::new();
+                for striped in &added_overwritten {
+                    let path = self.game_path(striped);
+                    let modified = tokio::fs::metadata(&path)
+                        .await
+                        .ok()
+                        .and_then(|metadata| metadata.modified().ok())
+                        .filter(|_| self.options.preserve_timestamps);
+                    originals.push((striped.as_path(), path, modified));
+                }
+                if self.extend_backup(backup_zip, originals, false).await? > 0 {
+                    *metadata = BackupMetadata::read_from_path(backup_zip)?.unwrap_or_default();
+                }
+            }
+            self.remove_dropped(&dropped, &created, backup.as_ref())
+                .await?;
+            created.retain(|striped| !dropped.contains(striped));
+            overwritten.retain(|striped| !dropped.contains(striped));
+            created.extend(added_created);
+            overwritten.extend(added_overwritten);
+        }
+        let mut state = InstallState {
+            installed_at: SystemTime::now(),
+            backup: backup.as_ref().map(|(backup_zip, _)| backup_zip.clone()),
+            no_backup,
+            pack_version: Some(pack_version.clone()),
+            in_progress: true,
+            created,
+            overwritten,
+            created_dirs,
+            base: Some(alien_isolation_dir.to_path_buf()),
+            files,
+        };
+        StateFile::record(&self.state_dir, alien_isolation_dir, state.clone())?;
+
+        let journal = ProgressJournal::open(&self.state_dir, !completed.is_empty())?;
+        filtered.retain(|(_, striped)| {
+            !completed.contains(striped)
+                && !unchanged.contains(striped)
+                && !modified.contains(striped)
+        });
+        let phase = PhaseTimer::start();
+        let bytes: u64 = filtered
+            .iter()
+            .filter(|(item, _)| item.is_file)
+            .map(|(item, _)| item.size)
+            .sum();
+        self.reporter.on_phase_start(
+            "write",
+            filtered.iter().filter(|(item, _)| item.is_file).count(),
+        );
+        let written = self
+            .chinese(filtered, &stage_dir, &journal, self.options.verify_writes)
+            .await;
+        let write = phase.finish(bytes);
+        self.reporter.on_phase_end("write", &write);
+        timings.write = Some(write);
+        let (patched, up_to_date, verified) = match (written, &backup) {
+            (Ok(counts), _) => counts,
+            (Err(partial), Some((backup_zip, metadata))) if !self.options.no_rollback => {
+                let error = self.rollback(partial, backup_zip, metadata).await;
+                StateFile::forget(&self.state_dir, alien_isolation_dir)?;
+                ProgressJournal::remove(&self.state_dir)?;
+                return Err(error.into());
+            }
+            (Err(partial), _) => return Err(partial.error.into()),
+        };
+        state.in_progress = false;
+        self.remember_hashes(
+            &pack_version,
+            state
+                .files
+                .iter()
+                .filter(|(striped, _)| !modified.contains(*striped))
+                .map(|(striped, crc32)| (striped, *crc32)),
+        );
+        StateFile::record(&self.state_dir, alien_isolation_dir, state)?;
+        ProgressJournal::remove(&self.state_dir)?;
+        if let Err(error) = tokio::fs::remove_dir_all(&stage_dir).await {
+            tracing::warn!("Failed to remove [{}]: {}", stage_dir.display(), error);
+        }
+        let patched = patched + completed.len();
+        let mut summary = match backup {
+            Some((_, metadata)) if !metadata.already_patched.is_empty() => format!(
+                "Patched {} files, {} up to date, WARNING: {} files were already Chinese at backup time and have no English original in the backup",
+                patched,
+                up_to_date,
+                metadata.already_patched.len()
+            ),
+            Some(_) => format!("Patched {} files, {} up to date", patched, up_to_date),
+            None => format!(
+                "Patched {} files, {} up to date without a backup",
+                patched, up_to_date
+            ),
+        };
+        if self.options.verify_writes {
+            summary.push_str(&format!(", verified {} bytes read back", verified));
+        }
+        if !oversized.is_empty() {
+            for striped in &oversized {
+                tracing::warn!(
+                    "Skipped [{}], it is over --max-file-size",
+                    striped.display()
+                );
+            }
+            summary.push_str(&format!(
+                ", skipped {} files over --max-file-size",
+                oversized.len()
+            ));
+        }
+        if incremental {
+            return Ok(format!(
+                "{}, {} unchanged since the last pack, {} dropped, {} skipped as modified",
+                summary,
+                unchanged.len(),
+                dropped.len(),
+                modified.len()
+            ));
+        }
+        Ok(summary)
+    }
+
+    /// Restores the English files from the backup, then stages and installs the pack again.
+    /// Restoring first keeps the new backup from taking installed files for originals.
+    pub async fn reinstall(
+        &self,
+        manifest: Manifest,
+        timings: &mut Timings,
+    ) -> Result<String, AlienError> {
+        let report = self.restore(manifest, None).await?;
+        let mut manifest = self.load_pack().await?;
+        self.stage(&mut manifest, timings).await?;
+        let installed = self.install(&mut manifest, timings).await?;
+        Ok(format!(
+            "Reinstalled ({}), {}",
+            report.describe(),
+            installed
+        ))
+    }
+
+    /// Unpacks and verifies the pack in the staging dir next to the game, the game is untouched
+    pub async fn stage(
+        &self,
+        manifest: &mut Manifest,
+        timings: &mut Timings,
+    ) -> Result<String, AlienError> {
+        self.check()?;
+        let phase = PhaseTimer::start();
+        let pack_version = manifest.version();
+        let stage_dir = path_structure::stage_dir(&self.game_dir);
+        let mut filtered = manifest.filter_hans_dir(self.prefix());
+        if !filtered.iter().any(|(item, _)| item.is_file) {
+            return Err(AlienError::EmptyPack {
+                prefix: self.prefix().to_path_buf(),
+            });
+        }
+        if let Some(selection) = &self.selection {
+            filtered.retain(|(_, striped)| selection.contains(striped));
+        }
+        let oversized = match self.options.max_file_size {
+            Some(max_file_size) => self.oversized_files(&filtered, max_file_size).await,
+            None => Vec::new(),
+        };
+        filtered.retain(|(_, striped)| !oversized.contains(striped));
+        for striped in &oversized {
+            tracing::warn!(
+                "Skipped [{}], it is over --max-file-size",
+                striped.display()
+            );
+        }
+        if stage_dir.exists() {
+            tokio::fs::remove_dir_all(&stage_dir)
+                .await
+                .with_context(|| format!("Failed to clear [{}]", stage_dir.display()))?;
+        }
+        tracing::info!("Staging to [{}]", stage_dir.display());
+        let mut writes = futures::stream::iter(filtered.iter_mut().map(|(item, striped)| {
+            let path = stage_dir.join(striped);
+            async move { self.write_file(item, path).await }
+        }))
+        .buffer_unordered(self.options.jobs.max(1));
+        while let Some(result) = writes.next().await {
+            result?;
+        }
+        drop(writes);
+        let mut mismatched = Vec::new();
+        let mut staged = 0;
+        let mut staged_bytes = 0;
+        for (item, striped) in filtered.iter().filter(|(item, _)| item.is_file) {
+            match item.check_file(&stage_dir.join(striped)).await {
+                Ok(FileState::Matches) => {
+                    staged += 1;
+                    staged_bytes += item.size;
+                }
+                _ => mismatched.push(striped.clone()),
+            }
+        }
+        if !mismatched.is_empty() {
+            let _ = tokio::fs::remove_dir_all(&stage_dir).await;
+            return Err(AlienError::StageMismatch(mismatched));
+        }
+        let version_entry = stage_dir.join(STAGE_VERSION_ENTRY);
+        tokio::fs::write(&version_entry, pack_version)
+            .await
+            .with_context(|| format!("Failed to write [{}]", version_entry.display()))?;
+        let stage = phase.finish(staged_bytes);
+        timings.stage = Some(stage);
+        tracing::info!("Staged {} files take {:?}", staged, stage.elapsed);
+        let mut summary = format!("Staged {} files in [{}]", staged, stage_dir.display());
+        if !oversized.is_empty() {
+            summary.push_str(&format!(
+                ", skipped {} files over --max-file-size",
+                oversized.len()
+            ));
+        }
+        summary.push_str(", run `alien zh --commit` to apply them");
+        Ok(summary)
+    }
+
+    /// Writes a patched copy of the game to the zip or directory `output`
+    pub async fn patch_output(
+        &self,
+        manifest: &mut Manifest,
+        output: &Path,
+    ) -> Result<String, AlienError> {
+        let instant = std::time::Instant::now();
+        let alien_isolation_dir = self.game_dir.as_path();
+        if output.starts_with(alien_isolation_dir) {
+            return Err(AlienError::OutputInsideGame {
+                output: output.to_path_buf(),
+            });
+        }
+        let mut filtered = manifest.filter_hans_dir(self.prefix());
+        filtered.retain(|(item, striped)| item.is_file && self.is_selected(striped));
+        let pack = filtered
+            .iter()
+            .map(|(item, striped)| {
+                let path = self.game_path(striped);
+                let relative = path
+                    .strip_prefix(alien_isolation_dir)
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|_| striped.clone());
+                (manifest::path_key(&relative), (relative, &**item))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let originals = backups::clone_tree_files(alien_isolation_dir)?
+            .into_iter()
+            .filter(|relative| !pack.contains_key(&manifest::path_key(relative)))
+            .collect::<Vec<_>>();
+        tracing::info!(
+            "Writing {} game files and {} pack files to [{}]",
+            originals.len(),
+            pack.len(),
+            output.display()
+        );
+        let is_zip = output
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+        if is_zip {
+            let entries = originals
+                .iter()
+                .map(|relative| (relative.clone(), None))
+                .chain(pack.values().map(|(relative, item)| {
+                    let item = (item.deferred.clone(), item.bytes.clone());
+                    (relative.clone(), Some(item))
+                }))
+                .map(|(relative, item)| (self.compression_method(&relative), relative, item))
+                .collect::<Vec<_>>();
+            let game_dir = alien_isolation_dir.to_path_buf();
+            let source = self.pack.clone();
+            let target = output.to_path_buf();
+            let permit = descriptors::acquire_many(2).await?;
+            tasks::blocking("write the patched zip", move || {
+                let _permit = permit;
+                let file = std::fs::File::create(&target)
+                    .with_context(|| format!("Failed to create [{}]", target.display()))?;
+                let mut archive = ZipWriter::new(std::io::BufWriter::new(file));
+                for (method, relative, item) in entries {
+                    let name = relative.display().to_string();
+                    let options = SimpleFileOptions::default().compression_method(method);
+                    archive
+                        .start_file(name.as_str(), options)
+                        .with_context(|| format!("Failed to start file [{}] in archive", name))?;
+                    match item {
+                        Some((deferred, bytes)) => {
+                            let written = match &deferred {
+                                Some(deferred) => source.read_deferred(deferred, |reader| {
+                                    std::io::copy(reader, &mut archive)?;
+                                    Ok(())
+                                }),
+                                None => archive.write_all(&bytes).map_err(Into::into),
+                            };
+                            written.with_context(|| {
+                                format!("Failed to write [{}] to archive", name)
+                            })?;
+                        }
+                        None => {
+                            let path = game_dir.join(&relative);
+                            let mut file = std::fs::File::open(&path)
+                                .with_context(|| format!("Failed to open [{}]", path.display()))?;
+                            std::io::copy(&mut file, &mut archive)
+                                .with_context(|| format!("Failed to copy [{}]", path.display()))?;
+                        }
+                    }
+                }
+                archive
+                    .finish()
+                    .with_context(|| format!("Failed to finish [{}]", target.display()))?;
+                Ok(())
+            })
+            .await?;
+        } else {
+            let mut copies = futures::stream::iter(originals.iter().map(|relative| async move {
+                let path = alien_isolation_dir.join(relative);
+                let target = output.join(relative);
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::copy(&path, &target)
+                    .await
+                    .with_context(|| format!("Failed to copy [{}]", path.display()))?;
+                Ok::<_, color_eyre::Report>(())
+            }))
+            .buffer_unordered(self.options.jobs.max(1));
+            while let Some(result) = copies.next().await {
+                result?;
+            }
+            drop(copies);
+            for (relative, item) in pack.values() {
+                let target = output.join(relative);
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .with_context(|| format!("Failed to create [{}]", parent.display()))?;
+                }
+                match &item.deferred {
+                    Some(deferred) => {
+                        self.stream_entry(&self.pack, deferred, item, &target, &self.cancel)
+                            .await
+                    }
+                    None => tokio::fs::write(&target, &item.bytes)
+                        .await
+                        .map_err(Into::into),
+                }
+                .with_context(|| format!("Failed to write [{}]", target.display()))?;
+            }
+        }
+        tracing::info!("Wrote patched copy take {:?}", instant.elapsed());
+        Ok(format!(
+            "Wrote a patched copy with {} game files and {} pack files to [{}]",
+            originals.len(),
+            pack.len(),
+            output.display()
+        ))
+    }
+
+    pub(crate) async fn remove_dropped(
+        &self,
+        dropped: &[PathBuf],
+        created: &[PathBuf],
+        backup: Option<&(PathBuf, BackupMetadata)>,
+    ) -> Result<()> {
+        let (dropped_created, dropped_overwritten): (Vec<_>, Vec<_>) = dropped
+            .iter()
+            .cloned()
+            .partition(|striped| created.contains(striped));
+        for striped in &dropped_created {
+            let path = self.resolve_link(&self.game_path(striped))?;
+            match permissions::retry_writable(&path, false, self.options.fix_permissions, || {
+                tokio::fs::remove_file(&path)
+            })
+            .await
+            {
+                Ok(()) => tracing::info!("Removed [{}] dropped from the pack", path.display()),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+                Err(error) => {
+                    return Err(error)
+                        .with_context(|| format!("Failed to remove [{}]", path.display()))
+                }
+            }
+        }
+        match backup {
+            Some((backup_zip, metadata)) => {
+                self.rollback_written(&dropped_overwritten, backup_zip, metadata).await?
+            }
+            None if !dropped_overwritten.is_empty() => tracing::warn!(
+                "{} files dropped from the pack cannot be restored without a backup, verify game files in Steam",
+                dropped_overwritten.len()
+            ),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Rewrites only the pack files that are missing or differ from the pack
+    pub async fn repair(
+        &self,
+        manifest: &mut Manifest,
+        timings: &mut Timings,
+    ) -> Result<String, AlienError> {
+        self.check()?;
+        steam::ensure_installed(&self.game_dir)?;
+        let instant = std::time::Instant::now();
+        let pack_version = manifest.version();
+        let alien_isolation_dir = self.game_dir.as_path();
+        self.check_manifest_for_game_data(
+            &manifest.filter_hans_dir(self.prefix()),
+            self.options.on_missing,
+            self.options.tolerate_missing_dirs,
+        )?;
+        let phase = PhaseTimer::start();
+        let report = self.validate_cached(manifest).await?;
+        timings.validate = Some(phase.finish(manifest.bytes()));
+        let correct = report.matched;
+        let mismatched = report.mismatched.into_iter().collect::<HashSet<_>>();
+        let missing = report.missing.into_iter().collect::<HashSet<_>>();
+        let mut broken = Vec::new();
+        for (item, striped) in manifest.filter_hans_dir(self.prefix()) {
+            if !item.is_file {
+                continue;
+            }
+            let current = if mismatched.contains(&striped) {
+                let modified = tokio::fs::metadata(self.game_path(&striped))
+                    .await
+                    .ok()
+                    .and_then(|metadata| metadata.modified().ok());
+                Some(modified)
+            } else if missing.contains(&striped) {
+                None
+            } else {
+                continue;
+            };
+            broken.push((item, striped, current));
+        }
+        tracing::info!(
+            "Found {} files to repair take {:?}",
+            broken.len(),
+            instant.elapsed()
+        );
+        if broken.is_empty() {
+            return Ok(format!("All {} files are already correct", correct));
+        }
+
+        let backup_zip = StateFile::load(&self.state_dir)?
+            .get(alien_isolation_dir)
+            .and_then(|state| state.backup.clone());
+        let reverted = broken
+            .iter()
+            .flat_map(|(_, striped, current)| {
+                let modified = current
+                    .as_ref()?
+                    .filter(|_| self.options.preserve_timestamps);
+                Some((striped.as_path(), self.game_path(striped), modified))
+            })
+            .collect::<Vec<_>>();
+        let backed_up = match backup_zip {
+            Some(backup_zip) => self.extend_backup(&backup_zip, reverted, false).await?,
+            None => {
+                tracing::warn!(
+                    "No backup is recorded for this install, reverted files are not backed up"
+                );
+                0
+            }
+        };
+
+        let result = self
+            .join_all(broken.into_iter().map(|(item, striped, _)| async move {
+                self.write_file(item, self.game_path(&striped)).await?;
+                Ok::<_, color_eyre::Report>((striped, item.crc32))
+            }))
+            .await;
+        let repaired = result.into_iter().collect::<Result<Vec<_>>>()?;
+        self.remember_hashes(
+            &pack_version,
+            repaired.iter().map(|(striped, crc32)| (striped, *crc32)),
+        );
+        let repaired = repaired.len();
+        Ok(format!(
+            "Repaired {} files, {} already correct, added {} reverted files to the backup",
+            repaired, correct, backed_up
+        ))
+    }
+
+    /// Refreshes the backup with originals Steam updated since the install, then patches them
+    pub async fn sync(&self, manifest: &mut Manifest) -> Result<SyncReport, AlienError> {
+        self.check()?;
+        steam::ensure_installed(&self.game_dir)?;
+        let instant = std::time::Instant::now();
+        let alien_isolation_dir = self.game_dir.as_path();
+        let backup_zip = match StateFile::load(&self.state_dir)?
+            .get(alien_isolation_dir)
+            .and_then(|state| state.backup.clone())
+        {
+            Some(backup_zip) => backup_zip,
+            None => {
+                return Err(AlienError::from(NoBackupError {
+                    location: self.backup_dir.as_path().to_path_buf(),
+                }))
+            }
+        };
+        let mut backup = self.read_backup(Some(backup_zip.as_path())).await?;
+        BackupMetadata::take_from(&mut backup)?;
+        let originals = backup
+            .iter()
+            .filter(|item| item.is_file)
+            .map(|item| (item.lowercase_name.clone(), item.crc32))
+            .collect::<HashMap<_, _>>();
+        let mut filtered = manifest.filter_hans_dir(self.prefix());
+        filtered.retain(|(item, striped)| item.is_file && self.is_selected(striped));
+        let mut report = SyncReport::default();
+        let mut changed = Vec::new();
+        for (item, striped) in filtered {
+            let path = self.game_path(&striped);
+            if !path.exists() {
+                continue;
+            }
+            let crc32 = manifest::crc32_file(path).await?;
+            if crc32 == item.crc32 {
+                continue;
+            }
+            let newer = originals.get(&striped) != Some(&crc32);
+            if newer {
+                report.newer_original.push(striped.clone());
+            } else {
+                report.backup_original.push(striped.clone());
+            }
+            changed.push((item, striped, newer));
+        }
+        tracing::info!(
+            "Classified {} changed files take {:?}",
+            changed.len(),
+            instant.elapsed()
+        );
+        if report.newer_original.is_empty() {
+            return Ok(report);
+        }
+        self.confirm(&format!(
+            "Refresh {} backup entries with the updated originals and patch them again?",
+            report.newer_original.len()
+        ))?;
+        let refreshed = changed
+            .iter()
+            .filter(|(_, _, newer)| *newer)
+            .map(|(_, striped, _)| (striped.as_path(), self.game_path(striped), None))
+            .collect::<Vec<_>>();
+        report.refreshed = self.extend_backup(&backup_zip, refreshed, true).await?;
+        let result = self
+            .join_all(changed.into_iter().map(|(item, striped, _)| async move {
+                self.write_file(item, self.game_path(&striped)).await
+            }))
+            .await;
+        report.patched = result.into_iter().collect::<Result<Vec<_>>>()?.len();
+        Ok(report)
+    }
+
+    /// Adds originals to an existing backup, each copied from the file paired with its path, with
+    /// `refresh` entries already in it are replaced
+    pub(crate) async fn extend_backup(
+        &self,
+        backup_zip: &Path,
+        additions: Vec<(&Path, PathBuf, Option<SystemTime>)>,
+        refresh: bool,
+    ) -> Result<usize> {
+        let instant = std::time::Instant::now();
+        let mut metadata = match BackupMetadata::read_from_path(backup_zip)? {
+            Some(metadata) => metadata,
+            None => {
+                tracing::warn!(
+                    "[{}] is a legacy backup, reverted files are not added to it",
+                    backup_zip.display()
+                );
+                return Ok(0);
+            }
+        };
+        let additions = additions
+            .into_iter()
+            .filter(|(striped, _, _)| {
+                refresh || !metadata.overwritten.iter().any(|path| path == striped)
+            })
+            .collect::<Vec<_>>();
+        if additions.is_empty() {
+            return Ok(0);
+        }
+        if backups::is_clone_tree(backup_zip) {
+            let added = additions.len();
+            for (striped, original, modified) in additions {
+                let path = backup_zip.join(striped);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(&original, &path).with_context(|| {
+                    format!(
+                        "Failed to copy [{}] to [{}]",
+                        original.display(),
+                        path.display()
+                    )
+                })?;
+                if let Some(modified) = modified {
+                    let modified = filetime::FileTime::from_system_time(modified);
+                    filetime::set_file_mtime(&path, modified)?;
+                }
+                record_addition(&mut metadata, striped, &path, modified)?;
+            }
+            std::fs::write(
+                backup_zip.join(metadata::METADATA_ENTRY),
+                metadata.to_bytes()?,
+            )?;
+            tracing::info!(
+                "Added {} reverted files to [{}] take {:?}",
+                added,
+                backup_zip.display(),
+                instant.elapsed()
+            );
+            return Ok(added);
+        }
+        let file = std::fs::File::open(backup_zip)
+            .with_context(|| format!("Failed to open backup [{}]", backup_zip.display()))?;
+        let mut source = ZipArchive::new(std::io::BufReader::new(file))?;
+        let password = if metadata.encrypted {
+            let password = passphrase::backup_passphrase(false)?;
+            if let Some(first) = metadata.overwritten.first() {
+                source
+                    .by_name_decrypt(&first.display().to_string(), password.as_bytes())
+                    .map(drop)
+                    .map_err(|_| AlienError::WrongPassphrase)?;
+            }
+            Some(password)
+        } else {
+            None
+        };
+
+        let mut archive = self.create_partial(backup_zip)?;
+        // Compared in NFC, entries written on macOS may carry decomposed names
+        let replaced = additions
+            .iter()
+            .map(|(striped, _, _)| manifest::path_key(striped))
+            .collect::<HashSet<_>>();
+        let added = additions.len();
+        let write_entries = || -> Result<()> {
+            for index in 0..source.len() {
+                let file = source.by_index_raw(index)?;
+                let key = manifest::path_key(Path::new(file.name()));
+                if file.name() != metadata::METADATA_ENTRY && !replaced.contains(&key) {
+                    archive.raw_copy_file(file)?;
+                }
+            }
+            for (striped, original, modified) in additions {
+                let name = striped.display().to_string();
+                let method = self.compression_method(striped);
+                archive
+                    .start_file(
+                        name.as_str(),
+                        entry_options(method, modified, None, password.as_deref()),
+                    )
+                    .with_context(|| format!("Failed to start file [{}] in archive", name))?;
+                let mut file = std::fs::File::open(&original)
+                    .with_context(|| format!("Failed to open [{}]", original.display()))?;
+                let size = file.metadata()?.len();
+                let chunk_size = self.options.chunk_size;
+                chunked::copy(
+                    &mut file,
+                    &mut archive,
+                    &original,
+                    size,
+                    chunk_size,
+                    &self.cancel,
+                )
+                .with_context(|| format!("Failed to write [{}] to archive", name))?;
+                record_addition(&mut metadata, striped, &original, modified)?;
+            }
+            Ok(())
+        };
+        if let Err(error) = write_entries() {
+            self.discard_partial(backup_zip).await;
+            return Err(error);
+        }
+        drop(source);
+        self.commit_partial(archive, &metadata, backup_zip).await?;
+        tracing::info!(
+            "Added {} reverted files to [{}] take {:?}",
+            added,
+            backup_zip.display(),
+            instant.elapsed()
+        );
+        Ok(added)
+    }
+
+    pub(crate) async fn verify_completed_sample(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+        completed: &HashSet<PathBuf>,
+    ) -> Result<bool> {
+        const SAMPLE_SIZE: usize = 16;
+        let candidates = filtered
+            .iter()
+            .filter(|(item, striped)| item.is_file && completed.contains(striped))
+            .collect::<Vec<_>>();
+        let step = (candidates.len() / SAMPLE_SIZE).max(1);
+        for (item, striped) in candidates.into_iter().step_by(step).take(SAMPLE_SIZE) {
+            if !is_up_to_date(item, &self.game_path(striped)).await {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Fails on pack entries the game lacks unless they are optional. With `tolerate_dirs`,
+    /// directories whose pack files are all optional, or that hold none, only warn like files.
+    pub(crate) fn check_manifest_for_game_data(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+        on_missing: OnMissing,
+        tolerate_dirs: bool,
+    ) -> Result<()> {
+        let instant = std::time::Instant::now();
+        let alien_isolation_dir = self.game_dir.as_path();
+        tracing::info!(
+            "Checking manifest for game data [{}]",
+            alien_isolation_dir.display()
+        );
+        let not_found = filtered
+            .iter()
+            .flat_map(|(item, striped)| {
+                let path = self.game_path(striped);
+                if striped.components().count() == 0 || path.exists() || item.optional {
+                    return None;
+                }
+                if item.is_dir && tolerate_dirs && holds_only_optional(filtered, striped) {
+                    tracing::warn!(
+                        "Skipping missing directory [{}], the pack has no required files in it",
+                        striped.display()
+                    );
+                    return None;
+                }
+                Some(striped.to_path_buf())
+            })
+            .collect::<Vec<_>>();
+        if !not_found.is_empty() && on_missing != OnMissing::Error {
+            tracing::warn!(
+                "{} files are missing from the game, backing them up with --on-missing {:?}",
+                not_found.len(),
+                on_missing
+            );
+        } else if !not_found.is_empty() {
+            return Err(AlienError::MissingFiles(not_found).into());
+        }
+        self.check_path_lengths(filtered)?;
+        tracing::info!(
+            "Checked manifest for game data take {:?}",
+            instant.elapsed()
+        );
+        Ok(())
+    }
+
+    /// Paths the install writes must fit MAX_PATH, including the temporary file next to each
+    #[cfg(windows)]
+    pub(crate) fn check_path_lengths(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+    ) -> Result<()> {
+        use std::os::windows::ffi::OsStrExt;
+        const MAX_PATH: usize = 260;
+        let too_long = filtered
+            .iter()
+            .map(|(_, striped)| temporary_path(&self.game_path(striped)))
+            .filter(|path| path.as_os_str().encode_wide().count() >= MAX_PATH)
+            .collect::<Vec<_>>();
+        if too_long.is_empty() {
+            return Ok(());
+        }
+        Err(AlienError::PathsTooLong {
+            max: MAX_PATH,
+            paths: too_long,
+        }
+        .into())
+    }
+
+    #[cfg(not(windows))]
+    pub(crate) fn check_path_lengths(
+        &self,
+        _filtered: &[(&mut ManifestItem, PathBuf)],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Pack files whose pack or game copy is larger than `max_file_size` bytes
+    pub(crate) async fn oversized_files(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+        max_file_size: u64,
+    ) -> Vec<PathBuf> {
+        let mut oversized = Vec::new();
+        for (item, striped) in filtered.iter().filter(|(item, _)| item.is_file) {
+            let on_disk = tokio::fs::metadata(self.game_path(striped))
+                .await
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            if item.size > max_file_size || on_disk > max_file_size {
+                oversized.push(striped.clone());
+            }
+        }
+        oversized
+    }
+
+    pub(crate) async fn check_foreign_mods(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+    ) -> Result<()> {
+        let original = backups::list_backups(self.backup_dir.as_path())?
+            .into_iter()
+            .find(|backup| backup.original && backup.error.is_none());
+        let vanilla = match original {
+            Some(original) => BackupMetadata::read_from_path(&original.path)?
+                .map(|metadata| metadata.checksums)
+                .unwrap_or_default(),
+            None => BTreeMap::new(),
+        };
+        if vanilla.is_empty() {
+            tracing::info!("No checksums of a clean install, skipping the check for other mods");
+            return Ok(());
+        }
+        let instant = std::time::Instant::now();
+        let mut pack = HashMap::new();
+        for (item, striped) in filtered.iter().filter(|(item, _)| item.is_file) {
+            let hash = self.item_hash(item).await?;
+            pack.insert(striped, hashing::to_hex(&hash));
+        }
+        let targets = pack
+            .keys()
+            .map(|striped| (striped.to_path_buf(), self.game_path(striped)))
+            .collect();
+        let current = hashing::hash_files(targets, self.options.jobs).await?;
+        let foreign = current
+            .into_iter()
+            .map(|(striped, hash)| (striped, hashing::to_hex(&hash)))
+            .filter(|(striped, hash)| {
+                pack.get(striped) != Some(hash)
+                    && vanilla.get(striped).is_some_and(|vanilla| vanilla != hash)
+            })
+            .map(|(striped, _)| striped)
+            .collect::<Vec<_>>();
+        tracing::info!(
+            "Checked for files changed by other mods take {:?}",
+            instant.elapsed()
+        );
+        if foreign.is_empty() {
+            return Ok(());
+        }
+        if self.options.overwrite_foreign {
+            tracing::warn!(
+                "Overwriting {} files modified by something else, the backup keeps their modded content",
+                foreign.len()
+            );
+            return Ok(());
+        }
+        Err(AlienError::ForeignMods(foreign).into())
+    }
+
+    /// Warns about pack files whose size in the game is neither the backup's nor the pack's,
+    /// failing on them with `strict`. Returns the drifted files.
+    pub(crate) fn check_backup_drift(
+        &self,
+        filtered: &[(&mut ManifestItem, PathBuf)],
+    ) -> Result<Vec<PathBuf>> {
+        let backup_zip = match backups::default_backup(self.backup_dir.as_path())? {
+            Some(backup_zip) => backup_zip,
+            None => return Ok(Vec::new()),
+        };
+        let instant = std::time::Instant::now();
+        let pack_sizes = filtered
+            .iter()
+            .filter(|(item, _)| item.is_file)
+            .map(|(item, striped)| (striped, item.size))
+            .collect::<HashMap<_, _>>();
+        let mut drifted = Vec::new();
+        for (striped, backup_size) in backups::entry_sizes(&backup_zip)? {
+            let pack_size = match pack_sizes.get(&striped) {
+                Some(pack_size) => *pack_size,
+                None => continue,
+            };
+            let current_size = match std::fs::metadata(self.game_path(&striped)) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            if current_size != backup_size && current_size != pack_size {
+                tracing::warn!(
+                    "[{}] is {} bytes but {} bytes in the backup, the game may have been updated",
+                    striped.display(),
+                    current_size,
+                    backup_size
+                );
+                drifted.push(striped);
+            }
+        }
+        tracing::info!(
+            "Checked backup [{}] for drift take {:?}",
+            backup_zip.display(),
+            instant.elapsed()
+        );
+        if self.options.strict && !drifted.is_empty() {
+            return Err(AlienError::BackupDrift {
+                drifted: drifted.len(),
+                backup: backup_zip,
+            }
+            .into());
+        }
+        Ok(drifted)
+    }
+
+    pub(crate) async fn chinese(
+        &self,
+        mut filtered: Vec<(&mut ManifestItem, PathBuf)>,
+        stage_dir: &Path,
+        journal: &ProgressJournal,
+        verify: bool,
+    ) -> std::result::Result<(usize, usize, u64), PartialInstall> {
+        tracing::info!("Converting to Chinese from [{}]", stage_dir.display());
+        let instant = std::time::Instant::now();
+        // Files in place in the game dir, including any that then fail the read back
+        let committed = &Mutex::new(Vec::new());
+
+        let result = self
+            .join_all(filtered.iter_mut().map(|(item, striped)| async move {
+                if self.cancel.is_cancelled() {
+                    return Err(AlienError::Cancelled {
+                        what: format!("the install before [{}]", striped.display()),
+                    }
+                    .into());
+                }
+                let path = self.game_path(striped);
+                let written = if item.is_file && is_up_to_date(item, &path).await {
+                    false
+                } else if item.is_file {
+                    self.commit_staged(&stage_dir.join(striped.as_path()), &path, || {
+                        committed.lock().unwrap().push(striped.clone())
+                    })
+                    .await?;
+                    true
+                } else {
+                    self.write_file(item, path.clone()).await?;
+                    true
+                };
+                let verified = if written && item.is_file && verify {
+                    verify_written(item, &path).await?
+                } else {
+                    0
+                };
+                journal.record(striped);
+                if item.is_file {
+                    let bytes = if written { item.size } else { 0 };
+                    self.reporter.on_file_done("write", striped, bytes);
+                }
+                Ok((item.is_file, written, verified))
+            }))
+            .await;
+        let written = std::mem::take(&mut *committed.lock().unwrap());
+        let mut up_to_date = 0;
+        let mut verified = 0;
+        let mut first_error = None;
+        for result in result {
+            match result {
+                Ok((true, true, bytes)) => verified += bytes,
+                Ok((true, false, _)) => up_to_date += 1,
+                Ok(_) => {}
+                Err(error) if first_error.is_none() => first_error = Some(error),
+                Err(error) => tracing::error!("{:#}", error),
+            }
+        }
+        if let Some(error) = first_error {
+            return Err(PartialInstall { written, error });
+        }
+
+        tracing::info!("Converted to Chinese take {:?}", instant.elapsed());
+        Ok((written.len(), up_to_date, verified))
+    }
+
+    /// Moves `staged` into place at `path`, calling `moved` as soon as it is there, before the
+    /// directory is synced, so a failure after that point still rolls it back
+    pub(crate) async fn commit_staged(
+        &self,
+        staged: &Path,
+        path: &Path,
+        moved: impl FnOnce(),
+    ) -> Result<()> {
+        let path = self.resolve(path)?;
+        let path = path.as_path();
+        if let Some(parent) = path.parent() {
+            if !matches!(tokio::fs::try_exists(parent).await, Ok(true)) {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .with_context(|| format!("Failed to create [{}]", parent.display()))?;
+            }
+        }
+        if permissions::retry_writable(path, true, self.options.fix_permissions, || {
+            tokio::fs::rename(staged, path)
+        })
+        .await
+        .is_err()
+        {
+            let temporary = temporary_path(path);
+            let _permit = descriptors::acquire_many(2).await?;
+            if let Err(error) = tokio::fs::copy(staged, &temporary).await {
+                let _ = tokio::fs::remove_file(&temporary).await;
+                return Err(error)
+                    .with_context(|| format!("Failed to copy [{}]", staged.display()));
+            }
+            if self.options.durable {
+                tokio::fs::File::open(&temporary).await?.sync_all().await?;
+            }
+            if let Err(error) =
+                permissions::retry_writable(path, true, self.options.fix_permissions, || {
+                    tokio::fs::rename(&temporary, path)
+                })
+                .await
+            {
+                let _ = tokio::fs::remove_file(&temporary).await;
+                return Err(error)
+                    .with_context(|| format!("Failed to move [{}] into place", path.display()));
+            }
+        }
+        moved();
+        durability::sync_parent(path, self.options.durable)
+            .await
+            .with_context(|| format!("Failed to sync the directory of [{}]", path.display()))
+    }
+
+    pub(crate) async fn rollback(
+        &self,
+        partial: PartialInstall,
+        backup_zip: &Path,
+        metadata: &BackupMetadata,
+    ) -> color_eyre::Report {
+        tracing::warn!(
+            "Install failed, rolling back {} written files",
+            partial.written.len()
+        );
+        match self.rollback_written(&partial.written, backup_zip, metadata).await {
+            Ok(()) => partial
+                .error
+                .wrap_err("Install failed, all written files were rolled back"),
+            Err(rollback_error) => partial.error.wrap_err(format!(
+                "Install failed and the rollback failed too ({:#}), run `alien en --from {}` or verify game files in Steam",
+                rollback_error,
+                backup_zip.display()
+            )),
+        }
+    }
+
+    pub(crate) async fn rollback_written(
+        &self,
+        written: &[PathBuf],
+        backup_zip: &Path,
+        metadata: &BackupMetadata,
+    ) -> Result<()> {
+        let instant = std::time::Instant::now();
+        let written = written.iter().collect::<HashSet<_>>();
+        let mut backup = self.read_backup(Some(backup_zip)).await?;
+        BackupMetadata::take_from(&mut backup)?;
+
+        let result = self
+            .join_all(
+                metadata
+                    .created
+                    .iter()
+                    .filter(|striped| written.contains(striped))
+                    .map(|striped| self.game_path(striped))
+                    .map(|path| async move {
+                        let path = self.resolve_link(&path)?;
+                        permissions::retry_writable(
+                            &path,
+                            false,
+                            self.options.fix_permissions,
+                            || tokio::fs::remove_file(&path),
+                        )
+                        .await
+                        .with_context(|| format!("Failed to remove [{}]", path.display()))
+                    }),
+            )
+            .await;
+        result.into_iter().collect::<Result<Vec<_>>>()?;
+
+        let result = self
+            .join_all(
+                backup
+                    .iter_mut()
+                    .filter(|item| written.contains(&item.lowercase_name))
+                    .map(|item| async move {
+                        let path = self.game_path(&item.lowercase_name);
+                        self.write_original(item, path).await
+                    }),
+            )
+            .await;
+        result.into_iter().collect::<Result<Vec<_>>>()?;
+
+        tracing::info!("Rolled back take {:?}", instant.elapsed());
+        Ok(())
+    }
+}
+
+fn check_stage(stage_dir: &Path, pack_version: &str) -> Result<(), AlienError> {
+    match std::fs::read_to_string(stage_dir.join(STAGE_VERSION_ENTRY)) {
+        Ok(version) if version == pack_version => Ok(()),
+        Ok(_) => Err(AlienError::StagedFromOtherPack {
+            dir: stage_dir.to_path_buf(),
+        }),
+        Err(_) => Err(AlienError::NothingStaged {
+            dir: stage_dir.to_path_buf(),
+        }),
+    }
+}
+
+fn record_addition(
+    metadata: &mut BackupMetadata,
+    striped: &Path,
+    original: &Path,
+    modified: Option<SystemTime>,
+) -> Result<()> {
+    metadata.record_modified(striped, modified);
+    metadata.created.retain(|path| path != striped);
+    metadata.already_patched.retain(|path| path != striped);
+    if !metadata.overwritten.iter().any(|path| path == striped) {
+        metadata.overwritten.push(striped.to_path_buf());
+    }
+    let hash = std::fs::File::open(original)
+        .and_then(hashing::hash_reader)
+        .with_context(|| format!("Failed to hash [{}]", original.display()))?;
+    metadata
+        .checksums
+        .insert(striped.to_path_buf(), hashing::to_hex(&hash));
+    Ok(())
+}
+
+fn holds_only_optional(filtered: &[(&mut ManifestItem, PathBuf)], dir: &Path) -> bool {
+    filtered
+        .iter()
+        .filter(|(item, striped)| item.is_file && striped.starts_with(dir))
+        .all(|(item, _)| item.optional)
+}
+
+struct PartialInstall {
+    written: Vec<PathBuf>,
+    error: color_eyre::Report,
+}
+
+/// Reads `path` back in chunks and compares its size and CRC32 with `item`
+async fn verify_written(item: &ManifestItem, path: &Path) -> Result<u64> {
+    let permit = descriptors::acquire().await?;
+    let file = path.to_path_buf();
+    let (read, read_crc32) = tasks::blocking("read back a written file", move || {
+        let _permit = permit;
+        let read = std::fs::metadata(&file)?.len();
+        Ok((read, manifest::crc32_reader(std::fs::File::open(&file)?)?))
+    })
+    .await
+    .with_context(|| format!("Failed to read back [{}]", path.display()))?;
+    if read != item.size || read_crc32 != item.crc32 {
+        return Err(AlienError::ReadBackMismatch {
+            path: path.to_path_buf(),
+            read,
+            read_crc32,
+            written: item.size,
+            written_crc32: item.crc32,
+        }
+        .into());
+    }
+    Ok(read)
+}
+
+async fn is_up_to_date(item: &ManifestItem, path: &Path) -> bool {
+    matches!(item.check_file(path).await, Ok(FileState::Matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backups;
+    use crate::error::AlienError;
+    use crate::installer::tests::{
+        cancel_after, fixture_installer, original, original_name, scratch_dir, stage_and_install,
+    };
+    use crate::installer::{Installer, OnMissing, Options, PackSource};
+    use crate::path_structure;
+    use crate::progress::{ProgressEvent, ProgressReporter};
+    use crate::report::{Phase, Timings};
+    use crate::state::StateFile;
+    use std::io::{Cursor, Read, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use zip::write::SimpleFileOptions;
+    use zip::{ZipArchive, ZipWriter};
+
+    #[tokio::test]
+    async fn installs_more_files_than_the_io_limit() {
+        let mut installer = fixture_installer("io-limit", 64, 64);
+        installer.options.io_limit = 4;
+        let mut manifest = installer.load_pack().await.unwrap();
+        let summary = stage_and_install(&installer, &mut manifest).await.unwrap();
+        assert_eq!(summary, "Patched 64 files, 0 up to date");
+        for (item, striped) in manifest.filter_hans_dir(installer.prefix()) {
+            if item.is_file {
+                let installed = std::fs::read(installer.game_dir().join(striped)).unwrap();
+                assert_eq!(installed, item.bytes[..]);
+            }
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_failure_past_the_io_limit_keeps_its_typed_error() {
+        let mut installer = fixture_installer("io-limit-failure", 64, 64);
+        installer.options.io_limit = 4;
+        installer.options.verify_writes = true;
+        let mut manifest = installer.load_pack().await.unwrap();
+        let mut timings = Timings::default();
+        installer.stage(&mut manifest, &mut timings).await.unwrap();
+        // The staged copy no longer matches the pack, so it fails the read back once committed
+        let stage_dir = path_structure::stage_dir(installer.game_dir());
+        std::fs::write(stage_dir.join(original_name(40)), b"tampered").unwrap();
+
+        let error = installer
+            .install(&mut manifest, &mut timings)
+            .await
+            .unwrap_err();
+        match error {
+            AlienError::ReadBackMismatch { path, .. } => {
+                assert_eq!(path, installer.game_dir().join(original_name(40)))
+            }
+            error => panic!("expected a read back mismatch, got {:?}", error),
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_failure_mid_patch_rolls_the_game_back() {
+        let mut installer = fixture_installer("rollback", 32, 64);
+        installer.options.io_limit = 4;
+        installer.options.verify_writes = true;
+        installer.options.on_missing = OnMissing::Skip;
+        // One file the pack creates, the rollback has to remove it again
+        std::fs::remove_file(installer.game_dir().join(original_name(3))).unwrap();
+        let mut manifest = installer.load_pack().await.unwrap();
+        let mut timings = Timings::default();
+        installer.stage(&mut manifest, &mut timings).await.unwrap();
+        // Committed like the rest, then fails the read back half way through the patch
+        let stage_dir = path_structure::stage_dir(installer.game_dir());
+        std::fs::write(stage_dir.join(original_name(16)), b"tampered").unwrap();
+
+        let error = installer
+            .install(&mut manifest, &mut timings)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AlienError::ReadBackMismatch { .. }));
+        for index in 0..32 {
+            let path = installer.game_dir().join(original_name(index));
+            if index == 3 {
+                assert!(!path.exists());
+            } else {
+                assert_eq!(std::fs::read(&path).unwrap(), original(index));
+            }
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn cancelling_mid_install_rolls_the_game_back() {
+        let mut installer = fixture_installer("cancel-install", 32, 64);
+        installer.options.io_limit = 4;
+        let installer = cancel_after(installer, "write", Some(8));
+        let mut manifest = installer.load_pack().await.unwrap();
+
+        let error = stage_and_install(&installer, &mut manifest)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AlienError::Cancelled { .. }));
+        for index in 0..32 {
+            let path = installer.game_dir().join(original_name(index));
+            assert_eq!(std::fs::read(&path).unwrap(), original(index));
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_dir_that_is_not_the_game_is_refused() {
+        let mut installer = fixture_installer("not-a-game", 4, 64);
+        installer.options.safe_mode = true;
+        let mut manifest = installer.load_pack().await.unwrap();
+
+        let error = stage_and_install(&installer, &mut manifest)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AlienError::NotAGameDir { .. }));
+        let error = installer.restore(manifest, None).await.unwrap_err();
+        assert!(matches!(error, AlienError::NotAGameDir { .. }));
+        assert!(!path_structure::stage_dir(installer.game_dir()).exists());
+        for index in 0..4 {
+            let path = installer.game_dir().join(original_name(index));
+            assert_eq!(std::fs::read(&path).unwrap(), original(index));
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    /// Paths written to the game, the files of the "write" phase that were not up to date
+    #[derive(Default)]
+    struct Written(Mutex<Vec<PathBuf>>);
+
+    impl ProgressReporter for Written {
+        fn on_file_done(&self, phase: &'static str, path: &Path, bytes: u64) {
+            if phase == "write" && bytes > 0 {
+                self.0.lock().unwrap().push(path.to_path_buf());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn an_updated_pack_writes_only_its_changed_file() {
+        let written = Arc::new(Written::default());
+        let installer = fixture_installer("incremental", 8, 64).with_reporter(written.clone());
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        written.0.lock().unwrap().clear();
+
+        let changed = PathBuf::from(original_name(5));
+        let mut manifest = installer.load_pack().await.unwrap();
+        for (item, striped) in manifest.filter_hans_dir(installer.prefix()) {
+            if striped == changed {
+                item.bytes = b"updated translation".to_vec();
+                item.size = item.bytes.len() as u64;
+                item.crc32 = crc32fast::hash(&item.bytes);
+            }
+        }
+        let summary = stage_and_install(&installer, &mut manifest).await.unwrap();
+        assert!(
+            summary.ends_with("7 unchanged since the last pack, 0 dropped, 0 skipped as modified"),
+            "{}",
+            summary
+        );
+        assert_eq!(*written.0.lock().unwrap(), [changed.clone()]);
+        assert_eq!(
+            std::fs::read(installer.game_dir().join(&changed)).unwrap(),
+            b"updated translation"
+        );
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn reinstalling_over_an_install_keeps_a_valid_backup() {
+        let installer = fixture_installer("reinstall", 8, 64);
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+
+        let manifest = installer.load_pack().await.unwrap();
+        let summary = installer
+            .reinstall(manifest, &mut Timings::default())
+            .await
+            .unwrap();
+        assert!(
+            summary.starts_with("Reinstalled (restored 8 files, removed 0 files), Patched 8 files"),
+            "{}",
+            summary
+        );
+        let mut manifest = installer.load_pack().await.unwrap();
+        for (item, striped) in manifest.filter_hans_dir(installer.prefix()) {
+            if item.is_file {
+                let installed = std::fs::read(installer.game_dir().join(striped)).unwrap();
+                assert_eq!(installed, item.bytes[..]);
+            }
+        }
+
+        let manifest = installer.load_pack().await.unwrap();
+        installer.restore(manifest, None).await.unwrap();
+        for index in 0..8 {
+            let restored = std::fs::read(installer.game_dir().join(original_name(index))).unwrap();
+            assert_eq!(restored, original(index));
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn installing_without_a_backup_writes_no_archive() {
+        let mut installer = fixture_installer("no-backup", 4, 64);
+        installer.options.no_backup = true;
+        let mut manifest = installer.load_pack().await.unwrap();
+
+        let summary = stage_and_install(&installer, &mut manifest).await.unwrap();
+        assert!(summary.contains("without a backup"), "{}", summary);
+        assert!(backups::list_backups(installer.backup_dir())
+            .unwrap()
+            .is_empty());
+        let state = StateFile::load(&installer.state_dir).unwrap();
+        assert!(state.get(installer.game_dir()).unwrap().no_backup);
+
+        let manifest = installer.load_pack().await.unwrap();
+        let error = installer.restore(manifest, None).await.unwrap_err();
+        assert!(matches!(error, AlienError::BackupMissing(_)));
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_file_grown_since_the_backup_is_reported_as_drift() {
+        let mut installer = fixture_installer("backup-drift", 4, 64);
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        let grown = PathBuf::from(original_name(2));
+        std::fs::write(
+            installer.game_dir().join(&grown),
+            "original 2, grown by a game update",
+        )
+        .unwrap();
+
+        let mut manifest = installer.load_pack().await.unwrap();
+        let filtered = manifest.filter_hans_dir(installer.prefix());
+        assert_eq!(installer.check_backup_drift(&filtered).unwrap(), [grown]);
+        installer.options.strict = true;
+        let error = AlienError::from(installer.check_backup_drift(&filtered).unwrap_err());
+        assert!(matches!(error, AlienError::BackupDrift { drifted: 1, .. }));
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn an_install_times_each_of_its_phases() {
+        let installer = fixture_installer("timings", 16, 4096);
+        let mut manifest = installer.load_pack().await.unwrap();
+        let mut timings = Timings::default();
+        installer.stage(&mut manifest, &mut timings).await.unwrap();
+        installer
+            .install(&mut manifest, &mut timings)
+            .await
+            .unwrap();
+
+        for (name, phase) in &timings.phases()[..] {
+            let expected = !matches!(*name, "read manifest" | "validate");
+            assert_eq!(phase.is_some(), expected, "{}", name);
+        }
+        let written = manifest
+            .filter_hans_dir(installer.prefix())
+            .into_iter()
+            .filter(|(item, _)| item.is_file)
+            .map(|(item, _)| item.size)
+            .sum::<u64>();
+        assert_eq!(timings.write.unwrap().bytes, written);
+        let originals = (0..16)
+            .map(|index| original(index).len() as u64)
+            .sum::<u64>();
+        assert_eq!(timings.backup_compress.unwrap().bytes, originals);
+        assert!(timings.table().contains("backup compress"));
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_patched_copy_leaves_the_game_untouched() {
+        let installer = fixture_installer("patch-output", 8, 256);
+        let scratch = installer.game_dir().parent().unwrap().to_path_buf();
+        let other = Path::new("data/other.txt");
+        std::fs::write(installer.game_dir().join(other), b"not in the pack").unwrap();
+        let mut manifest = installer.load_pack().await.unwrap();
+        let pack = manifest
+            .filter_hans_dir(installer.prefix())
+            .into_iter()
+            .filter(|(item, _)| item.is_file)
+            .map(|(item, striped)| (striped, item.bytes.clone()))
+            .collect::<Vec<_>>();
+
+        let zipped = scratch.join("patched.zip");
+        installer
+            .patch_output(&mut manifest, &zipped)
+            .await
+            .unwrap();
+        let mut archive = ZipArchive::new(std::fs::File::open(&zipped).unwrap()).unwrap();
+        let mut read_zipped = |relative: &Path| {
+            let mut bytes = Vec::new();
+            let name = relative.display().to_string();
+            archive
+                .by_name(&name)
+                .unwrap()
+                .read_to_end(&mut bytes)
+                .unwrap();
+            bytes
+        };
+        for (striped, bytes) in &pack {
+            assert_eq!(&read_zipped(striped), bytes);
+        }
+        assert_eq!(read_zipped(other), b"not in the pack");
+
+        let dir = scratch.join("patched");
+        installer.patch_output(&mut manifest, &dir).await.unwrap();
+        for (striped, bytes) in &pack {
+            assert_eq!(&std::fs::read(dir.join(striped)).unwrap(), bytes);
+        }
+        assert_eq!(std::fs::read(dir.join(other)).unwrap(), b"not in the pack");
+
+        for index in 0..8 {
+            let path = installer.game_dir().join(original_name(index));
+            assert_eq!(std::fs::read(&path).unwrap(), original(index));
+        }
+        assert!(backups::list_backups(installer.backup_dir())
+            .unwrap()
+            .is_empty());
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn paths_over_max_path_are_refused() {
+        let installer = fixture_installer("max-path", 4, 64);
+        let scratch = installer.game_dir().parent().unwrap().to_path_buf();
+        let options = installer.options.clone();
+        let pack = crate::bench::fixture(&options.language_prefix, 4, 64).unwrap();
+        let game_dir = |depth: usize| {
+            (0..depth).fold(scratch.join("game"), |dir, _| {
+                dir.join("AlienIsolationData")
+            })
+        };
+        for (depth, refused) in [(0, false), (16, true)] {
+            let installer = Installer::new(
+                game_dir(depth),
+                scratch.join("backups"),
+                PackSource::Memory(pack.clone().into()),
+            )
+            .with_options(options.clone());
+            let mut manifest = installer.load_pack().await.unwrap();
+            let filtered = manifest.filter_hans_dir(installer.prefix());
+            let result = installer.check_path_lengths(&filtered);
+            match result {
+                Err(error) if refused => assert!(matches!(
+                    AlienError::find(&error),
+                    Some(AlienError::PathsTooLong { max: 260, paths }) if paths.len() == 4
+                )),
+                Ok(()) if !refused => {}
+                result => panic!("depth {}: {:?}", depth, result),
+            }
+        }
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[tokio::test]
+    async fn each_missing_file_policy_installs_and_restores_as_documented() {
+        for (name, on_missing) in [
+            ("on-missing-error", OnMissing::Error),
+            ("on-missing-skip", OnMissing::Skip),
+            ("on-missing-empty", OnMissing::Empty),
+        ] {
+            let mut installer = fixture_installer(name, 8, 64);
+            installer.options.on_missing = on_missing;
+            let missing = installer.game_dir().join(original_name(2));
+            std::fs::remove_file(&missing).unwrap();
+            let mut manifest = installer.load_pack().await.unwrap();
+            let installed = stage_and_install(&installer, &mut manifest).await;
+            if on_missing == OnMissing::Error {
+                assert!(
+                    matches!(&installed, Err(AlienError::MissingFiles(paths)) if paths.len() == 1),
+                    "{:?}",
+                    installed
+                );
+                assert!(!missing.exists());
+                assert_eq!(
+                    std::fs::read(installer.game_dir().join(original_name(0))).unwrap(),
+                    original(0)
+                );
+                let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+                continue;
+            }
+            installed.unwrap();
+            assert!(missing.exists());
+
+            let manifest = installer.load_pack().await.unwrap();
+            installer.restore(manifest, None).await.unwrap();
+            match on_missing {
+                OnMissing::Skip => assert!(!missing.exists()),
+                _ => assert_eq!(std::fs::read(&missing).unwrap(), b""),
+            }
+            for index in (0..8).filter(|index| *index != 2) {
+                let path = installer.game_dir().join(original_name(index));
+                assert_eq!(std::fs::read(&path).unwrap(), original(index));
+            }
+            let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_pack_without_translatable_files_is_refused() {
+        let installer = fixture_installer("empty-pack", 4, 64);
+        // Every file lies outside the language dir, only its directory entry is inside
+        let mut pack = Cursor::new(Vec::new());
+        let mut archive = ZipWriter::new(&mut pack);
+        let options = SimpleFileOptions::default();
+        archive
+            .add_directory(installer.prefix().display().to_string(), options)
+            .unwrap();
+        archive.start_file("readme.txt", options).unwrap();
+        archive.write_all(b"outside the language dir").unwrap();
+        archive.finish().unwrap();
+        let installer = Installer::new(
+            installer.game_dir().to_path_buf(),
+            installer.backup_dir().to_path_buf(),
+            PackSource::Memory(pack.into_inner().into()),
+        )
+        .with_options(installer.options.clone());
+
+        let mut manifest = installer.load_pack().await.unwrap();
+        let error = stage_and_install(&installer, &mut manifest)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, AlienError::EmptyPack { prefix } if prefix == installer.prefix()),
+            "{:?}",
+            error
+        );
+        assert!(
+            error.to_string().contains("No translatable files"),
+            "{}",
+            error
+        );
+        assert!(backups::list_backups(installer.backup_dir())
+            .unwrap()
+            .is_empty());
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn only_files_over_the_max_file_size_are_skipped() {
+        let mut installer = fixture_installer("max-file-size", 4, 64);
+        installer.options.max_file_size = Some(100);
+        let under = installer.game_dir().join(original_name(1));
+        let over = installer.game_dir().join(original_name(2));
+        std::fs::write(&under, vec![b'u'; 100]).unwrap();
+        std::fs::write(&over, vec![b'o'; 101]).unwrap();
+        let mut manifest = installer.load_pack().await.unwrap();
+        let summary = stage_and_install(&installer, &mut manifest).await.unwrap();
+        assert!(
+            summary.ends_with(", skipped 1 files over --max-file-size"),
+            "{}",
+            summary
+        );
+
+        assert_eq!(std::fs::read(&over).unwrap(), vec![b'o'; 101]);
+        for (item, striped) in manifest.filter_hans_dir(installer.prefix()) {
+            if item.is_file && striped != Path::new(&original_name(2)) {
+                let installed = std::fs::read(installer.game_dir().join(striped)).unwrap();
+                assert_eq!(installed, item.bytes);
+            }
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_missing_white_listed_dir_only_warns_when_tolerated() {
+        let dlc = "data/env/production/dlc/salvagemode1/text/english";
+        for tolerate in [false, true] {
+            let scratch = scratch_dir(&format!("missing-dir-{}", tolerate));
+            let options = Options {
+                safe_mode: false,
+                assume_yes: true,
+                tolerate_missing_dirs: tolerate,
+                ..Options::default()
+            };
+            let prefix = options.language_prefix.display().to_string();
+            let mut pack = Cursor::new(Vec::new());
+            let mut archive = ZipWriter::new(&mut pack);
+            let zip_options = SimpleFileOptions::default();
+            archive
+                .add_directory(format!("{}/{}/", prefix, dlc), zip_options)
+                .unwrap();
+            for name in [
+                format!("{}/global.txt", dlc),
+                "data/ui/menu.txt".to_string(),
+            ] {
+                archive
+                    .start_file(format!("{}/{}", prefix, name), zip_options)
+                    .unwrap();
+                archive.write_all(b"translated").unwrap();
+            }
+            archive.finish().unwrap();
+            let menu = scratch.join("game/data/ui/menu.txt");
+            std::fs::create_dir_all(menu.parent().unwrap()).unwrap();
+            std::fs::write(&menu, b"english").unwrap();
+            let installer = Installer::new(
+                scratch.join("game"),
+                scratch.join("backups"),
+                PackSource::Memory(pack.into_inner().into()),
+            )
+            .with_options(options);
+
+            let mut manifest = installer.load_pack().await.unwrap();
+            let installed = stage_and_install(&installer, &mut manifest).await;
+            if tolerate {
+                installed.unwrap();
+                assert_eq!(std::fs::read(&menu).unwrap(), b"translated");
+            } else {
+                match &installed {
+                    Err(AlienError::MissingFiles(paths)) => {
+                        assert_eq!(paths, &[PathBuf::from(dlc)])
+                    }
+                    installed => panic!("{:?}", installed),
+                }
+                assert_eq!(std::fs::read(&menu).unwrap(), b"english");
+            }
+            let _ = std::fs::remove_dir_all(&scratch);
+        }
+    }
+
+    /// Every reporter call in the order the install made it
+    #[derive(Default)]
+    struct Events(Mutex<Vec<ProgressEvent>>);
+
+    impl ProgressReporter for Events {
+        fn on_phase_start(&self, phase: &'static str, files: usize) {
+            let event = ProgressEvent::PhaseStart { phase, files };
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_file_done(&self, phase: &'static str, path: &Path, bytes: u64) {
+            let path = path.to_path_buf();
+            let event = ProgressEvent::FileDone { phase, path, bytes };
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_phase_end(&self, phase: &'static str, stats: &Phase) {
+            let event = ProgressEvent::PhaseEnd {
+                phase,
+                stats: *stats,
+            };
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_backup_completes_before_the_first_write() {
+        let events = Arc::new(Events::default());
+        let mut installer = fixture_installer("event-order", 32, 64).with_reporter(events.clone());
+        installer.options.jobs = 4;
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+
+        let events = events.0.lock().unwrap();
+        let files_done = |wanted: &str| {
+            events
+                .iter()
+                .enumerate()
+                .filter(|(_, event)| {
+                    matches!(event, ProgressEvent::FileDone { phase, .. } if *phase == wanted)
+                })
+                .map(|(index, _)| index)
+                .collect::<Vec<_>>()
+        };
+        let backup_end = events
+            .iter()
+            .position(|event| {
+                matches!(
+                    event,
+                    ProgressEvent::PhaseEnd {
+                        phase: "backup",
+                        ..
+                    }
+                )
+            })
+            .unwrap();
+        let backed_up = files_done("backup");
+        let written = files_done("write");
+        assert_eq!(backed_up.len(), 32);
+        assert_eq!(written.len(), 32);
+        assert!(backed_up.iter().all(|index| *index < backup_end));
+        assert!(written.iter().all(|index| *index > backup_end));
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+}
@@ -0,0 +1,1051 @@
+use crate::backups::{self, NoBackupError, DEFAULT_CHUNK_SIZE};
+use crate::casing::GameTree;
+use crate::chunked::{Cancel, DEFAULT_IO_BUFFER_SIZE};
+use crate::config::BackupConfig;
+use crate::error::AlienError;
+use crate::manifest::{self, Deferred, Manifest, ManifestItem, DEFAULT_STREAM_THRESHOLD};
+use crate::progress::{NoProgress, ProgressReporter};
+use crate::report::ValidationReport;
+use crate::state::HashCache;
+use crate::tasks::DEFAULT_IO_LIMIT;
+use crate::{
+    chunked, descriptors, durability, hashing, languages, path_structure, permissions, symlinks,
+    tasks,
+};
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{BufReader, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::io::AsyncWriteExt;
+
+/// What the backup does with a pack file missing from the game that is not in the white list
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OnMissing {
+    /// Refuse to install
+    Error,
+    /// Record it as created, the restore deletes it again
+    Skip,
+    /// Back up an empty file, the restore leaves an empty file in its place
+    Empty,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum BackupMode {
+    Zip,
+    /// Mirror tree of clonefile(2) or reflink copies, plain copies across filesystems
+    #[value(alias = "clone")]
+    Clonefile,
+}
+
+/// Where the language pack is read from
+#[derive(Debug, Clone)]
+pub enum PackSource {
+    /// The pack built into the binary
+    Embedded,
+    /// An external pack zip, refused unless its `<pack>.sig` checks out. The signature is
+    /// checked on `copy` and everything is read from it, `path` is never opened again.
+    Signed {
+        path: PathBuf,
+        allow_unsigned: bool,
+        copy: PrivateCopy,
+    },
+    /// Loose files laid out like the zip (`language/...`)
+    Dir(PathBuf),
+    /// A pack zip read as is, such as one passed to `extract`
+    Zip(PathBuf),
+    /// A pack zip already in memory, such as one generated for a benchmark or a test
+    Memory(Arc<[u8]>),
+}
+
+impl PackSource {
+    pub fn describe(&self) -> String {
+        match self {
+            PackSource::Embedded => {
+                format!("embedded, sha256 {}", path_structure::LANGUAGE_ZIP_SHA256)
+            }
+            PackSource::Signed { path, .. } => format!("[{}]", path.display()),
+            PackSource::Dir(dir) => format!("directory [{}]", dir.display()),
+            PackSource::Zip(path) => format!("[{}], unchecked", path.display()),
+            PackSource::Memory(bytes) => format!("in memory, {} bytes", bytes.len()),
+        }
+    }
+
+    /// Reads the pack, leaving entries over `stream_threshold` bytes in it
+    fn read(&self, options: &Options, stream_threshold: u64) -> Result<Manifest> {
+        let prefix = options.language_prefix.as_path();
+        match self {
+            PackSource::Embedded => Manifest::read_from_language_zip(prefix, stream_threshold),
+            PackSource::Signed {
+                path,
+                allow_unsigned,
+                copy,
+            } => Manifest::read_from_signed_pack(
+                path,
+                copy.of(path)?,
+                *allow_unsigned,
+                &options.trusted_keys,
+                prefix,
+                stream_threshold,
+            ),
+            PackSource::Dir(dir) => Manifest::from_dir(dir, prefix, stream_threshold),
+            PackSource::Zip(path) => {
+                Manifest::read_from_language_path(path, prefix, stream_threshold)
+            }
+            PackSource::Memory(bytes) => Manifest::read_from_bytes(bytes, prefix, stream_threshold),
+        }
+    }
+
+    /// Hands the bytes of an entry that was left out of memory to `read`, from the private copy
+    /// of a signed pack. The caller checks the CRC32 of what it read against the manifest.
+    pub(crate) fn read_deferred<R>(
+        &self,
+        deferred: &Deferred,
+        read: impl FnOnce(&mut dyn Read) -> Result<R>,
+    ) -> Result<R> {
+        let index = match deferred {
+            Deferred::File(path) => {
+                let mut file = std::fs::File::open(path)
+                    .with_context(|| format!("Failed to open [{}]", path.display()))?;
+                return read(&mut file);
+            }
+            Deferred::Entry(index) => *index,
+        };
+        match self {
+            PackSource::Embedded => {
+                manifest::read_entry(Cursor::new(path_structure::LANGUAGE_ZIP_DATA), index, read)
+            }
+            PackSource::Signed { path, copy, .. } => {
+                let copy = copy
+                    .path()
+                    .ok_or_else(|| eyre!("[{}] was not verified before reading", path.display()))?;
+                let file = std::fs::File::open(copy)
+                    .with_context(|| format!("Failed to open [{}]", copy.display()))?;
+                manifest::read_entry(BufReader::new(file), index, read)
+            }
+            PackSource::Zip(path) => {
+                let file = std::fs::File::open(path)
+                    .with_context(|| format!("Failed to open [{}]", path.display()))?;
+                manifest::read_entry(BufReader::new(file), index, read)
+            }
+            PackSource::Memory(bytes) => {
+                manifest::read_entry(Cursor::new(bytes.clone()), index, read)
+            }
+            PackSource::Dir(_) => unreachable!("directory packs defer files, not zip entries"),
+        }
+    }
+}
+
+/// Copy of a signed pack that only this process writes, so the bytes whose signature was checked
+/// are the bytes that get installed. Clones share it, the last one removes the file.
+#[derive(Debug, Clone, Default)]
+pub struct PrivateCopy(Arc<OnceLock<PrivateFile>>);
+
+#[derive(Debug)]
+struct PrivateFile(PathBuf);
+
+impl Drop for PrivateFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl PrivateCopy {
+    /// Copies `pack` to a new file readable only by the user on first use
+    fn of(&self, pack: &Path) -> Result<&Path> {
+        static COPIES: AtomicUsize = AtomicUsize::new(0);
+        if let Some(copy) = self.0.get() {
+            return Ok(&copy.0);
+        }
+        let copy = PrivateFile(std::env::temp_dir().join(format!(
+            "alien-pack-{}-{}.zip",
+            std::process::id(),
+            COPIES.fetch_add(1, Ordering::Relaxed)
+        )));
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(&copy.0)
+            .with_context(|| format!("Failed to create [{}]", copy.0.display()))?;
+        let mut source = std::fs::File::open(pack)
+            .with_context(|| format!("Failed to open [{}]", pack.display()))?;
+        std::io::copy(&mut source, &mut file)
+            .with_context(|| format!("Failed to copy [{}]", pack.display()))?;
+        // A copy made concurrently by another clone is dropped, and removed, here
+        let _ = self.0.set(copy);
+        Ok(&self.0.get().expect("set above").0)
+    }
+
+    fn path(&self) -> Option<&Path> {
+        self.0.get().map(|copy| copy.0.as_path())
+    }
+}
+
+/// Everything that changes how an [Installer] backs up, writes and restores, the CLI fills it
+/// from its flags and the config file
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Language dir of the pack to install, see [languages::LANGUAGES]
+    pub language_prefix: PathBuf,
+    /// Files hashed and compressed at once
+    pub jobs: usize,
+    /// Files written at once
+    pub io_limit: usize,
+    /// Stop all remaining writes at the first failure instead of finishing them
+    pub fail_fast: bool,
+    /// Sync every written file and its directory to disk
+    pub durable: bool,
+    /// Read every installed file back and roll back when it differs from the pack
+    pub verify_writes: bool,
+    /// Modify game files reached through a symlink instead of refusing
+    pub follow_symlinks: bool,
+    /// Clear the read-only flag of game files and retry instead of failing
+    pub fix_permissions: bool,
+    /// Carry file modification times through backup and restore
+    pub preserve_timestamps: bool,
+    /// Carry Unix permission bits through backup and restore
+    pub preserve_permissions: bool,
+    /// Files larger than this many bytes are backed up in chunks of this size
+    pub chunk_size: usize,
+    /// Pack entries larger than this many bytes stay in the pack instead of in memory and are
+    /// streamed to the game when written
+    pub stream_threshold: u64,
+    /// Bytes per write when installing, restoring and writing backups
+    pub io_buffer_size: usize,
+    /// Extensions stored without compression, [backups::STORED_EXTENSIONS] when `None`
+    pub stored_extensions: Option<Vec<String>>,
+    /// Hex encoded ed25519 public keys trusted to sign external packs, besides the built-in ones
+    pub trusted_keys: Vec<String>,
+    pub backup_mode: BackupMode,
+    pub retention: BackupConfig,
+    /// Encrypt backup entries with a passphrase
+    pub encrypt: bool,
+    pub on_missing: OnMissing,
+    /// Warn about and skip missing pack directories that hold only optional files or none
+    pub tolerate_missing_dirs: bool,
+    /// Skip backing up and patching files larger than this many bytes
+    pub max_file_size: Option<u64>,
+    /// Skip the backup entirely
+    pub no_backup: bool,
+    /// Leave files written before an install failure in place instead of rolling back
+    pub no_rollback: bool,
+    /// Discard an interrupted install and start over, reusing its backup
+    pub restart: bool,
+    /// Patch over files that another mod changed instead of refusing
+    pub overwrite_foreign: bool,
+    /// Also overwrite files that something other than alien changed since the install
+    pub overwrite_modified: bool,
+    /// Fail instead of warning when the game files drifted from the existing backup
+    pub strict: bool,
+    /// Restore even when the game already looks English
+    pub force: bool,
+    /// Hash every file instead of trusting the hash cache
+    pub no_cache: bool,
+    /// Refuse game dirs that lack the game's DATA/UI and DATA/ENV directories
+    pub safe_mode: bool,
+    /// Answer yes to every confirmation
+    pub assume_yes: bool,
+    /// Game dir the install was recorded for, to restore after moving the game elsewhere
+    pub relative_to: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            language_prefix: languages::LANGUAGES[0].prefix().to_path_buf(),
+            jobs: std::thread::available_parallelism()
+                .map(usize::from)
+                .unwrap_or(1),
+            io_limit: DEFAULT_IO_LIMIT,
+            fail_fast: false,
+            durable: false,
+            verify_writes: false,
+            follow_symlinks: true,
+            fix_permissions: true,
+            preserve_timestamps: true,
+            preserve_permissions: true,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            stream_threshold: DEFAULT_STREAM_THRESHOLD,
+            io_buffer_size: DEFAULT_IO_BUFFER_SIZE,
+            stored_extensions: None,
+            trusted_keys: Vec::new(),
+            backup_mode: BackupMode::Zip,
+            retention: BackupConfig::default(),
+            encrypt: false,
+            on_missing: OnMissing::Error,
+            tolerate_missing_dirs: false,
+            max_file_size: None,
+            no_backup: false,
+            no_rollback: false,
+            restart: false,
+            overwrite_foreign: false,
+            overwrite_modified: false,
+            strict: false,
+            force: false,
+            no_cache: false,
+            safe_mode: true,
+            assume_yes: false,
+            relative_to: None,
+        }
+    }
+}
+
+/// Answers a yes/no question before a destructive step
+pub type Prompt = dyn Fn(&str) -> bool + Send + Sync;
+
+/// Installs a language pack into one game dir and restores it, keeping backups in `backup_dir`
+/// and the install state, hash cache and progress journal in `state_dir`
+pub struct Installer {
+    pub(crate) game_dir: PathBuf,
+    pub(crate) backup_dir: PathBuf,
+    pub(crate) state_dir: PathBuf,
+    pub(crate) pack: PackSource,
+    pub(crate) options: Options,
+    pub(crate) selection: Option<HashSet<PathBuf>>,
+    pub(crate) reporter: Arc<dyn ProgressReporter>,
+    prompt: Option<Arc<Prompt>>,
+    pub(crate) cancel: Cancel,
+    tree: OnceLock<GameTree>,
+}
+
+impl Installer {
+    pub fn new(game_dir: PathBuf, backup_dir: PathBuf, pack: PackSource) -> Self {
+        Self {
+            state_dir: backup_dir.clone(),
+            game_dir,
+            backup_dir,
+            pack,
+            options: Options::default(),
+            selection: None,
+            reporter: Arc::new(NoProgress),
+            prompt: None,
+            cancel: Cancel::default(),
+            tree: OnceLock::new(),
+        }
+    }
+
+    /// Keeps the install state, hash cache and progress journal here instead of the backup dir
+    pub fn with_state_dir(mut self, state_dir: PathBuf) -> Self {
+        self.state_dir = state_dir;
+        self
+    }
+
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_reporter(mut self, reporter: Arc<dyn ProgressReporter>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    /// Asks before destructive steps, without a prompt they fail unless `assume_yes`
+    pub fn with_prompt(mut self, prompt: Arc<Prompt>) -> Self {
+        self.prompt = Some(prompt);
+        self
+    }
+
+    pub fn game_dir(&self) -> &Path {
+        &self.game_dir
+    }
+
+    pub fn backup_dir(&self) -> &Path {
+        &self.backup_dir
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Cancelling it stops every chunked write at its next chunk
+    pub fn cancel_token(&self) -> Cancel {
+        self.cancel.clone()
+    }
+
+    pub(crate) fn prefix(&self) -> &Path {
+        &self.options.language_prefix
+    }
+
+    /// Reads the language pack without looking at the game, entries over
+    /// `options.stream_threshold` are left in it
+    pub async fn read_pack(&self) -> Result<Manifest, AlienError> {
+        self.read_pack_from(&self.pack, self.options.stream_threshold)
+            .await
+    }
+
+    /// Reads the language pack with every entry in memory, for rewriting it whole
+    pub async fn read_pack_whole(&self) -> Result<Manifest, AlienError> {
+        self.read_pack_from(&self.pack, u64::MAX).await
+    }
+
+    pub(crate) async fn read_pack_from(
+        &self,
+        pack: &PackSource,
+        stream_threshold: u64,
+    ) -> Result<Manifest, AlienError> {
+        let pack = pack.clone();
+        let options = self.options.clone();
+        let manifest = tasks::blocking("read the language pack", move || {
+            pack.read(&options, stream_threshold)
+        })
+        .await?;
+        Ok(manifest)
+    }
+
+    /// The bytes of a pack entry, read back from the pack when it was left out of memory
+    pub(crate) async fn item_bytes<'a>(&self, item: &'a ManifestItem) -> Result<Cow<'a, [u8]>> {
+        let deferred = match &item.deferred {
+            Some(deferred) => deferred.clone(),
+            None => return Ok(Cow::Borrowed(&item.bytes)),
+        };
+        let permit = descriptors::acquire().await?;
+        let pack = self.pack.clone();
+        let bytes = tasks::blocking("read a large pack entry", move || {
+            let _permit = permit;
+            pack.read_deferred(&deferred, |reader| {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            })
+        })
+        .await?;
+        Ok(Cow::Owned(bytes))
+    }
+
+    /// SHA-256 of a pack entry, hashed as it is read back from the pack when it was left out of
+    /// memory
+    pub(crate) async fn item_hash(&self, item: &ManifestItem) -> Result<[u8; 32]> {
+        let deferred = match &item.deferred {
+            Some(deferred) => deferred.clone(),
+            None => return Ok(hashing::hash_bytes(&item.bytes)),
+        };
+        let permit = descriptors::acquire().await?;
+        let pack = self.pack.clone();
+        tasks::blocking("hash a large pack entry", move || {
+            let _permit = permit;
+            pack.read_deferred(&deferred, |reader| Ok(hashing::hash_reader(reader)?))
+        })
+        .await
+    }
+
+    /// Reads the language pack and scans the game dirs it writes into for their casing on disk
+    pub async fn load_pack(&self) -> Result<Manifest, AlienError> {
+        let manifest = self.read_pack().await?;
+        if self.tree.get().is_none() {
+            let _ = self.tree.set(GameTree::scan(
+                &self.game_dir,
+                &manifest,
+                &self.options.language_prefix,
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// Path of a manifest entry inside the game dir, a plain join before [Installer::load_pack]
+    pub(crate) fn game_path(&self, striped: &Path) -> PathBuf {
+        match self.tree.get() {
+            Some(tree) => tree.resolve(striped),
+            None => self.game_dir.join(striped),
+        }
+    }
+
+    pub(crate) fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        symlinks::resolve(&self.game_dir, path, self.options.follow_symlinks)
+    }
+
+    /// Like [Installer::resolve] for a path to remove, a symlinked file is removed itself
+    pub(crate) fn resolve_link(&self, path: &Path) -> Result<PathBuf> {
+        symlinks::resolve_link(&self.game_dir, path, self.options.follow_symlinks)
+    }
+
+    pub(crate) async fn join_all<T, F>(
+        &self,
+        futures: impl IntoIterator<Item = F>,
+    ) -> Vec<Result<T>>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        tasks::join_all(futures, self.options.io_limit, self.options.fail_fast).await
+    }
+
+    /// Refuses a game dir that lacks the game's DATA/UI and DATA/ENV, unless safe mode is off
+    pub fn check(&self) -> Result<(), AlienError> {
+        if !self.options.safe_mode || path_structure::looks_like_game_dir(&self.game_dir) {
+            return Ok(());
+        }
+        Err(AlienError::NotAGameDir {
+            dir: self.game_dir.clone(),
+        })
+    }
+
+    /// Removes what an interrupted run left behind, partial backups and temporary files
+    pub fn clean_up(&self, pack: &Manifest) -> Result<(), AlienError> {
+        backups::clean_partials(&self.backup_dir)?;
+        Ok(self.clean_stray_temporaries(pack)?)
+    }
+
+    pub(crate) async fn read_backup(&self, backup_zip: Option<&Path>) -> Result<Manifest> {
+        let backup_zip = backup_zip.map(Path::to_path_buf);
+        let backup_dir = self.backup_dir.clone();
+        tasks::blocking("read the backup", move || match backup_zip {
+            Some(backup_zip) => Manifest::read_from_backup_path(&backup_zip),
+            None => Manifest::read_from_backup_dir(&backup_dir),
+        })
+        .await
+    }
+
+    /// The default backup or `from`, indexed for a streamed restore
+    pub(crate) async fn index_backup(&self, from: Option<&Path>) -> Result<(PathBuf, Manifest)> {
+        let from = from.map(Path::to_path_buf);
+        let backup_dir = self.backup_dir.clone();
+        tasks::blocking("index the backup", move || {
+            let backup = match from {
+                Some(from) => from,
+                None => backups::default_backup(&backup_dir)?.ok_or(AlienError::from(
+                    NoBackupError {
+                        location: backup_dir.clone(),
+                    },
+                ))?,
+            };
+            let manifest = Manifest::index_backup(&backup)?;
+            Ok((backup, manifest))
+        })
+        .await
+    }
+
+    pub(crate) fn confirm(&self, question: &str) -> Result<()> {
+        if self.options.assume_yes {
+            return Ok(());
+        }
+        match &self.prompt {
+            Some(prompt) if prompt(question) => Ok(()),
+            Some(_) => Err(AlienError::Aborted.into()),
+            None => Err(AlienError::ConfirmationRequired {
+                question: question.to_string(),
+            }
+            .into()),
+        }
+    }
+
+    /// Restricts patch, backup and restore to the relative paths listed in `list`, one per line.
+    /// Paths the pack lacks are an error unless `ignore_missing`.
+    pub fn select_files(
+        &mut self,
+        list: &str,
+        pack: &Manifest,
+        ignore_missing: bool,
+    ) -> Result<(), AlienError> {
+        let known = pack
+            .hans_plan(self.prefix())
+            .into_iter()
+            .map(|(_, striped)| striped)
+            .collect::<HashSet<_>>();
+        let mut selection = HashSet::new();
+        let mut unknown = Vec::new();
+        for line in list.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let striped = manifest::path_key(Path::new(line));
+            if known.contains(&striped) {
+                selection.insert(striped);
+            } else if ignore_missing {
+                tracing::warn!("Ignoring [{}], it is not in the language pack", line);
+            } else {
+                unknown.push(PathBuf::from(line));
+            }
+        }
+        if !unknown.is_empty() {
+            return Err(AlienError::NotInPack(unknown));
+        }
+        tracing::info!("Selected {} files from the file list", selection.len());
+        self.selection = Some(selection);
+        Ok(())
+    }
+
+    pub(crate) fn is_selected(&self, striped: &Path) -> bool {
+        match &self.selection {
+            Some(selection) => selection.contains(striped),
+            None => true,
+        }
+    }
+
+    pub(crate) async fn validate_cached(&self, manifest: &Manifest) -> Result<ValidationReport> {
+        let resolve = |striped: &Path| self.game_path(striped);
+        if self.options.no_cache {
+            return manifest
+                .validate_against_disk(self.prefix(), &resolve, self.options.jobs, None)
+                .await;
+        }
+        let mut cache = HashCache::load(&self.state_dir, &self.game_dir, &manifest.version());
+        let report = manifest
+            .validate_against_disk(self.prefix(), &resolve, self.options.jobs, Some(&mut cache))
+            .await?;
+        cache.save(&self.state_dir)?;
+        Ok(report)
+    }
+
+    /// Caches the CRC32 of files that were just written with the content of pack `pack_version`
+    pub(crate) fn remember_hashes<'a>(
+        &self,
+        pack_version: &str,
+        files: impl Iterator<Item = (&'a PathBuf, u32)>,
+    ) {
+        let mut cache = HashCache::load(&self.state_dir, &self.game_dir, pack_version);
+        for (striped, crc32) in files {
+            let metadata = match std::fs::metadata(self.game_path(striped)) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if let Ok(modified) = metadata.modified() {
+                cache.insert(striped.clone(), metadata.len(), modified, crc32);
+            }
+        }
+        if let Err(error) = cache.save(&self.state_dir) {
+            tracing::warn!("Failed to update the hash cache: {:#}", error);
+        }
+    }
+
+    pub(crate) async fn write_file(&self, item: &ManifestItem, path: PathBuf) -> Result<()> {
+        self.write_from(&self.pack, item, path, &self.cancel).await
+    }
+
+    /// Like [Installer::write_file], but a cancel does not stop it, for rolling back after one
+    pub(crate) async fn write_original(&self, item: &ManifestItem, path: PathBuf) -> Result<()> {
+        self.write_from(&self.pack, item, path, &Cancel::default())
+            .await
+    }
+
+    /// Writes `item` of `pack` to `path` through a temporary file, streaming it from `pack` when
+    /// it was left out of memory, stopping at the next chunk once `cancel` is set
+    pub(crate) async fn write_from(
+        &self,
+        pack: &PackSource,
+        item: &ManifestItem,
+        path: PathBuf,
+        cancel: &Cancel,
+    ) -> Result<()> {
+        let path = self.resolve(&path)?;
+        if item.is_file {
+            let parent = path
+                .parent()
+                .ok_or_else(|| AlienError::NoParent { path: path.clone() })?;
+            if !matches!(tokio::fs::try_exists(parent).await, Ok(true)) {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .with_context(|| format!("Failed to create [{}]", parent.display()))?;
+            }
+            let temporary = temporary_path(&path);
+            let written = match &item.deferred {
+                Some(deferred) => {
+                    self.stream_entry(pack, deferred, item, &temporary, cancel)
+                        .await
+                }
+                None => self.write_temporary(&temporary, &item.bytes, cancel).await,
+            };
+            if let Err(error) = written {
+                let _ = tokio::fs::remove_file(&temporary).await;
+                return Err(error)
+                    .with_context(|| format!("Failed to write [{}]", temporary.display()));
+            }
+            permissions::retry_writable(&path, true, self.options.fix_permissions, || {
+                tokio::fs::rename(&temporary, &path)
+            })
+            .await
+            .map_err(|source| AlienError::WriteFailed {
+                path: path.clone(),
+                source,
+            })?;
+            durability::sync_parent(&path, self.options.durable)
+                .await
+                .with_context(|| format!("Failed to sync [{}]", parent.display()))?;
+        } else if !matches!(tokio::fs::try_exists(&path).await, Ok(true)) {
+            tokio::fs::create_dir_all(&path)
+                .await
+                .map_err(|source| AlienError::WriteFailed {
+                    path: path.clone(),
+                    source,
+                })?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn write_temporary(
+        &self,
+        path: &Path,
+        bytes: &[u8],
+        cancel: &Cancel,
+    ) -> Result<()> {
+        let _permit = descriptors::acquire().await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await?;
+        chunked::write_all(&mut file, bytes, path, self.options.io_buffer_size, cancel).await?;
+        file.flush().await?;
+        if self.options.durable {
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    /// Copies a deferred entry of `pack` to `path` on the blocking pool, a buffer at a time,
+    /// and checks what was copied against the entry's size and CRC32
+    pub(crate) async fn stream_entry(
+        &self,
+        pack: &PackSource,
+        deferred: &Deferred,
+        item: &ManifestItem,
+        path: &Path,
+        cancel: &Cancel,
+    ) -> Result<()> {
+        let permit = descriptors::acquire_many(2).await?;
+        let pack = pack.clone();
+        let deferred = deferred.clone();
+        let entry = item.path.clone();
+        let path = path.to_path_buf();
+        let (size, crc32) = (item.size, item.crc32);
+        let buffer_size = self.options.io_buffer_size;
+        let durable = self.options.durable;
+        let cancel = cancel.clone();
+        tasks::blocking("stream a pack entry", move || {
+            let _permit = permit;
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)?;
+            let (copied, copied_crc32) = pack.read_deferred(&deferred, |reader| {
+                chunked::copy(reader, &mut file, &path, size, buffer_size, &cancel)
+            })?;
+            if copied != size || copied_crc32 != crc32 {
+                return Err(AlienError::PackCorrupt {
+                    entry,
+                    source: std::io::ErrorKind::InvalidData.into(),
+                }
+                .into());
+            }
+            file.flush()?;
+            if durable {
+                file.sync_all()?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    pub(crate) fn clean_stray_temporaries(&self, manifest: &Manifest) -> Result<()> {
+        for (_, striped) in manifest.hans_plan(self.prefix()) {
+            let temporary = temporary_path(&self.game_path(&striped));
+            if temporary.exists() {
+                tracing::warn!("Removing stray temporary file [{}]", temporary.display());
+                std::fs::remove_file(&temporary)
+                    .with_context(|| format!("Failed to remove [{}]", temporary.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".alien-tmp");
+    path.with_file_name(file_name)
+}
+
+pub(crate) fn restore_modified_time(item: &ManifestItem, path: &Path) -> Result<()> {
+    if let (true, Some(modified)) = (item.is_file, item.modified) {
+        filetime::set_file_mtime(path, filetime::FileTime::from_system_time(modified))
+            .with_context(|| format!("Failed to restore mtime of [{}]", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::report::{Phase, Timings};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    /// Empty scratch dir below the temp dir, one per test and process
+    pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("alien-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Installer over a scratch game dir that holds an original of every file of a generated
+    /// pack of `files` files of `size` bytes
+    pub(crate) fn fixture_installer(name: &str, files: usize, size: usize) -> Installer {
+        let scratch = scratch_dir(name);
+        let game_dir = scratch.join("game");
+        let options = Options {
+            safe_mode: false,
+            assume_yes: true,
+            ..Options::default()
+        };
+        let pack = crate::bench::fixture(&options.language_prefix, files, size).unwrap();
+        for index in 0..files {
+            let path = game_dir.join(original_name(index));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, original(index)).unwrap();
+        }
+        Installer::new(
+            game_dir,
+            scratch.join("backups"),
+            PackSource::Memory(pack.into()),
+        )
+        .with_options(options)
+    }
+
+    /// Stages the pack and commits it, the way `alien zh` runs the two steps
+    pub(crate) async fn stage_and_install(
+        installer: &Installer,
+        manifest: &mut Manifest,
+    ) -> Result<String, AlienError> {
+        let mut timings = Timings::default();
+        installer.stage(manifest, &mut timings).await?;
+        installer.install(manifest, &mut timings).await
+    }
+
+    /// Reporter that cancels the installer once `files` files of `phase` are done, or at the
+    /// end of `phase` without a count, like a Ctrl-C at that point
+    struct CancelAfter {
+        cancel: Cancel,
+        phase: &'static str,
+        files: Option<usize>,
+        done: AtomicUsize,
+    }
+
+    impl ProgressReporter for CancelAfter {
+        fn on_file_done(&self, phase: &'static str, _path: &Path, _bytes: u64) {
+            if phase == self.phase
+                && Some(self.done.fetch_add(1, Ordering::Relaxed) + 1) == self.files
+            {
+                self.cancel.cancel();
+            }
+        }
+
+        fn on_phase_end(&self, phase: &'static str, _stats: &Phase) {
+            if phase == self.phase && self.files.is_none() {
+                self.cancel.cancel();
+            }
+        }
+    }
+
+    pub(crate) fn cancel_after(
+        installer: Installer,
+        phase: &'static str,
+        files: Option<usize>,
+    ) -> Installer {
+        let reporter = CancelAfter {
+            cancel: installer.cancel_token(),
+            phase,
+            files,
+            done: AtomicUsize::new(0),
+        };
+        installer.with_reporter(Arc::new(reporter))
+    }
+
+    pub(crate) fn original_name(index: usize) -> String {
+        format!("data/bench/{:06}.txt", index)
+    }
+
+    pub(crate) fn original(index: usize) -> Vec<u8> {
+        format!("original {}", index).into_bytes()
+    }
+
+    #[tokio::test]
+    async fn install_then_restore_round_trips() {
+        let installer = fixture_installer("round-trip", 8, 4096);
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        for (item, striped) in manifest.filter_hans_dir(installer.prefix()) {
+            if item.is_file {
+                let installed = std::fs::read(installer.game_dir().join(striped)).unwrap();
+                assert_eq!(installed, item.bytes[..]);
+            }
+        }
+
+        let manifest = installer.load_pack().await.unwrap();
+        installer.restore(manifest, None).await.unwrap();
+        for index in 0..8 {
+            let restored = std::fs::read(installer.game_dir().join(original_name(index))).unwrap();
+            assert_eq!(restored, original(index));
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn streams_entries_over_the_threshold() {
+        const SIZE: u64 = 100 * 1024 * 1024;
+        let scratch = scratch_dir("stream-threshold");
+        let options = Options {
+            safe_mode: false,
+            assume_yes: true,
+            ..Options::default()
+        };
+        let striped = Path::new("data/movies/large.bik");
+        let game_path = scratch.join("game").join(striped);
+        std::fs::create_dir_all(game_path.parent().unwrap()).unwrap();
+        std::fs::write(&game_path, original(0)).unwrap();
+        let pack = scratch.join("pack.zip");
+        let mut archive = ZipWriter::new(std::fs::File::create(&pack).unwrap());
+        let name = options.language_prefix.join(striped);
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        archive
+            .start_file(name.display().to_string(), stored)
+            .unwrap();
+        let chunk = (0..1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        for _ in 0..SIZE / chunk.len() as u64 {
+            archive.write_all(&chunk).unwrap();
+        }
+        archive.finish().unwrap();
+        let installer = Installer::new(
+            scratch.join("game"),
+            scratch.join("backups"),
+            PackSource::Zip(pack),
+        )
+        .with_options(options);
+
+        let mut manifest = installer.load_pack().await.unwrap();
+        let item = manifest.iter().find(|item| item.is_file).unwrap();
+        assert_eq!(item.size, SIZE);
+        assert!(item.bytes.is_empty());
+        assert!(item.deferred.is_some());
+        let crc32 = item.crc32;
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        let installed = std::fs::File::open(&game_path).unwrap();
+        assert_eq!(installed.metadata().unwrap().len(), SIZE);
+        assert_eq!(manifest::crc32_reader(installed).unwrap(), crc32);
+
+        let manifest = installer.load_pack().await.unwrap();
+        installer.restore(manifest, None).await.unwrap();
+        assert_eq!(std::fs::read(&game_path).unwrap(), original(0));
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[tokio::test]
+    async fn failed_write_leaves_the_original() {
+        let scratch = scratch_dir("failed-write");
+        let options = Options {
+            safe_mode: false,
+            stream_threshold: 0,
+            io_buffer_size: 4096,
+            ..Options::default()
+        };
+        let name = options.language_prefix.join(original_name(0));
+        let bytes = vec![b'x'; 64 * 1024];
+        let mut pack = Cursor::new(Vec::new());
+        let mut archive = ZipWriter::new(&mut pack);
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        archive
+            .start_file(name.display().to_string(), stored)
+            .unwrap();
+        archive.write_all(&bytes).unwrap();
+        archive.finish().unwrap();
+        let mut pack = pack.into_inner();
+        // Near the end of the stored data, the CRC32 check fails after most of it is written
+        let data = pack
+            .windows(bytes.len())
+            .position(|window| window == bytes)
+            .unwrap();
+        pack[data + bytes.len() - 10] = b'y';
+        let game_dir = scratch.join("game");
+        let path = game_dir.join(original_name(0));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, original(0)).unwrap();
+        let installer = Installer::new(
+            game_dir,
+            scratch.join("backups"),
+            PackSource::Memory(pack.into()),
+        )
+        .with_options(options);
+
+        let manifest = installer.load_pack().await.unwrap();
+        let item = manifest.iter().find(|item| item.is_file).unwrap();
+        assert!(item.deferred.is_some());
+        assert!(installer.write_file(item, path.clone()).await.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), original(0));
+        assert!(!temporary_path(&path).exists());
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[tokio::test]
+    async fn writes_create_missing_nested_dirs() {
+        let scratch = scratch_dir("nested-dirs");
+        let options = Options::default();
+        let pack = crate::bench::fixture(&options.language_prefix, 3, 16).unwrap();
+        let game_dir = scratch.join("game");
+        let installer = Installer::new(
+            game_dir.clone(),
+            scratch.join("backups"),
+            PackSource::Memory(pack.into()),
+        )
+        .with_options(options);
+
+        let mut manifest = installer.read_pack().await.unwrap();
+        let prefix = installer.prefix().to_path_buf();
+        // Directory entries are skipped, each file has to create its own parents
+        for (item, striped) in manifest.filter_hans_dir(&prefix) {
+            if item.is_file {
+                let path = game_dir.join("new/nested").join(&striped);
+                installer.write_file(item, path.clone()).await.unwrap();
+                assert_eq!(std::fs::read(&path).unwrap(), item.bytes);
+            }
+        }
+        assert!(game_dir.join("new/nested/data/bench").is_dir());
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[tokio::test]
+    async fn a_signed_pack_swapped_after_verification_is_not_read() {
+        let scratch = scratch_dir("signed-swap");
+        let prefix = Options::default().language_prefix;
+        let pack = scratch.join("pack.zip");
+        std::fs::write(&pack, crate::bench::fixture(&prefix, 2, 64).unwrap()).unwrap();
+        let key = scratch.join("key");
+        std::fs::write(&key, "07".repeat(32)).unwrap();
+        let public_key = crate::signing::sign_pack(&pack, &key).unwrap();
+        let options = Options {
+            stream_threshold: 0,
+            trusted_keys: vec![public_key],
+            ..Options::default()
+        };
+        let installer = Installer::new(
+            scratch.join("game"),
+            scratch.join("backups"),
+            PackSource::Signed {
+                path: pack.clone(),
+                allow_unsigned: false,
+                copy: PrivateCopy::default(),
+            },
+        )
+        .with_options(options);
+
+        let manifest = installer.read_pack().await.unwrap();
+        // An unsigned pack takes its place once the signature checked out
+        std::fs::write(&pack, crate::bench::fixture(&prefix, 2, 128).unwrap()).unwrap();
+        for item in manifest.iter().filter(|item| item.is_file) {
+            assert!(item.deferred.is_some());
+            let path = scratch.join("game").join(&item.lowercase_name);
+            installer.write_file(item, path.clone()).await.unwrap();
+            let written = std::fs::read(&path).unwrap();
+            assert_eq!(written.len(), 64);
+            assert_eq!(crc32fast::hash(&written), item.crc32);
+        }
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+}
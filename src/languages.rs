@@ -0,0 +1,67 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+pub struct LanguagePack {
+    pub code: &'static str,
+    pub name: &'static str,
+    pub prefix: &'static str,
+}
+
+impl LanguagePack {
+    pub fn prefix(&self) -> &'static Path {
+        Path::new(self.prefix)
+    }
+}
+
+impl Display for LanguagePack {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}  {}  [{}]", self.code, self.name, self.prefix)
+    }
+}
+
+/// Languages the embedded pack provides, keyed by the code passed to `--lang`
+pub static LANGUAGES: &[LanguagePack] = &[LanguagePack {
+    code: "zh",
+    name: "Simplified Chinese",
+    prefix: "language/zh_cn_hans",
+}];
+
+pub fn find(code: &str) -> Result<&'static LanguagePack> {
+    LANGUAGES
+        .iter()
+        .find(|language| language.code.eq_ignore_ascii_case(code))
+        .ok_or_else(|| {
+            let codes = LANGUAGES
+                .iter()
+                .map(|language| language.code)
+                .collect::<Vec<_>>();
+            eyre!(
+                "Unknown language [{}], available languages: {}",
+                code,
+                codes.join(", ")
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, LANGUAGES};
+
+    #[test]
+    fn codes_are_matched_ignoring_case() {
+        assert_eq!(find("ZH").unwrap().code, "zh");
+    }
+
+    #[test]
+    fn an_unknown_code_lists_the_available_ones() {
+        let message = find("xx").unwrap_err().to_string();
+        assert!(message.contains("[xx]"), "{}", message);
+        for language in LANGUAGES {
+            assert!(message.contains(language.code), "{}", message);
+        }
+    }
+}
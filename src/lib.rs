@@ -0,0 +1,30 @@
+mod archive;
+pub mod backups;
+pub mod bench;
+pub mod casing;
+pub mod chunked;
+pub mod config;
+pub mod descriptors;
+pub mod durability;
+pub mod editions;
+pub mod encoding;
+pub mod error;
+pub mod hashing;
+mod inspect;
+mod install;
+pub mod installer;
+pub mod languages;
+pub mod manifest;
+pub mod metadata;
+pub mod optional;
+pub mod passphrase;
+pub mod path_structure;
+pub mod permissions;
+pub mod progress;
+pub mod report;
+mod restore;
+pub mod signing;
+pub mod state;
+pub mod steam;
+pub mod symlinks;
+pub mod tasks;
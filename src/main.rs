@@ -1,261 +1,1369 @@
-mod manifest;
-#[allow(unused)]
-mod path_structure;
-
-use crate::manifest::{Manifest, ManifestItem};
-use clap::{Parser, Subcommand};
+use alien::config::Config;
+use alien::editions::Edition;
+use alien::error::AlienError;
+use alien::installer::{BackupMode, Installer, OnMissing, Options, PackSource, PrivateCopy};
+use alien::progress::{ProgressEvent, ProgressReporter};
+use alien::report::{Explain, Info, Phase, PhaseTimer, Timings};
+use alien::state::StateFile;
+use alien::{
+    backups, bench, chunked, descriptors, editions, encoding, languages, manifest, path_structure,
+    signing, steam,
+};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::{eyre, Context};
 use color_eyre::Result;
-use std::collections::HashMap;
-use std::io::{Cursor, Write};
-use std::path::PathBuf;
-use tokio::io::AsyncWriteExt;
-use zip::write::SimpleFileOptions;
-use zip::ZipWriter;
-
-static WHITE_LIST: &str = include_str!("../assets/white_list.txt");
+use std::collections::BTreeMap;
+use std::io::{Cursor, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+use zip::ZipArchive;
 
 #[derive(Debug, Parser)]
 pub struct Alien {
     #[command(subcommand)]
     language: Language,
+
+    /// Language of the pack to install, see `alien languages`
+    #[arg(long, global = true, env = "ALIEN_LANG", default_value = "zh")]
+    lang: String,
+
+    /// Install this signed language pack zip instead of the embedded one
+    #[arg(
+        long,
+        global = true,
+        env = "ALIEN_PACK",
+        conflicts_with = "language_dir"
+    )]
+    pack: Option<PathBuf>,
+
+    /// Accept a --pack without a `<pack>.sig` signature
+    #[arg(long, global = true)]
+    allow_unsigned: bool,
+
+    /// Read the language pack from loose files laid out like the zip (`language/...`)
+    #[arg(long, global = true, env = "ALIEN_LANGUAGE_DIR")]
+    language_dir: Option<PathBuf>,
+
+    /// Worker threads of the async runtime and the hashing pool
+    #[arg(
+        short,
+        long,
+        visible_alias = "threads",
+        global = true,
+        env = "ALIEN_JOBS"
+    )]
+    jobs: Option<usize>,
+
+    /// Files open at once while installing and restoring, whatever --jobs is (32 when not
+    /// given)
+    #[arg(long, global = true, env = "ALIEN_IO_LIMIT")]
+    io_limit: Option<usize>,
+
+    /// AlienIsolationData directory of the game installation
+    #[arg(long, global = true, env = "ALIEN_GAME_DIR")]
+    game_dir: Option<PathBuf>,
+
+    /// Store edition to look for when --game-dir is not given, required when several are installed
+    #[arg(long, global = true, value_enum, env = "ALIEN_EDITION")]
+    edition: Option<Edition>,
+
+    /// Game dir the install was recorded for, to restore after moving the game elsewhere
+    #[arg(long, global = true)]
+    relative_to: Option<PathBuf>,
+
+    /// Directory holding the backup archives
+    #[arg(long, global = true, env = "ALIEN_BACKUP_DIR")]
+    backup_dir: Option<PathBuf>,
+
+    /// Flavor of the async runtime, picked from the subcommand when omitted
+    #[arg(long, global = true, value_enum)]
+    runtime: Option<RuntimeFlavor>,
+
+    /// Do not carry file modification times through backup and restore
+    #[arg(long = "no-preserve-timestamps", global = true, action = ArgAction::SetFalse)]
+    preserve_timestamps: bool,
+
+    /// Do not carry Unix permission bits through backup and restore
+    #[arg(long = "no-preserve-perms", global = true, action = ArgAction::SetFalse)]
+    preserve_perms: bool,
+
+    /// Fail on read-only game files instead of clearing the flag and retrying
+    #[arg(long = "no-fix-permissions", global = true, action = ArgAction::SetFalse)]
+    fix_permissions: bool,
+
+    /// Sync every written file and its directory to disk, slower but safe against power loss
+    #[arg(long, global = true)]
+    durable: bool,
+
+    /// Read every installed file back and fail, rolling back, when it differs from the pack
+    #[arg(long, global = true)]
+    verify_writes: bool,
+
+    /// Stop all remaining writes at the first failure instead of finishing them
+    #[arg(long, global = true, overrides_with = "no_fail_fast")]
+    fail_fast: bool,
+
+    /// Finish every write and report the first failure in pack order, the default
+    #[arg(long, global = true, overrides_with = "fail_fast")]
+    no_fail_fast: bool,
+
+    /// Refuse to modify game files reached through a symlink instead of following it
+    #[arg(long = "no-follow-symlinks", global = true, action = ArgAction::SetFalse)]
+    follow_symlinks: bool,
+
+    /// Hash every file for verify and status instead of trusting the cached hash of files whose
+    /// size and mtime are unchanged, which misses content changed without touching either
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Silence phase logs and print only the final summary line to stdout
+    #[arg(long, global = true)]
+    summary_only: bool,
+
+    /// Print phase and file progress as JSON lines on stderr, for GUI wrappers
+    #[arg(long, global = true)]
+    progress_json: bool,
+
+    /// Print the time, bytes and throughput of each phase, as a table or with the JSON output
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Act on a game dir that lacks the game's DATA/UI and DATA/ENV directories
+    #[arg(long, global = true)]
+    no_safe_mode: bool,
+
+    /// Print the resolved game dir, backup dir, pack and limits before acting
+    #[arg(long, global = true)]
+    explain: bool,
+
+    /// Discard an interrupted install and start over, reusing its backup
+    #[arg(long, global = true)]
+    restart: bool,
+
+    /// Skip the backup entirely, restoring then requires Steam's file verification
+    #[arg(long, global = true)]
+    no_backup: bool,
+
+    /// Answer yes to confirmation prompts
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Leave files written before an install failure in place instead of rolling back
+    #[arg(long, global = true)]
+    no_rollback: bool,
+
+    /// Restrict patch, backup and restore to the relative paths listed in this file, `-` for stdin
+    #[arg(long, global = true)]
+    files_from: Option<PathBuf>,
+
+    /// Skip backing up and patching files larger than this many bytes, in the pack or the game
+    #[arg(long, global = true)]
+    max_file_size: Option<u64>,
+
+    /// Skip paths from --files-from that the language pack does not contain
+    #[arg(long, global = true)]
+    ignore_missing: bool,
+
+    /// Restore even when the game already looks English
+    #[arg(long, global = true)]
+    force: bool,
+
+    /// Patch over files that another mod changed instead of refusing
+    #[arg(long, global = true)]
+    overwrite_foreign: bool,
+
+    /// Also overwrite files that something other than alien changed since the install
+    #[arg(long, global = true)]
+    overwrite_modified: bool,
+
+    /// Fail instead of warning when the game files drifted from the existing backup
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Encrypt backup entries with a passphrase (or ALIEN_BACKUP_PASSWORD)
+    #[arg(long, global = true)]
+    encrypt: bool,
+
+    /// Policy for pack files missing from the game, see the values for how restore treats them
+    #[arg(long, global = true, value_enum, default_value_t = OnMissing::Error)]
+    on_missing: OnMissing,
+
+    /// Warn about and skip missing pack directories that hold only optional files or none
+    #[arg(long, global = true)]
+    quiet_errors_as_warnings: bool,
+
+    /// Files larger than this many bytes are backed up in chunks of this size, 8 MiB by default
+    #[arg(long, global = true)]
+    chunk_size: Option<usize>,
+
+    /// Pack files larger than this many bytes are streamed from the pack instead of held in
+    /// memory, 8 MiB by default
+    #[arg(long, global = true)]
+    stream_threshold: Option<u64>,
+
+    /// Bytes per write when installing, restoring and writing backups, 1 MiB by default
+    #[arg(long, global = true)]
+    io_buffer_size: Option<usize>,
+
+    /// Most game files open at once, further opens queue, the file limit minus 16 by default
+    #[arg(long, global = true)]
+    max_open_files: Option<usize>,
+
+    /// How the backup is stored, clonefile copies files where reflinks are unsupported
+    #[arg(
+        long,
+        visible_alias = "backup-format",
+        global = true,
+        value_enum,
+        default_value_t = BackupMode::Zip
+    )]
+    backup_mode: BackupMode,
+
+    /// Output format of reporting subcommands
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Format of log lines, json emits one structured event per line
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
 }
 
-#[derive(Default, Debug, Clone, Subcommand)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum RuntimeFlavor {
+    CurrentThread,
+    MultiThread,
+}
+
+#[derive(Debug, Clone, Subcommand)]
 pub enum Language {
-    #[default]
-    #[command(name = "zh")]
-    Chinese,
-    #[command(name = "en")]
-    English,
+    /// Install the pack selected with --lang, Simplified Chinese by default
+    #[command(name = "zh", alias = "patch")]
+    Chinese {
+        /// Only unpack and verify the pack in the staging directory, the game is left untouched
+        #[arg(long, conflicts_with = "commit")]
+        stage: bool,
+
+        /// Only move previously staged files into the game directory
+        #[arg(long)]
+        commit: bool,
+
+        /// Write a patched copy of the game to this zip or directory, the game is left untouched
+        #[arg(long, conflicts_with_all = ["stage", "commit"])]
+        output: Option<PathBuf>,
+    },
+    /// Restore the English files from the backup, whichever language was installed
+    #[command(name = "en", alias = "restore")]
+    English {
+        /// Restore from this backup archive instead of the default backup
+        #[arg(long)]
+        from: Option<PathBuf>,
+
+        /// Write the list of restored and removed files as JSON to this path
+        #[arg(long)]
+        manifest_out: Option<PathBuf>,
+
+        /// Without a backup, only delete the files the language pack installs
+        #[arg(long, conflicts_with = "from")]
+        delete_only: bool,
+
+        /// Without a backup, remove the created files and let Steam re-download the originals
+        #[arg(long, conflicts_with_all = ["from", "delete_only"])]
+        from_steam: bool,
+    },
+    /// Restore English then apply the language pack again
+    Reinstall,
+    /// Rewrite only the Chinese files that are missing or differ from the language pack
+    Repair,
+    /// Compare the installed files against the language pack, the exit code tells the verdict
+    Verify {
+        /// Check the backup instead, which entries a restore would change
+        #[arg(long)]
+        backup: bool,
+
+        /// Print the classification of every file
+        #[arg(long)]
+        list: bool,
+    },
+    /// Refresh the backup with originals Steam updated since the install, then patch them again
+    Sync,
+    /// Unpack the language pack to a directory for inspection
+    Extract {
+        /// Directory to unpack into, the `language/...` structure is preserved
+        #[arg(long)]
+        dest: PathBuf,
+
+        /// Extract this archive instead of the embedded language pack
+        #[arg(long)]
+        language_zip: Option<PathBuf>,
+
+        /// Only extract entries under this subtree, e.g. `language/zh_cn_hans`
+        #[arg(long)]
+        only: Option<PathBuf>,
+    },
+    /// Report BOMs, invalid encodings and mixed line endings in the pack's text files
+    CheckEncoding {
+        /// Write a copy of the pack with fixable issues normalized to this zip
+        #[arg(long)]
+        fix: Option<PathBuf>,
+    },
+    /// List the languages the embedded pack provides
+    Languages,
+    /// Print the version, build and embedded pack hash to quote in bug reports
+    Info,
+    /// Show what the last install recorded for the game directory
+    Status,
+    /// Hash the game files and compare them with a known-good set, the pack's by default
+    VerifyInstall {
+        /// Expected hashes in `sha256sum` format, e.g. of an untouched English install
+        #[arg(long)]
+        hashes: Option<PathBuf>,
+    },
+    /// Compare the pack's files with the game or with a reference tree
+    Diff {
+        /// Reference directory laid out like the game data dir, e.g. an extracted older pack
+        /// or another translation, instead of the live game
+        #[arg(long)]
+        compare_with: Option<PathBuf>,
+    },
+    /// Report whether the pack covers a game file and whether it is patched
+    Which {
+        /// Path relative to the game data dir, matched ignoring case and separators
+        path: PathBuf,
+    },
+    /// Diagnose the game installation and backup state
+    Doctor,
+    /// List the archives in the backup directory
+    Backups,
+    /// Inspect the configuration file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Tools for pack maintainers
+    Pack {
+        #[command(subcommand)]
+        action: PackAction,
+    },
+    /// Remove backups that fall outside the configured retention policy
+    Clean {
+        #[arg(long)]
+        apply_retention: bool,
+    },
+    /// Time the install phases on a generated pack in a scratch directory
+    #[command(hide = true)]
+    Bench {
+        /// Number of files in the generated pack
+        #[arg(long, default_value_t = 1000)]
+        files: usize,
+
+        /// Size of each generated file in bytes
+        #[arg(long, default_value_t = 64 * 1024)]
+        size: usize,
+
+        /// Run once on each runtime flavor in turn, timing how long the runtime takes to start
+        #[arg(long)]
+        runtimes: bool,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    color_eyre::install()?;
-    tracing_subscriber::fmt::init();
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigAction {
+    /// Print the location of the configuration file
+    Path,
+}
 
-    let alien = Alien::parse();
+#[derive(Debug, Clone, Subcommand)]
+pub enum PackAction {
+    /// Write the detached signature `<zip>.sig` that --pack checks
+    Sign {
+        zip: PathBuf,
+        /// File holding the hex encoded ed25519 secret key
+        #[arg(long)]
+        key: PathBuf,
+    },
+}
+impl Alien {
+    /// The flags merged over the config file
+    fn options(&self, config: &Config) -> Result<Options> {
+        let defaults = Options::default();
+        let jobs = self.jobs.or(config.jobs);
+        Ok(Options {
+            language_prefix: languages::find(&self.lang)?.prefix().to_path_buf(),
+            jobs: jobs.unwrap_or(defaults.jobs),
+            io_limit: self.io_limit.unwrap_or(defaults.io_limit).max(1),
+            fail_fast: self.fail_fast && !self.no_fail_fast,
+            durable: self.durable || config.durable,
+            verify_writes: self.verify_writes,
+            follow_symlinks: self.follow_symlinks,
+            fix_permissions: self.fix_permissions,
+            preserve_timestamps: self.preserve_timestamps,
+            preserve_permissions: self.preserve_perms,
+            chunk_size: self.chunk_size.unwrap_or(defaults.chunk_size).max(1),
+            stream_threshold: self.stream_threshold.unwrap_or(defaults.stream_threshold),
+            io_buffer_size: self.io_buffer_size.unwrap_or(defaults.io_buffer_size),
+            stored_extensions: config.backup.stored_extensions.clone(),
+            trusted_keys: config.trusted_keys.clone(),
+            backup_mode: self.backup_mode,
+            retention: config.backup.clone(),
+            encrypt: self.encrypt,
+            on_missing: self.on_missing,
+            tolerate_missing_dirs: self.quiet_errors_as_warnings,
+            max_file_size: self.max_file_size,
+            no_backup: self.no_backup,
+            no_rollback: self.no_rollback,
+            restart: self.restart,
+            overwrite_foreign: self.overwrite_foreign,
+            overwrite_modified: self.overwrite_modified,
+            strict: self.strict,
+            force: self.force,
+            no_cache: self.no_cache,
+            safe_mode: !self.no_safe_mode,
+            assume_yes: self.yes,
+            relative_to: self.relative_to.clone(),
+        })
+    }
 
-    let mut manifest = Manifest::read_from_language_zip()?;
-    match alien.language {
-        Language::Chinese => {
-            let filtered = manifest.filter_hans_dir();
-            check_manifest_for_game_data(&filtered)?;
-            backup_alien_isolation_data(&filtered).await?;
-            chinese(filtered).await?;
+    fn pack_source(&self) -> PackSource {
+        match (&self.language_dir, &self.pack) {
+            (Some(language_dir), _) => PackSource::Dir(language_dir.clone()),
+            (None, Some(pack)) => PackSource::Signed {
+                path: pack.clone(),
+                allow_unsigned: self.allow_unsigned,
+                copy: PrivateCopy::default(),
+            },
+            (None, None) => PackSource::Embedded,
+        }
+    }
+
+    /// Uses --game-dir or the config's, otherwise the installed edition. Subcommands that leave
+    /// the game alone run without one.
+    fn game_dir(&self, config: &Config) -> Result<PathBuf> {
+        if let Some(game_dir) = self.game_dir.clone().or_else(|| config.game_dir.clone()) {
+            return Ok(game_dir);
+        }
+        if !self.language.needs_game_dir() {
+            // Doctor lists every edition, it must not fail on several being installed
+            return Ok(editions::detect(self.edition)
+                .ok()
+                .flatten()
+                .unwrap_or_default());
+        }
+        match editions::detect(self.edition)? {
+            Some(game_dir) => Ok(game_dir),
+            None => Err(AlienError::GameDirNotFound {
+                edition: match self.edition {
+                    Some(edition) => edition.to_string(),
+                    None => "Steam, GOG or Epic".to_string(),
+                },
+            }
+            .into()),
+        }
+    }
+
+    fn installer(&self, config: &Config) -> Result<Installer> {
+        let backup_dir = match self
+            .backup_dir
+            .clone()
+            .or_else(|| config.backup_dir.clone())
+        {
+            Some(backup_dir) => backup_dir,
+            None => backups::default_dir()
+                .ok_or(AlienError::NoDefaultDir { what: "data" })
+                .context("Pass --backup-dir")?,
+        };
+        let state_dir = StateFile::default_dir().unwrap_or_else(|| backup_dir.clone());
+        let mut installer = Installer::new(self.game_dir(config)?, backup_dir, self.pack_source())
+            .with_state_dir(state_dir)
+            .with_options(self.options(config)?);
+        if self.progress_json {
+            installer = installer.with_reporter(Arc::new(JsonLinesReporter));
+        }
+        if std::io::stdin().is_terminal() {
+            installer = installer.with_prompt(Arc::new(ask));
+        }
+        Ok(installer)
+    }
+
+    /// Writes the resolved settings to `out`, as JSON with --format json
+    fn explain(&self, installer: &Installer, out: &mut impl Write) -> Result<()> {
+        let options = installer.options();
+        let explain = Explain {
+            game_dir: installer.game_dir().to_path_buf(),
+            backup_dir: installer.backup_dir().to_path_buf(),
+            config: Config::path().filter(|path| path.exists()),
+            language: self.lang.clone(),
+            language_prefix: options.language_prefix.clone(),
+            pack: self.pack_source().describe(),
+            optional_files: format!(
+                "{} in the pack, the built-in white list without it",
+                manifest::PACK_METADATA_ENTRY
+            ),
+            jobs: options.jobs,
+            io_limit: options.io_limit,
+        };
+        match self.format {
+            OutputFormat::Json => writeln!(
+                out,
+                "{}",
+                serde_json::to_string_pretty(&serde_json::json!({ "explain": explain }))?
+            )?,
+            OutputFormat::Text => writeln!(out, "{}", explain.table())?,
+        }
+        Ok(())
+    }
+
+    fn runtime_flavor(&self) -> RuntimeFlavor {
+        if let Some(flavor) = self.runtime {
+            return flavor;
         }
-        Language::English => {
-            let needs_remove = manifest;
-            let manifest = Manifest::read_from_backup_zip()?;
-            english(manifest, needs_remove).await?;
+        match (self.jobs, self.language.is_read_only()) {
+            (Some(1), _) | (None, true) => RuntimeFlavor::CurrentThread,
+            _ => RuntimeFlavor::MultiThread,
         }
     }
-    Ok(())
+
+    fn build_runtime(&self) -> Result<tokio::runtime::Runtime> {
+        build_runtime(self.runtime_flavor(), self.jobs)
+    }
+
+    fn init_tracing(&self) {
+        self.subscriber(std::io::stderr).init();
+    }
+
+    /// Log subscriber of --log-format and --summary-only writing to `writer`
+    fn subscriber<W>(&self, writer: W) -> Box<dyn tracing::Subscriber + Send + Sync>
+    where
+        W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    {
+        let max_level = if self.summary_only {
+            tracing::Level::WARN
+        } else {
+            tracing::Level::INFO
+        };
+        let builder = tracing_subscriber::fmt()
+            .with_max_level(max_level)
+            .with_writer(writer);
+        match self.log_format {
+            LogFormat::Text => Box::new(builder.finish()),
+            LogFormat::Json => Box::new(builder.json().finish()),
+        }
+    }
+
+    fn report_summary(&self, summary: &str) {
+        let _ = self.write_summary(&mut std::io::stdout(), summary);
+    }
+
+    /// Writes `summary` as a line of `out` with --summary-only, otherwise it is only logged
+    fn write_summary(&self, out: &mut impl Write, summary: &str) -> std::io::Result<()> {
+        if self.summary_only {
+            writeln!(out, "{}", summary)
+        } else {
+            tracing::info!("{}", summary);
+            Ok(())
+        }
+    }
+
+    /// Prints `report` as JSON, or its text form
+    fn print<T: serde::Serialize>(&self, report: &T, text: impl FnOnce() -> String) -> Result<()> {
+        match self.format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(report)?),
+            OutputFormat::Text => println!("{}", text()),
+        }
+        Ok(())
+    }
 }
 
-fn check_manifest_for_game_data(filtered: &[(&mut ManifestItem, PathBuf)]) -> Result<()> {
+fn build_runtime(flavor: RuntimeFlavor, jobs: Option<usize>) -> Result<tokio::runtime::Runtime> {
     let instant = std::time::Instant::now();
-    let alien_isolation_dir = path_structure::alien_isolation_dir();
-    tracing::info!(
-        "Checking manifest for game data [{}]",
-        alien_isolation_dir.display()
-    );
-    let white_list = WHITE_LIST.lines().collect::<Vec<_>>();
-    let not_found = filtered
-        .iter()
-        .flat_map(|(_, striped)| {
-            let path = alien_isolation_dir.join(striped);
-            if striped.components().count() > 0
-                && !path.exists()
-                && !white_list.contains(&striped.display().to_string().as_str())
-            {
-                Some(striped.to_path_buf())
-            } else {
-                None
+    let runtime = match flavor {
+        RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?,
+        RuntimeFlavor::MultiThread => {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+            if let Some(jobs) = jobs {
+                builder.worker_threads(jobs.max(1));
             }
+            builder.enable_all().build()?
+        }
+    };
+    tracing::info!("Started {:?} runtime take {:?}", flavor, instant.elapsed());
+    Ok(runtime)
+}
+
+/// Runs the bench on a runtime of each flavor in turn, each on a thread of its own as a runtime
+/// cannot be started from within another
+fn bench_runtimes(
+    options: &Options,
+    jobs: Option<usize>,
+    files: usize,
+    size: usize,
+) -> Result<BTreeMap<String, Timings>> {
+    let mut runs = BTreeMap::new();
+    for flavor in RuntimeFlavor::value_variants() {
+        let options = options.clone();
+        let timings = std::thread::spawn(move || {
+            let instant = std::time::Instant::now();
+            let runtime = build_runtime(*flavor, jobs)?;
+            let runtime_start = instant.elapsed();
+            let mut timings = runtime.block_on(bench::run(options, files, size))?;
+            timings.runtime_start = Some(runtime_start);
+            Ok::<_, color_eyre::Report>(timings)
         })
-        .collect::<Vec<_>>();
-    if !not_found.is_empty() {
-        let mut first = Err(eyre!("File missing"));
-        for path in not_found.into_iter() {
-            first = first.with_context(|| path.display().to_string());
+        .join()
+        .map_err(|_| eyre!("The bench on the {:?} runtime panicked", flavor))??;
+        let name = flavor
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default();
+        runs.insert(name, timings);
+    }
+    Ok(runs)
+}
+
+/// Writes every progress event as one line of JSON to stderr
+struct JsonLinesReporter;
+
+impl JsonLinesReporter {
+    fn emit(&self, event: ProgressEvent) {
+        if let Ok(line) = serde_json::to_string(&event) {
+            eprintln!("{}", line);
         }
-        return first;
     }
-    tracing::info!(
-        "Checked manifest for game data take {:?}",
-        instant.elapsed()
-    );
-    Ok(())
 }
 
-async fn backup_alien_isolation_data(filtered: &[(&mut ManifestItem, PathBuf)]) -> Result<()> {
-    let instant = std::time::Instant::now();
-    let alien_isolation_dir = path_structure::alien_isolation_dir();
-    let backup_dir = path_structure::backup_dir();
-    let backup_zip = backup_dir.join(path_structure::language_zip());
-    tracing::info!(
-        "Backing up [{}] to [{}]",
-        alien_isolation_dir
-            .join(path_structure::data_dir())
-            .display(),
-        backup_zip.display(),
-    );
-    let mut data_buffer = Cursor::new(Vec::new());
-    let mut archive = ZipWriter::new(&mut data_buffer);
-    let white_list = WHITE_LIST.lines().collect::<Vec<_>>();
+impl ProgressReporter for JsonLinesReporter {
+    fn on_phase_start(&self, phase: &'static str, files: usize) {
+        self.emit(ProgressEvent::PhaseStart { phase, files });
+    }
 
-    let buffers = futures::future::join_all(
-        filtered
-            .iter()
-            .map(|(_, striped)| (alien_isolation_dir.join(striped), striped))
-            .map(|(path, striped)| {
-                let white_list = white_list.clone();
-                async move {
-                    let metadata = match tokio::fs::metadata(&path).await {
-                        Ok(metadata) => metadata,
-                        Err(error) => {
-                            return if error.kind() == std::io::ErrorKind::NotFound
-                                && white_list.contains(&striped.display().to_string().as_str())
-                            {
-                                Ok(None)
-                            } else {
-                                Err(error)
-                            }
+    fn on_file_done(&self, phase: &'static str, path: &Path, bytes: u64) {
+        self.emit(ProgressEvent::FileDone {
+            phase,
+            path: path.to_path_buf(),
+            bytes,
+        });
+    }
+
+    fn on_phase_end(&self, phase: &'static str, stats: &Phase) {
+        self.emit(ProgressEvent::PhaseEnd {
+            phase,
+            stats: *stats,
+        });
+    }
+}
+
+impl Language {
+    fn modifies_game(&self) -> bool {
+        matches!(
+            self,
+            Language::Chinese { .. }
+                | Language::English { .. }
+                | Language::Reinstall
+                | Language::Repair
+                | Language::Sync
+        )
+    }
+
+    fn needs_game_dir(&self) -> bool {
+        !matches!(
+            self,
+            Language::Doctor
+                | Language::Info
+                | Language::Bench { .. }
+                | Language::Config { .. }
+                | Language::Pack { .. }
+                | Language::Languages
+                | Language::Backups
+                | Language::Clean { .. }
+                | Language::Extract { .. }
+                | Language::CheckEncoding { .. }
+        )
+    }
+
+    fn is_read_only(&self) -> bool {
+        match self {
+            Language::Chinese { .. }
+            | Language::English { .. }
+            | Language::Reinstall
+            | Language::Repair
+            | Language::Sync
+            | Language::Extract { .. }
+            | Language::Clean { .. } => false,
+            Language::CheckEncoding { .. }
+            | Language::Languages
+            | Language::Info
+            | Language::Status
+            | Language::Verify { .. }
+            | Language::VerifyInstall { .. }
+            | Language::Diff { .. }
+            | Language::Which { .. }
+            | Language::Doctor
+            | Language::Backups
+            | Language::Pack { .. }
+            | Language::Config { .. }
+            | Language::Bench { .. } => true,
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let alien = Alien::parse();
+    alien.init_tracing();
+    let config = Config::load()?;
+    descriptors::set_max_open_files(alien.max_open_files);
+    let installer = alien.installer(&config)?;
+    if alien.explain {
+        match alien.format {
+            OutputFormat::Json => alien.explain(&installer, &mut std::io::stdout())?,
+            OutputFormat::Text => alien.explain(&installer, &mut std::io::stderr())?,
+        }
+    }
+    if alien.language.modifies_game() {
+        installer.check()?;
+    }
+    let game_dir = installer.game_dir().to_path_buf();
+    let game_dir_existed = game_dir.is_dir();
+    let format = alien.format;
+    let runtime = alien.build_runtime()?;
+    let error = match runtime.block_on(run(alien, config, installer)) {
+        Ok(0) => return Ok(()),
+        Ok(code) => std::process::exit(code),
+        Err(error) => error,
+    };
+    match AlienError::find(&error) {
+        Some(AlienError::MissingFiles(missing)) if format == OutputFormat::Json => {
+            let missing = serde_json::json!({ "game_dir": game_dir, "missing": missing });
+            println!("{}", serde_json::to_string_pretty(&missing)?);
+            std::process::exit(1);
+        }
+        Some(alien_error @ (AlienError::BackupMissing(_) | AlienError::Cancelled { .. })) => {
+            eprintln!("{}", error);
+            std::process::exit(alien_error.exit_code());
+        }
+        _ if game_dir_existed && is_volume_gone(&error, &game_dir) => Err(error.wrap_err(
+            "Game volume became unavailable, reconnect it and re-run the command, partial backups are cleaned up on the next run",
+        )),
+        _ => Err(error),
+    }
+}
+
+#[cfg(target_os = "linux")]
+const VOLUME_GONE_CODES: &[i32] = &[5, 6, 19, 107, 116];
+#[cfg(target_os = "macos")]
+const VOLUME_GONE_CODES: &[i32] = &[5, 6, 19, 57, 70];
+#[cfg(windows)]
+const VOLUME_GONE_CODES: &[i32] = &[21, 55, 64, 1167];
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+const VOLUME_GONE_CODES: &[i32] = &[];
+
+/// The first Ctrl-C stops every chunked write at its next chunk, so a large file is never cut
+/// off mid-write, a second Ctrl-C exits at once
+fn watch_ctrl_c(cancel: chunked::Cancel) {
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if cancel.cancel() {
+                std::process::exit(130);
+            }
+            tracing::warn!("Stopping after the current chunks, press Ctrl-C again to exit at once");
+        }
+    });
+}
+
+fn is_volume_gone(error: &color_eyre::Report, game_dir: &Path) -> bool {
+    let root_missing = !game_dir.exists();
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|error| {
+            error.kind() != std::io::ErrorKind::PermissionDenied
+                && (root_missing
+                    || error
+                        .raw_os_error()
+                        .is_some_and(|code| VOLUME_GONE_CODES.contains(&code)))
+        })
+}
+
+/// Asks on the terminal, anything but yes is a no
+fn ask(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Runs the subcommand, returning the exit code of reporting subcommands
+async fn run(alien: Alien, config: Config, mut installer: Installer) -> Result<i32> {
+    watch_ctrl_c(installer.cancel_token());
+    match alien.language {
+        Language::Config {
+            action: ConfigAction::Path,
+        } => return config_path(),
+        Language::Pack {
+            action: PackAction::Sign { ref zip, ref key },
+        } => {
+            let public_key = signing::sign_pack(zip, key)?;
+            alien.report_summary(&format!(
+                "Signed [{}], public key {}",
+                zip.display(),
+                public_key
+            ));
+            return Ok(0);
+        }
+        Language::CheckEncoding { ref fix } => {
+            return check_encoding(&alien, &installer, fix.as_deref()).await;
+        }
+        Language::Languages => return list_languages(alien.format),
+        Language::Info => return info(alien.format),
+        Language::Status => {
+            let report = installer.status().await?;
+            alien.print(&report, || report.describe())?;
+            return Ok(0);
+        }
+        Language::Verify { backup: true, list } => {
+            let report = installer.verify_backup().await?;
+            alien.print(&report, || {
+                let mut lines = Vec::new();
+                if list {
+                    let classified = [
+                        ("will be restored", &report.will_restore),
+                        ("already identical", &report.identical),
+                        ("target missing", &report.missing),
+                    ];
+                    for (class, paths) in classified {
+                        for path in paths {
+                            lines.push(format!("{:<18} {}", class, path.display()));
                         }
-                    };
-                    if metadata.is_file() {
-                        let buffer = tokio::fs::read(&path).await?;
-                        Ok(Some((striped, Some(buffer))))
-                    } else {
-                        Ok(Some((striped, None)))
                     }
                 }
-            }),
-    )
-    .await;
-
-    let mut buffer_map = buffers
-        .into_iter()
-        .map(|it| Ok(it?))
-        .collect::<Result<Vec<Option<_>>>>()?
-        .into_iter()
-        .flatten()
-        .collect::<HashMap<_, _>>();
-
-    for (_, striped) in filtered.iter() {
-        let buffer = match buffer_map.get_mut(striped) {
-            Some(buffer) => buffer,
-            None => continue,
-        };
-        match buffer {
-            None => {
-                archive
-                    .add_directory(striped.display().to_string(), SimpleFileOptions::default())
-                    .with_context(|| {
-                        format!("Failed to add directory [{}] to archive", striped.display())
-                    })?;
+                lines.push(report.describe());
+                lines.join("\n")
+            })?;
+            if !report.poisoned.is_empty() {
+                eprintln!(
+                    "WARNING: {} backup entries are Chinese files from the language pack, restoring them keeps those files Chinese:",
+                    report.poisoned.len()
+                );
+                for path in &report.poisoned {
+                    eprintln!("  {}", path.display());
+                }
             }
-            Some(buffer) => {
-                archive
-                    .start_file(striped.display().to_string(), SimpleFileOptions::default())
-                    .with_context(|| {
-                        format!("Failed to start file [{}] in archive", striped.display())
-                    })?;
-                archive.write_all(buffer).with_context(|| {
-                    format!("Failed to write [{}] to archive", striped.display())
-                })?;
+            return Ok(0);
+        }
+        Language::Verify { .. } => {
+            let mut timings = Timings::default();
+            let report = installer.verify(&mut timings).await?;
+            if alien.timings {
+                timings.open_files_wait = Some(descriptors::waited());
+                eprintln!("{}", timings.table());
+            }
+            alien.print(&report, || {
+                let mut lines = Vec::new();
+                for path in &report.mismatched {
+                    lines.push(format!("mismatched  {}", path.display()));
+                }
+                for path in &report.missing {
+                    lines.push(format!("missing     {}", path.display()));
+                }
+                lines.push(report.describe());
+                lines.join("\n")
+            })?;
+            return Ok(report.exit_code());
+        }
+        Language::VerifyInstall { ref hashes } => {
+            let report = installer.verify_install(hashes.as_deref()).await?;
+            alien.print(&report, || {
+                let mut lines = Vec::new();
+                for path in &report.mismatched {
+                    lines.push(format!("mismatched  {}", path.display()));
+                }
+                for path in &report.missing {
+                    lines.push(format!("missing     {}", path.display()));
+                }
+                lines.push(format!(
+                    "{} matched, {} mismatched, {} missing",
+                    report.matched,
+                    report.mismatched.len(),
+                    report.missing.len()
+                ));
+                lines.join("\n")
+            })?;
+            return Ok(
+                match report.mismatched.is_empty() && report.missing.is_empty() {
+                    true => 0,
+                    false => 1,
+                },
+            );
+        }
+        Language::Diff { ref compare_with } => {
+            let tree_diff = installer.diff(compare_with.as_deref()).await?;
+            alien.print(&tree_diff, || {
+                let mut lines = Vec::new();
+                for entry in &tree_diff.added {
+                    lines.push(format!("added    {}", entry.describe()));
+                }
+                for entry in &tree_diff.removed {
+                    lines.push(format!("removed  {}", entry.describe()));
+                }
+                for entry in &tree_diff.changed {
+                    lines.push(format!("changed  {}", entry.describe()));
+                }
+                lines.push(tree_diff.describe());
+                lines.join("\n")
+            })?;
+            return Ok(0);
+        }
+        Language::Which { ref path } => {
+            let report = installer.which(path).await?;
+            alien.print(&report, || report.describe())?;
+            return Ok(0);
+        }
+        Language::Doctor => return doctor(&installer),
+        Language::Backups => return list_backups(installer.backup_dir(), alien.format),
+        Language::Clean { apply_retention } => return clean(&installer, &config, apply_retention),
+        Language::Bench {
+            files,
+            size,
+            runtimes: true,
+        } => {
+            let runs = bench_runtimes(installer.options(), alien.jobs, files, size)?;
+            alien.print(&runs, || {
+                runs.iter()
+                    .map(|(flavor, timings)| format!("{} runtime\n{}", flavor, timings.table()))
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })?;
+            return Ok(0);
+        }
+        Language::Bench { files, size, .. } => {
+            let timings = bench::run(installer.options().clone(), files, size).await?;
+            alien.print(&timings, || timings.table())?;
+            return Ok(0);
+        }
+        Language::Extract {
+            ref dest,
+            ref language_zip,
+            ref only,
+        } => {
+            let instant = std::time::Instant::now();
+            let extracted = installer
+                .extract(dest, language_zip.as_deref(), only.as_deref())
+                .await?;
+            alien.report_summary(&format!(
+                "Extracted {} files to [{}] in {:.1?}",
+                extracted,
+                dest.display(),
+                instant.elapsed()
+            ));
+            return Ok(0);
+        }
+        _ => {}
+    }
+    let instant = std::time::Instant::now();
+    let phase = PhaseTimer::start();
+    let mut timings = Timings::default();
+    let mut manifest = installer.load_pack().await?;
+    timings.read_manifest = Some(phase.finish(manifest.bytes()));
+    installer.clean_up(&manifest)?;
+    if let Some(files_from) = &alien.files_from {
+        let list = read_file_list(files_from)?;
+        installer.select_files(&list, &manifest, alien.ignore_missing)?;
+    }
+    let summary = match alien.language {
+        Language::Chinese {
+            output: Some(ref output),
+            ..
+        } => installer.patch_output(&mut manifest, output).await?,
+        Language::Chinese { stage: true, .. } => {
+            installer.stage(&mut manifest, &mut timings).await?
+        }
+        Language::Chinese { commit, .. } => {
+            if !commit {
+                installer.stage(&mut manifest, &mut timings).await?;
+            }
+            installer.install(&mut manifest, &mut timings).await?
+        }
+        Language::English {
+            ref from,
+            ref manifest_out,
+            delete_only,
+            from_steam,
+        } => {
+            let report = if from_steam {
+                installer.restore_from_steam().await?
+            } else if delete_only {
+                installer.delete_installed(manifest).await?
+            } else {
+                installer.restore(manifest, from.as_deref()).await?
+            };
+            if let Some(manifest_out) = manifest_out {
+                report.write_to(manifest_out)?;
+            }
+            if alien.format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
             }
+            format!("Finished, {}", report.describe())
+        }
+        Language::Reinstall => installer.reinstall(manifest, &mut timings).await?,
+        Language::Repair => installer.repair(&mut manifest, &mut timings).await?,
+        Language::Sync => {
+            let report = installer.sync(&mut manifest).await?;
+            match alien.format {
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+                OutputFormat::Text if alien.summary_only => {}
+                OutputFormat::Text => {
+                    let table = report.table();
+                    if !table.is_empty() {
+                        println!("{}", table);
+                    }
+                }
+            }
+            report.describe()
+        }
+        Language::CheckEncoding { .. }
+        | Language::Languages
+        | Language::Info
+        | Language::Status
+        | Language::Verify { .. }
+        | Language::VerifyInstall { .. }
+        | Language::Diff { .. }
+        | Language::Which { .. }
+        | Language::Doctor
+        | Language::Backups
+        | Language::Pack { .. }
+        | Language::Config { .. }
+        | Language::Extract { .. }
+        | Language::Clean { .. }
+        | Language::Bench { .. } => unreachable!(),
+    };
+    let summary = if installer.options().durable {
+        format!("{} with durable writes", summary)
+    } else {
+        summary
+    };
+    alien.report_summary(&format!("{} in {:.1?}", summary, instant.elapsed()));
+    if alien.timings {
+        timings.open_files_wait = Some(descriptors::waited());
+        match alien.format {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&timings)?),
+            OutputFormat::Text => eprintln!("{}", timings.table()),
         }
     }
-    archive.finish()?;
+    Ok(0)
+}
 
-    let mut data_zip = tokio::fs::File::create(backup_zip).await?;
-    data_buffer.set_position(0);
-    tokio::io::copy(&mut data_buffer, &mut data_zip).await?;
+/// The --files-from list, `-` reads it from stdin
+fn read_file_list(files_from: &Path) -> Result<String> {
+    if files_from == Path::new("-") {
+        return std::io::read_to_string(std::io::stdin())
+            .context("Failed to read the file list from stdin");
+    }
+    std::fs::read_to_string(files_from)
+        .with_context(|| format!("Failed to read the file list [{}]", files_from.display()))
+}
 
-    tracing::info!("Backed up take {:?}", instant.elapsed());
-    Ok(())
+async fn check_encoding(alien: &Alien, installer: &Installer, fix: Option<&Path>) -> Result<i32> {
+    let manifest = installer.read_pack_whole().await?;
+    let (texts, issues) = encoding::check_pack(&manifest);
+    alien.print(&issues, || {
+        let mut lines = issues
+            .iter()
+            .map(|(path, issues)| {
+                let issues = issues.iter().map(ToString::to_string).collect::<Vec<_>>();
+                format!("{}: {}", path.display(), issues.join(", "))
+            })
+            .collect::<Vec<_>>();
+        lines.push(format!(
+            "{} of {} text files have encoding issues",
+            issues.len(),
+            texts
+        ));
+        lines.join("\n")
+    })?;
+    if let Some(fix) = fix {
+        encoding::write_normalized(&manifest, fix)?;
+    }
+    Ok(0)
 }
 
-async fn chinese(mut filtered: Vec<(&mut ManifestItem, PathBuf)>) -> Result<()> {
-    tracing::info!("Converting to Chinese");
-    let instant = std::time::Instant::now();
-    let alien_isolation_dir = path_structure::alien_isolation_dir();
-
-    let result: Vec<Result<()>> =
-        futures::future::join_all(filtered.iter_mut().map(|(item, striped)| async move {
-            let path = alien_isolation_dir.join(striped);
-            write_file(item, path).await
-        }))
-        .await;
-    result.into_iter().collect::<Result<Vec<_>>>()?;
-
-    tracing::info!("Converted to Chinese take {:?}", instant.elapsed());
-    Ok(())
+fn list_languages(format: OutputFormat) -> Result<i32> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(languages::LANGUAGES)?),
+        OutputFormat::Text => {
+            for language in languages::LANGUAGES {
+                println!("{}", language);
+            }
+        }
+    }
+    Ok(0)
 }
 
-async fn english(mut manifest: Manifest, mut needs_remove: Manifest) -> Result<()> {
-    let instant = std::time::Instant::now();
-    tracing::info!("Restore to English");
-    let filtered = needs_remove.filter_hans_dir();
-    let needs_remove_dir_len = filtered.iter().filter(|(item, _)| item.is_dir).count();
-    let manifest_dir_len = manifest.iter().filter(|item| item.is_dir).count();
-    if needs_remove_dir_len != manifest_dir_len {
-        return Err(eyre!(
-            "needs remove dir len [{}] not equal to manifest dir len [{}]",
-            needs_remove_dir_len,
-            manifest_dir_len
-        ));
+fn info(format: OutputFormat) -> Result<i32> {
+    let info = build_info()?;
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&info)?),
+        OutputFormat::Text => println!("{}", info.table()),
     }
-    let alien_isolation_dir = path_structure::alien_isolation_dir();
-    let result = futures::future::join_all(
-        filtered
-            .into_iter()
-            .filter(|(item, _)| item.is_file)
-            .map(|(_, striped)| alien_isolation_dir.join(striped))
-            .map(|path| async move {
-                if let Ok(metadata) = tokio::fs::metadata(&path).await {
-                    if metadata.is_file() {
-                        tokio::fs::remove_file(&path).await?;
-                    }
-                }
-                Ok(())
-            }),
-    )
-    .await;
-    result.into_iter().collect::<Result<Vec<_>>>()?;
-
-    let result: Vec<Result<()>> =
-        futures::future::join_all(manifest.iter_mut().map(|item| async move {
-            let path = alien_isolation_dir.join(&item.lowercase_name);
-            write_file(item, path).await
-        }))
-        .await;
-    result.into_iter().collect::<Result<Vec<_>>>()?;
-
-    tracing::info!("Restored to English take {:?}", instant.elapsed());
-    Ok(())
+    Ok(0)
+}
+
+fn build_info() -> Result<Info> {
+    let archive = ZipArchive::new(Cursor::new(path_structure::LANGUAGE_ZIP_DATA))?;
+    Ok(Info {
+        version: env!("CARGO_PKG_VERSION"),
+        pack_sha256: path_structure::LANGUAGE_ZIP_SHA256,
+        pack_files: archive
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .count(),
+        target: env!("ALIEN_TARGET"),
+        git_commit: env!("ALIEN_GIT_COMMIT"),
+    })
+}
+
+fn config_path() -> Result<i32> {
+    match Config::path() {
+        Some(path) => println!("{}", path.display()),
+        None => {
+            return Err(AlienError::NoDefaultDir {
+                what: "configuration",
+            }
+            .into())
+        }
+    }
+    Ok(0)
+}
+
+fn doctor(installer: &Installer) -> Result<i32> {
+    let game_dir = installer.game_dir();
+    let exists = game_dir.is_dir();
+    println!(
+        "Game directory: {} ({})",
+        game_dir.display(),
+        if exists { "found" } else { "missing" }
+    );
+    for (edition, dir) in editions::detect_all() {
+        println!("Edition: {} at {}", edition, dir.display());
+    }
+    match steam::read_app_manifest(game_dir) {
+        Ok(Some(manifest)) if manifest.is_fully_installed() => {
+            println!("Steam: {} is fully installed", manifest.name)
+        }
+        Ok(Some(manifest)) => println!(
+            "Steam: {} is installed but still downloading (StateFlags {}); finish the download before patching.",
+            manifest.name, manifest.state_flags
+        ),
+        Ok(None) => println!("Steam: no appmanifest found"),
+        Err(error) => println!("Steam: unreadable appmanifest ({:#})", error),
+    }
+    match backups::default_backup(installer.backup_dir())? {
+        Some(backup_zip) => println!("Backup: {}", backup_zip.display()),
+        None => println!("Backup: none found in {}", installer.backup_dir().display()),
+    }
+    Ok(0)
+}
+
+fn list_backups(backup_dir: &Path, format: OutputFormat) -> Result<i32> {
+    let backups = backups::list_backups(backup_dir)?;
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&backups)?),
+        OutputFormat::Text if backups.is_empty() => println!("No backups found"),
+        OutputFormat::Text => {
+            for backup in &backups {
+                println!("{}", backup);
+            }
+        }
+    }
+    Ok(0)
 }
 
-async fn write_file(item: &mut ManifestItem, path: PathBuf) -> Result<()> {
-    if item.is_file {
-        let parent = path
-            .parent()
-            .ok_or(eyre!("{} not found parent", path.display()))?;
-        if matches!(tokio::fs::try_exists(parent).await, Ok(true)) {
-            tokio::fs::create_dir_all(parent).await?;
-        }
-        let mut file = tokio::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)
-            .await?;
-        file.write_all(&item.bytes).await?;
-    } else if matches!(tokio::fs::try_exists(&path).await, Ok(true)) {
-        tokio::fs::create_dir_all(path).await?;
-    }
-    Ok(())
+fn clean(installer: &Installer, config: &Config, apply_retention: bool) -> Result<i32> {
+    backups::clean_partials(installer.backup_dir())?;
+    if !apply_retention {
+        tracing::info!("Pass --apply-retention to also prune old backups");
+        return Ok(0);
+    }
+    backups::apply_retention(installer.backup_dir(), &config.backup)?;
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_info, is_volume_gone, Alien, VOLUME_GONE_CODES};
+    use alien::config::Config;
+    use alien::installer::Options;
+    use alien::manifest::Manifest;
+    use alien::{hashing, path_structure};
+    use clap::Parser;
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    /// Log lines written by a subscriber, shared with the test
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Lines {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn summary_only_prints_exactly_the_summary_line() {
+        let alien = Alien::try_parse_from(["alien", "--summary-only", "zh"]).unwrap();
+        let mut out = Vec::new();
+        alien
+            .write_summary(&mut out, "Patched 1580 files, 0 up to date in 4.2s")
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            ["Patched 1580 files, 0 up to date in 4.2s"]
+        );
+
+        let alien = Alien::try_parse_from(["alien", "zh"]).unwrap();
+        let mut out = Vec::new();
+        alien.write_summary(&mut out, "Patched").unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn json_logs_are_one_structured_event_per_line() {
+        let alien = Alien::try_parse_from(["alien", "--log-format", "json", "zh"]).unwrap();
+        let lines = Lines::default();
+        let writer = lines.clone();
+        let subscriber = alien.subscriber(move || writer.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(files = 3, "Staged {} files", 3);
+        });
+
+        let bytes = lines.0.lock().unwrap().clone();
+        let text = String::from_utf8(bytes).unwrap();
+        let line = text.lines().next().unwrap();
+        let event = serde_json::from_str::<serde_json::Value>(line).unwrap();
+        for key in ["timestamp", "level", "target", "fields"] {
+            assert!(event.get(key).is_some(), "{} has no {}", line, key);
+        }
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["fields"]["message"], "Staged 3 files");
+        assert_eq!(event["fields"]["files"], 3);
+    }
+
+    #[test]
+    fn flags_override_the_config_file_which_overrides_the_defaults() {
+        let config =
+            toml::from_str::<Config>("game_dir = \"/games/config\"\njobs = 5\ndurable = true\n")
+                .unwrap();
+        let alien = Alien::try_parse_from(["alien", "zh"]).unwrap();
+        assert_eq!(alien.game_dir(&config).unwrap(), Path::new("/games/config"));
+        let options = alien.options(&config).unwrap();
+        assert_eq!(options.jobs, 5);
+        assert!(options.durable);
+
+        let flags = ["alien", "--game-dir", "/games/flag", "--jobs", "3", "zh"];
+        let alien = Alien::try_parse_from(flags).unwrap();
+        assert_eq!(alien.game_dir(&config).unwrap(), Path::new("/games/flag"));
+        assert_eq!(alien.options(&config).unwrap().jobs, 3);
+
+        let alien = Alien::try_parse_from(["alien", "zh"]).unwrap();
+        let options = alien.options(&Config::default()).unwrap();
+        assert_eq!(options.jobs, Options::default().jobs);
+        assert!(!options.durable);
+    }
+
+    #[test]
+    fn a_vanished_volume_is_told_apart_from_a_permission_error() {
+        let game_dir = std::env::temp_dir();
+        let io_error = |code| {
+            color_eyre::Report::new(std::io::Error::from_raw_os_error(code))
+                .wrap_err("Failed to back up [DATA/ENGLISH.TXT]")
+        };
+        for code in VOLUME_GONE_CODES {
+            assert!(is_volume_gone(&io_error(*code), &game_dir), "{}", code);
+        }
+        let denied =
+            color_eyre::Report::new(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert!(!is_volume_gone(&denied, &game_dir));
+
+        let unplugged = game_dir.join(format!("alien-unplugged-{}", std::process::id()));
+        let not_found =
+            || color_eyre::Report::new(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert!(is_volume_gone(&not_found(), &unplugged));
+        assert!(!is_volume_gone(&not_found(), &game_dir));
+        assert!(!is_volume_gone(
+            &color_eyre::eyre::eyre!("not an I/O error"),
+            &unplugged
+        ));
+    }
+
+    #[test]
+    fn explain_shows_the_game_dir_passed() {
+        let game_dir = std::env::temp_dir().join("alien-explain-game");
+        let game_dir = game_dir.display().to_string();
+        for format in ["text", "json"] {
+            let alien = Alien::try_parse_from([
+                "alien",
+                "--game-dir",
+                &game_dir,
+                "--backup-dir",
+                "backups",
+                "--format",
+                format,
+                "--explain",
+                "zh",
+            ])
+            .unwrap();
+            let installer = alien.installer(&Config::default()).unwrap();
+            let mut out = Vec::new();
+            alien.explain(&installer, &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            if format == "json" {
+                let explain = serde_json::from_str::<serde_json::Value>(&out).unwrap();
+                assert_eq!(explain["explain"]["game_dir"], game_dir.as_str());
+            } else {
+                let line = out
+                    .lines()
+                    .find(|line| line.starts_with("game dir"))
+                    .unwrap();
+                assert!(line.ends_with(&game_dir), "{}", out);
+            }
+        }
+    }
+
+    #[test]
+    fn info_reports_the_hash_of_the_embedded_pack() {
+        let info = build_info().unwrap();
+        let sha256 = hashing::hash_bytes(path_structure::LANGUAGE_ZIP_DATA);
+        assert_eq!(info.pack_sha256, hashing::to_hex(&sha256));
+        assert_eq!(build_info().unwrap().pack_sha256, info.pack_sha256);
+        let pack =
+            Manifest::read_from_bytes(path_structure::LANGUAGE_ZIP_DATA, Path::new(""), u64::MAX)
+                .unwrap();
+        assert_eq!(
+            info.pack_files,
+            pack.iter().filter(|item| item.is_file).count()
+        );
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    }
 }
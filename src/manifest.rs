@@ -1,14 +1,56 @@
-use crate::path_structure;
+use crate::backups::NoBackupError;
+use crate::error::AlienError;
+use crate::metadata::{BackupMetadata, METADATA_ENTRY};
+use crate::optional::OptionalFiles;
+use crate::report::ValidationReport;
+use crate::state::HashCache;
+use crate::{backups, descriptors, hashing, passphrase, path_structure, permissions, signing};
+use color_eyre::eyre::Context;
 use color_eyre::Result;
+use futures::StreamExt;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
-use std::io::{Cursor, Read, Seek};
+use std::io::{BufReader, Cursor, Read, Seek};
 use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use unicode_normalization::UnicodeNormalization;
 use zip::read::ZipFile;
+use zip::result::ZipError;
 use zip::ZipArchive;
 
 pub struct Manifest(Vec<ManifestItem>);
 
+/// Entries larger than this many bytes are left in the pack and streamed when written
+pub const DEFAULT_STREAM_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+/// Most bytes reserved up front for an entry, the size in its header may be a lie
+const MAX_CAPACITY_HINT: usize = 1024 * 1024;
+
+/// Optional files of packs that carry no `pack.toml`
+static WHITE_LIST: &str = include_str!("../assets/white_list.txt");
+
+/// Per-entry settings at the root of a pack, keyed by path or glob relative to the language dir:
+///
+/// ```toml
+/// [files."data/ui/fonts_cn.gfx"]
+/// optional = true
+/// ```
+pub static PACK_METADATA_ENTRY: &str = "pack.toml";
+
+#[derive(Debug, Default, Deserialize)]
+struct PackMetadata {
+    #[serde(default)]
+    files: BTreeMap<PathBuf, PackEntry>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PackEntry {
+    #[serde(default)]
+    optional: bool,
+}
+
 impl Deref for Manifest {
     type Target = Vec<ManifestItem>;
 
@@ -33,13 +75,36 @@ impl Display for Manifest {
 }
 
 impl Manifest {
-    pub fn new<T: Read + Seek>(mut archive: ZipArchive<T>) -> Result<Self> {
+    /// Reads a pack zip, leaving entries over `stream_threshold` bytes in it
+    pub fn new<T: Read + Seek>(archive: ZipArchive<T>, stream_threshold: u64) -> Result<Self> {
+        Self::read_all(archive, None, false, stream_threshold)
+    }
+
+    /// Reads a backup zip, decrypting its entries with `password`
+    pub fn with_password<T: Read + Seek>(
+        archive: ZipArchive<T>,
+        password: Option<&[u8]>,
+    ) -> Result<Self> {
+        Self::read_all(archive, password, true, u64::MAX)
+    }
+
+    fn read_all<T: Read + Seek>(
+        mut archive: ZipArchive<T>,
+        password: Option<&[u8]>,
+        backup: bool,
+        stream_threshold: u64,
+    ) -> Result<Self> {
         let len = archive.len();
         let items = (0..len)
             .into_iter()
             .map(|i| {
-                let file = archive.by_index(i)?;
-                Ok(ManifestItem::new(file))
+                let file = entry(&mut archive, i, password)?;
+                if file.is_file() && file.size() > stream_threshold {
+                    return Ok(
+                        ManifestItem::index(&file).map(|item| item.deferred(Deferred::Entry(i)))
+                    );
+                }
+                ManifestItem::new(file, backup)
             })
             .collect::<Result<Vec<_>>>()?
             .into_iter()
@@ -48,46 +113,406 @@ impl Manifest {
         Ok(Self(items))
     }
 
-    pub fn read_from_language_zip() -> Result<Manifest> {
+    /// Reads the embedded pack, with the optional files of the language dir `prefix` flagged
+    pub fn read_from_language_zip(prefix: &Path, stream_threshold: u64) -> Result<Manifest> {
         let instant = std::time::Instant::now();
         tracing::info!(
             "Read manifest from bytes: {}",
             path_structure::LANGUAGE_ZIP_DATA.len()
         );
+        let sha256 = hashing::to_hex(&hashing::hash_bytes(path_structure::LANGUAGE_ZIP_DATA));
+        if sha256 != path_structure::LANGUAGE_ZIP_SHA256 {
+            return Err(AlienError::EmbeddedPackCorrupt {
+                sha256,
+                expected: path_structure::LANGUAGE_ZIP_SHA256,
+            }
+            .into());
+        }
         let cursor = Cursor::new(path_structure::LANGUAGE_ZIP_DATA);
         let archive = ZipArchive::new(cursor)?;
-        let manifest = Manifest::new(archive)?;
+        let manifest = Manifest::new(archive, stream_threshold)?.mark_optional(prefix)?;
+        tracing::info!("Read manifest in {:?}", instant.elapsed());
+        Ok(manifest)
+    }
+
+    /// Reads the signed pack at `path` from `copy`, a private copy of it, refusing it before
+    /// parsing unless the copy checks out against the pack's signature with the embedded keys or
+    /// `extra_keys`. Entries over `stream_threshold` bytes are left in the copy.
+    pub fn read_from_signed_pack(
+        path: &Path,
+        copy: &Path,
+        allow_unsigned: bool,
+        extra_keys: &[String],
+        prefix: &Path,
+        stream_threshold: u64,
+    ) -> Result<Manifest> {
+        let instant = std::time::Instant::now();
+        tracing::info!("Read manifest from [{}]", path.display());
+        let digest = std::fs::File::open(copy)
+            .and_then(signing::pack_digest)
+            .with_context(|| format!("Failed to read [{}]", copy.display()))?;
+        signing::verify_pack(path, &digest, allow_unsigned, extra_keys)?;
+        let file = std::fs::File::open(copy)
+            .with_context(|| format!("Failed to open [{}]", copy.display()))?;
+        let archive = ZipArchive::new(BufReader::new(file))
+            .with_context(|| format!("[{}] is not a zip archive", path.display()))?;
+        let manifest = Manifest::new(archive, stream_threshold)?.mark_optional(prefix)?;
+        tracing::info!("Read manifest in {:?}", instant.elapsed());
+        Ok(manifest)
+    }
+
+    pub fn read_from_language_path(
+        path: &Path,
+        prefix: &Path,
+        stream_threshold: u64,
+    ) -> Result<Manifest> {
+        let instant = std::time::Instant::now();
+        tracing::info!("Read manifest from [{}]", path.display());
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open [{}]", path.display()))?;
+        let archive = ZipArchive::new(BufReader::new(file))
+            .with_context(|| format!("[{}] is not a zip archive", path.display()))?;
+        let manifest = Manifest::new(archive, stream_threshold)?.mark_optional(prefix)?;
         tracing::info!("Read manifest in {:?}", instant.elapsed());
         Ok(manifest)
     }
 
-    pub fn read_from_backup_zip() -> Result<Manifest> {
+    /// Reads a pack already in memory, such as one generated for a benchmark or a test
+    pub fn read_from_bytes(bytes: &[u8], prefix: &Path, stream_threshold: u64) -> Result<Manifest> {
         let instant = std::time::Instant::now();
-        let backup_zip = path_structure::backup_dir().join(path_structure::language_zip());
+        tracing::info!("Read manifest from bytes: {}", bytes.len());
+        let archive = ZipArchive::new(Cursor::new(bytes)).context("Pack is not a zip archive")?;
+        let manifest = Manifest::new(archive, stream_threshold)?.mark_optional(prefix)?;
+        tracing::info!("Read manifest in {:?}", instant.elapsed());
+        Ok(manifest)
+    }
+
+    pub fn from_dir(root: &Path, prefix: &Path, stream_threshold: u64) -> Result<Manifest> {
+        let instant = std::time::Instant::now();
+        tracing::info!("Read manifest from directory [{}]", root.display());
+        if !root.is_dir() {
+            return Err(AlienError::NotADirectory {
+                path: root.to_path_buf(),
+            }
+            .into());
+        }
+        let mut items = Vec::new();
+        walk_dir(root, PathBuf::new(), stream_threshold, &mut items)?;
+        tracing::info!("Read manifest in {:?}", instant.elapsed());
+        Self(items).mark_optional(prefix)
+    }
+
+    /// Reads the backup restores default to among those in `dir`
+    pub fn read_from_backup_dir(dir: &Path) -> Result<Manifest> {
+        let instant = std::time::Instant::now();
+        let backup_zip = backups::default_backup(dir)?.ok_or(AlienError::from(NoBackupError {
+            location: dir.to_path_buf(),
+        }))?;
+        if backups::is_clone_tree(&backup_zip) {
+            return Manifest::read_from_clone_tree(&backup_zip);
+        }
         tracing::info!("Read manifest from [{}]", backup_zip.display());
-        let cursor = Cursor::new(std::fs::read(backup_zip)?);
-        let archive = ZipArchive::new(cursor)?;
-        let manifest = Manifest::new(archive)?;
+        let file = std::fs::File::open(&backup_zip)
+            .with_context(|| format!("Failed to open backup [{}]", backup_zip.display()))?;
+        let archive = ZipArchive::new(BufReader::new(file))
+            .with_context(|| format!("[{}] is not a zip archive", backup_zip.display()))?;
+        let manifest = Manifest::read_backup(archive)?;
         tracing::info!("Read manifest in {:?}", instant.elapsed());
         Ok(manifest)
     }
 
-    pub fn filter_hans_dir(&mut self) -> Vec<(&mut ManifestItem, PathBuf)> {
+    pub fn read_from_backup_path(path: &Path) -> Result<Manifest> {
+        if backups::is_clone_tree(path) {
+            return Manifest::read_from_clone_tree(path);
+        }
         let instant = std::time::Instant::now();
-        let hans_dir = path_structure::hans_dir();
-        tracing::info!("Filtering for [{}]", hans_dir.display());
-        let filtered = self
-            .iter_mut()
-            .filter(|item| item.lowercase_name.starts_with(hans_dir))
-            .flat_map(|item| {
+        tracing::info!("Read manifest from [{}]", path.display());
+        let archive = open_backup(path)?;
+        let manifest = Manifest::read_backup(archive)?;
+        tracing::info!("Read manifest in {:?}", instant.elapsed());
+        Ok(manifest)
+    }
+
+    /// The entries of the backup at `path` without their bytes, except for the metadata, for
+    /// restoring with [`Manifest::stream_backup`]. Clone trees are read whole, restoring them
+    /// copies files rather than decompressing them.
+    pub fn index_backup(path: &Path) -> Result<Manifest> {
+        if backups::is_clone_tree(path) {
+            return Manifest::read_from_clone_tree(path);
+        }
+        let instant = std::time::Instant::now();
+        tracing::info!("Index backup [{}]", path.display());
+        let mut archive = open_backup(path)?;
+        let mut items = Vec::new();
+        for index in 0..archive.len() {
+            let is_metadata = archive.by_index_raw(index)?.name() == METADATA_ENTRY;
+            let item = if is_metadata {
+                ManifestItem::new(archive.by_index(index)?, true)?
+            } else {
+                ManifestItem::index(&archive.by_index_raw(index)?)
+            };
+            items.extend(item);
+        }
+        tracing::info!(
+            "Indexed {} entries take {:?}",
+            items.len(),
+            instant.elapsed()
+        );
+        Ok(Self(items))
+    }
+
+    /// Decompresses the entries of the backup zip at `path` whose path key is in `names`, one
+    /// at a time, handing each to `sink` before the next is read
+    pub fn stream_backup(
+        path: &Path,
+        names: &HashSet<PathBuf>,
+        mut sink: impl FnMut(ManifestItem) -> Result<()>,
+    ) -> Result<()> {
+        let instant = std::time::Instant::now();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open backup [{}]", path.display()))?;
+        let mut archive = ZipArchive::new(BufReader::new(file))
+            .with_context(|| format!("[{}] is not a zip archive", path.display()))?;
+        let password = if is_encrypted(&mut archive) {
+            Some(passphrase::backup_passphrase(false)?)
+        } else {
+            None
+        };
+        let mut streamed = 0;
+        for index in 0..archive.len() {
+            let selected = archive
+                .by_index_raw(index)?
+                .enclosed_name()
+                .is_some_and(|name| names.contains(&path_key(&name)));
+            if !selected {
+                continue;
+            }
+            let password = password.as_deref().map(str::as_bytes);
+            if let Some(item) = ManifestItem::new(entry(&mut archive, index, password)?, true)? {
+                sink(item)?;
+                streamed += 1;
+            }
+        }
+        tracing::info!(
+            "Streamed {} backup entries take {:?}",
+            streamed,
+            instant.elapsed()
+        );
+        Ok(())
+    }
+
+    pub fn read_from_clone_tree(root: &Path) -> Result<Manifest> {
+        let instant = std::time::Instant::now();
+        tracing::info!("Read manifest from clone tree [{}]", root.display());
+        let metadata =
+            BackupMetadata::read_from_path(root)?.ok_or_else(|| AlienError::NotABackup {
+                path: root.to_path_buf(),
+            })?;
+        tracing::info!("Backup {}", metadata.describe());
+        let mut items = backups::clone_tree_files(root)?
+            .into_iter()
+            .map(|striped| ManifestItem::read_from_file(root, striped, u64::MAX))
+            .collect::<Result<Vec<_>>>()?;
+        let bytes = metadata.to_bytes()?;
+        items.push(ManifestItem {
+            path: PathBuf::from(METADATA_ENTRY),
+            lowercase_name: PathBuf::from(METADATA_ENTRY),
+            crc32: crc32fast::hash(&bytes),
+            size: bytes.len() as u64,
+            bytes,
+            deferred: None,
+            is_file: true,
+            is_dir: false,
+            modified: None,
+            optional: false,
+            mode: None,
+        });
+        tracing::info!("Read manifest in {:?}", instant.elapsed());
+        Ok(Self(items))
+    }
+
+    fn read_backup<T: Read + Seek>(mut archive: ZipArchive<T>) -> Result<Manifest> {
+        let password = if is_encrypted(&mut archive) {
+            tracing::info!("Backup is encrypted");
+            Some(passphrase::backup_passphrase(false)?)
+        } else {
+            None
+        };
+        Manifest::with_password(archive, password.as_deref().map(str::as_bytes))
+    }
+
+    /// Flags the entries of the language dir `prefix` that `pack.toml` marks optional, or those
+    /// of the built-in white list
+    fn mark_optional(mut self, prefix: &Path) -> Result<Self> {
+        let metadata = self
+            .iter()
+            .find(|item| item.lowercase_name == Path::new(PACK_METADATA_ENTRY));
+        let optional = match metadata {
+            Some(item) => {
+                let text = std::str::from_utf8(&item.bytes)
+                    .with_context(|| format!("{} is not UTF-8", PACK_METADATA_ENTRY))?;
+                let files = toml::from_str::<PackMetadata>(text)
+                    .with_context(|| format!("Failed to parse {}", PACK_METADATA_ENTRY))?
+                    .files;
+                let patterns = files
+                    .iter()
+                    .filter(|(_, entry)| entry.optional)
+                    .map(|(pattern, _)| pattern.display().to_string())
+                    .collect::<Vec<_>>();
+                OptionalFiles::new(patterns.iter().map(String::as_str))?
+            }
+            None => OptionalFiles::new(WHITE_LIST.lines())?,
+        };
+        for (index, striped) in self.hans_plan(prefix) {
+            let pattern = optional.matching(&striped);
+            if let Some(pattern) = pattern {
+                tracing::debug!("[{}] is optional by [{}]", striped.display(), pattern);
+            }
+            self[index].optional = pattern.is_some();
+        }
+        Ok(self)
+    }
+
+    /// Uncompressed size of every file in the pack
+    pub fn bytes(&self) -> u64 {
+        self.iter().map(|item| item.size).sum()
+    }
+
+    /// Identifies the pack by its files and their CRC32, so a zip and the same files in a
+    /// directory share it
+    pub fn version(&self) -> String {
+        let mut files = self
+            .iter()
+            .filter(|item| item.is_file)
+            .map(|item| (&item.lowercase_name, item.crc32))
+            .collect::<Vec<_>>();
+        files.sort();
+        let mut hasher = crc32fast::Hasher::new();
+        for (name, crc32) in files {
+            hasher.update(name.as_os_str().as_encoded_bytes());
+            hasher.update(&crc32.to_le_bytes());
+        }
+        format!("{:08x}", hasher.finalize())
+    }
+
+    /// Index and path below `hans_dir` of every entry of the language dir `hans_dir`
+    pub fn hans_plan(&self, hans_dir: &Path) -> Vec<(usize, PathBuf)> {
+        self.iter()
+            .enumerate()
+            .flat_map(|(index, item)| {
                 let striped = item
                     .lowercase_name
                     .strip_prefix(hans_dir)
                     .ok()?
                     .to_path_buf();
-                Some((item, striped))
+                Some((index, striped))
             })
             .filter(|(_, striped)| striped.components().count() > 0)
+            .collect()
+    }
+
+    /// Compares every pack file of the language dir `prefix` by size and CRC32 with the file
+    /// `resolve` maps it to, reading at most `jobs` files at a time. Files whose size and mtime
+    /// match an entry of `cache` are not read again, the cache learns the CRC32 of every file
+    /// read.
+    pub async fn validate_against_disk(
+        &self,
+        prefix: &Path,
+        resolve: &(dyn Fn(&Path) -> PathBuf + Sync),
+        jobs: usize,
+        mut cache: Option<&mut HashCache>,
+    ) -> Result<ValidationReport> {
+        let instant = std::time::Instant::now();
+        let plan = self
+            .hans_plan(prefix)
+            .into_iter()
+            .filter(|(index, _)| self[*index].is_file)
+            .collect::<Vec<_>>();
+        let count = plan.len();
+        let step = (count / 10).max(1);
+        let lookup = cache.as_deref();
+        let mut checks =
+            futures::stream::iter(plan.into_iter().map(|(index, striped)| async move {
+                let item = &self[index];
+                let path = resolve(&striped);
+                let metadata = match tokio::fs::metadata(&path).await {
+                    Ok(metadata) => metadata,
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                        return Ok((striped, FileState::Missing, None))
+                    }
+                    Err(error) => {
+                        return Err(error)
+                            .with_context(|| format!("Failed to read [{}]", path.display()))
+                    }
+                };
+                if metadata.len() != item.size {
+                    return Ok((striped, FileState::Differs, None));
+                }
+                let modified = metadata.modified().ok();
+                let cached = modified.and_then(|modified| {
+                    lookup.and_then(|cache| cache.get(&striped, metadata.len(), modified))
+                });
+                let (crc32, learned) = match cached {
+                    Some(crc32) => (crc32, None),
+                    None => {
+                        let crc32 = crc32_file(path).await?;
+                        (
+                            crc32,
+                            modified.map(|modified| (metadata.len(), modified, crc32)),
+                        )
+                    }
+                };
+                let state = if crc32 == item.crc32 {
+                    FileState::Matches
+                } else {
+                    FileState::Differs
+                };
+                Ok::<_, color_eyre::Report>((striped, state, learned))
+            }))
+            .buffer_unordered(jobs.max(1));
+        let mut report = ValidationReport::default();
+        let mut learned = Vec::new();
+        let mut done = 0;
+        while let Some(check) = checks.next().await {
+            let (striped, state, hash) = check?;
+            done += 1;
+            if done % step == 0 {
+                tracing::info!("Validated {}/{} files", done, count);
+            }
+            if let Some(hash) = hash {
+                learned.push((striped.clone(), hash));
+            }
+            match state {
+                FileState::Matches => report.matched += 1,
+                FileState::Differs => report.mismatched.push(striped),
+                FileState::Missing => report.missing.push(striped),
+            }
+        }
+        drop(checks);
+        if let Some(cache) = cache.as_deref_mut() {
+            for (striped, (size, modified, crc32)) in learned {
+                cache.insert(striped, size, modified, crc32);
+            }
+        }
+        report.mismatched.sort();
+        report.missing.sort();
+        tracing::info!("Validated {} files take {:?}", count, instant.elapsed());
+        Ok(report)
+    }
+
+    pub fn filter_hans_dir(&mut self, hans_dir: &Path) -> Vec<(&mut ManifestItem, PathBuf)> {
+        let instant = std::time::Instant::now();
+        tracing::info!("Filtering for [{}]", hans_dir.display());
+        let mut plan = self.hans_plan(hans_dir).into_iter().peekable();
+        let filtered = self
+            .iter_mut()
+            .enumerate()
+            .flat_map(|(index, item)| match plan.peek() {
+                Some((next, _)) if *next == index => {
+                    plan.next().map(|(_, striped)| (item, striped))
+                }
+                _ => None,
+            })
             .collect::<Vec<_>>();
         tracing::info!(
             "Filtered {} items in {:?}",
@@ -98,32 +523,288 @@ impl Manifest {
     }
 }
 
+/// CRC32 of a file, read and hashed in chunks on the blocking pool so hashing runs on as many
+/// threads as files are validated at once
+pub(crate) async fn crc32_file(path: PathBuf) -> Result<u32> {
+    let permit = descriptors::acquire().await?;
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("Failed to read [{}]", path.display()))?;
+        crc32_reader(file).with_context(|| format!("Failed to read [{}]", path.display()))
+    })
+    .await?
+}
+
+/// CRC32 of everything `reader` yields, read in chunks
+pub fn crc32_reader(mut reader: impl Read) -> std::io::Result<u32> {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&chunk[..read]);
+    }
+}
+
+/// Opens the entry `index` of the pack zip `reader` and hands it to `read`, which reading to the
+/// end makes zip check its CRC32
+pub fn read_entry<T: Read + Seek, R>(
+    reader: T,
+    index: usize,
+    read: impl FnOnce(&mut dyn Read) -> Result<R>,
+) -> Result<R> {
+    let mut archive = ZipArchive::new(reader).context("Pack is not a zip archive")?;
+    let mut file = archive.by_index(index)?;
+    read(&mut file)
+}
+
+/// Collects entries below `root` in the zip's order, each directory ahead of its contents
+fn walk_dir(
+    root: &Path,
+    relative: PathBuf,
+    stream_threshold: u64,
+    items: &mut Vec<ManifestItem>,
+) -> Result<()> {
+    let dir = root.join(&relative);
+    let mut entries = std::fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read [{}]", dir.display()))?
+        .map(|entry| Ok(entry?))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let striped = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            items.push(ManifestItem {
+                lowercase_name: path_key(&striped),
+                path: striped.clone(),
+                bytes: Vec::new(),
+                size: 0,
+                deferred: None,
+                is_file: false,
+                is_dir: true,
+                modified: entry.metadata()?.modified().ok(),
+                crc32: 0,
+                optional: false,
+                mode: None,
+            });
+            walk_dir(root, striped, stream_threshold, items)?;
+        } else {
+            items.push(ManifestItem::read_from_file(
+                root,
+                striped,
+                stream_threshold,
+            )?);
+        }
+    }
+    Ok(())
+}
+
+fn is_legacy_backup<T: Read + Seek>(archive: &ZipArchive<T>) -> bool {
+    let data_dir = path_structure::data_dir().display().to_string();
+    archive.len() > 0
+        && archive
+            .file_names()
+            .all(|name| name.to_lowercase().starts_with(&data_dir))
+}
+
+/// Opens a backup zip, checking that it carries alien's metadata or looks like a legacy backup
+fn open_backup(path: &Path) -> Result<ZipArchive<BufReader<std::fs::File>>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(AlienError::from(NoBackupError {
+                location: path.to_path_buf(),
+            })
+            .into())
+        }
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to open backup [{}]", path.display()))
+        }
+    };
+    let mut archive = ZipArchive::new(BufReader::new(file))
+        .with_context(|| format!("[{}] is not a zip archive", path.display()))?;
+    match BackupMetadata::read_from_archive(&mut archive)? {
+        Some(metadata) => tracing::info!("Backup {}", metadata.describe()),
+        None if is_legacy_backup(&archive) => {
+            tracing::warn!("Backup has no metadata, assuming a legacy backup")
+        }
+        None => {
+            return Err(AlienError::NotABackup {
+                path: path.to_path_buf(),
+            }
+            .into())
+        }
+    }
+    Ok(archive)
+}
+
+/// Entry `index` of the archive, decrypted with `password` if it is encrypted
+fn entry<'a, T: Read + Seek>(
+    archive: &'a mut ZipArchive<T>,
+    index: usize,
+    password: Option<&[u8]>,
+) -> Result<ZipFile<'a>> {
+    let encrypted = archive.by_index_raw(index)?.encrypted();
+    match password {
+        Some(password) if encrypted => {
+            archive
+                .by_index_decrypt(index, password)
+                .map_err(|error| match error {
+                    ZipError::InvalidPassword => AlienError::WrongPassphrase.into(),
+                    error => error.into(),
+                })
+        }
+        _ => Ok(archive.by_index(index)?),
+    }
+}
+
+fn is_encrypted<T: Read + Seek>(archive: &mut ZipArchive<T>) -> bool {
+    (0..archive.len()).any(|i| {
+        archive
+            .by_index_raw(i)
+            .map(|file| file.encrypted())
+            .unwrap_or(false)
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileState {
+    Matches,
+    Differs,
+    Missing,
+}
+
+/// Where the bytes of an entry left out of memory are read from when it is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Deferred {
+    /// Index of the entry in the pack zip
+    Entry(usize),
+    /// File of a pack directory
+    File(PathBuf),
+}
+
 pub struct ManifestItem {
     pub path: PathBuf,
     pub lowercase_name: PathBuf,
+    /// Empty for directories and for entries that are [deferred](ManifestItem::deferred)
     pub bytes: Vec<u8>,
+    /// Uncompressed size of the entry
+    pub size: u64,
+    /// Set for entries over the stream threshold, which are read when written instead
+    pub deferred: Option<Deferred>,
     pub is_file: bool,
     pub is_dir: bool,
+    pub modified: Option<SystemTime>,
+    pub crc32: u32,
+    /// The game may lack this file, set from the pack's metadata
+    pub optional: bool,
+    /// Unix mode bits the entry was stored with
+    pub mode: Option<u32>,
 }
 
 impl ManifestItem {
-    fn new(file: ZipFile<'_>) -> Option<Self> {
-        let is_file = file.is_file();
-        let is_dir = file.is_dir();
+    /// Reads an entry to the end, which makes zip check its CRC32. A corrupt entry is
+    /// [AlienError::BackupCorrupt] in a `backup`, [AlienError::PackCorrupt] in a pack.
+    fn new(mut file: ZipFile<'_>, backup: bool) -> Result<Option<Self>> {
+        let mut item = match Self::index(&file) {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        // Sized up front, growing the buffer would briefly hold large entries twice
+        item.bytes = Vec::with_capacity((file.size() as usize).min(MAX_CAPACITY_HINT));
+        if let Err(source) = file.read_to_end(&mut item.bytes) {
+            let entry = item.path.clone();
+            return Err(match backup {
+                true => AlienError::BackupCorrupt { entry, source },
+                false => AlienError::PackCorrupt { entry, source },
+            }
+            .into());
+        }
+        Ok(Some(item))
+    }
+
+    /// Everything about an entry but its bytes, which are left empty
+    fn index(file: &ZipFile<'_>) -> Option<Self> {
         let path = file.enclosed_name()?;
-        let lowercase_name = PathBuf::from(path.display().to_string().to_lowercase());
-        let bytes = file
-            .bytes()
-            .into_iter()
-            .map(|it| Ok(it?))
-            .collect::<Result<Vec<_>>>()
-            .ok()?;
         Some(Self {
+            lowercase_name: path_key(&path),
             path,
-            lowercase_name,
+            bytes: Vec::new(),
+            size: file.size(),
+            deferred: None,
+            is_file: file.is_file(),
+            is_dir: file.is_dir(),
+            modified: file.last_modified().and_then(from_zip_datetime),
+            crc32: file.crc32(),
+            optional: false,
+            mode: file.unix_mode(),
+        })
+    }
+
+    /// Compares a file on disk with this entry, by size first and then by CRC32
+    pub async fn check_file(&self, path: &Path) -> std::io::Result<FileState> {
+        match tokio::fs::metadata(path).await {
+            Ok(metadata) if metadata.len() != self.size => return Ok(FileState::Differs),
+            Ok(_) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(FileState::Missing)
+            }
+            Err(error) => return Err(error),
+        }
+        let permit = descriptors::acquire().await?;
+        let path = path.to_path_buf();
+        let crc32 = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            crc32_reader(std::fs::File::open(path)?)
+        })
+        .await??;
+        if crc32 == self.crc32 {
+            Ok(FileState::Matches)
+        } else {
+            Ok(FileState::Differs)
+        }
+    }
+
+    /// Leaves the bytes where `deferred` says instead of in memory
+    fn deferred(mut self, deferred: Deferred) -> Self {
+        self.bytes = Vec::new();
+        self.deferred = Some(deferred);
+        self
+    }
+
+    /// Reads a file below `root`, one over `stream_threshold` bytes is only hashed
+    fn read_from_file(root: &Path, striped: PathBuf, stream_threshold: u64) -> Result<Self> {
+        let path = root.join(&striped);
+        let metadata = std::fs::metadata(&path)
+            .with_context(|| format!("Failed to read [{}]", path.display()))?;
+        let (bytes, crc32, deferred) = if metadata.len() > stream_threshold {
+            let file = std::fs::File::open(&path)
+                .with_context(|| format!("Failed to read [{}]", path.display()))?;
+            let crc32 = crc32_reader(file)
+                .with_context(|| format!("Failed to read [{}]", path.display()))?;
+            (Vec::new(), crc32, Some(Deferred::File(path.clone())))
+        } else {
+            let bytes = std::fs::read(&path)
+                .with_context(|| format!("Failed to read [{}]", path.display()))?;
+            let crc32 = crc32fast::hash(&bytes);
+            (bytes, crc32, None)
+        };
+        Ok(Self {
+            lowercase_name: path_key(&striped),
+            path: striped,
+            crc32,
+            size: metadata.len(),
             bytes,
-            is_file,
-            is_dir,
+            deferred,
+            is_file: true,
+            is_dir: false,
+            modified: metadata.modified().ok(),
+            optional: false,
+            mode: permissions::mode(&metadata),
         })
     }
 }
@@ -141,3 +822,252 @@ impl Display for ManifestItem {
         write!(f, "[{}] {}", flag, self.path.display())
     }
 }
+
+/// Lowercase NFC form of a path, so composed zip names match decomposed names from macOS
+pub fn path_key(path: &Path) -> PathBuf {
+    PathBuf::from(
+        path.display()
+            .to_string()
+            .nfc()
+            .collect::<String>()
+            .to_lowercase(),
+    )
+}
+
+/// Form the platform stores names in, decomposed on macOS and composed elsewhere
+pub fn platform_form(path: &Path) -> PathBuf {
+    let path = path.display().to_string();
+    if cfg!(target_os = "macos") {
+        PathBuf::from(path.nfd().collect::<String>())
+    } else {
+        PathBuf::from(path.nfc().collect::<String>())
+    }
+}
+
+pub fn to_zip_datetime(time: SystemTime) -> Option<zip::DateTime> {
+    let time = time::OffsetDateTime::from(time);
+    zip::DateTime::from_date_and_time(
+        u16::try_from(time.year()).ok()?,
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+    )
+    .ok()
+}
+
+pub fn from_zip_datetime(datetime: zip::DateTime) -> Option<SystemTime> {
+    let date = time::Date::from_calendar_date(
+        i32::from(datetime.year()),
+        time::Month::try_from(datetime.month()).ok()?,
+        datetime.day(),
+    )
+    .ok()?;
+    let time = time::Time::from_hms(datetime.hour(), datetime.minute(), datetime.second()).ok()?;
+    Some(SystemTime::from(
+        time::PrimitiveDateTime::new(date, time).assume_utc(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bench, languages};
+    use std::io::Write;
+
+    fn prefix() -> &'static Path {
+        languages::LANGUAGES[0].prefix()
+    }
+
+    #[test]
+    fn truncated_pack_is_an_error() {
+        let pack = bench::fixture(prefix(), 4, 4096).unwrap();
+        for len in [0, 22, pack.len() / 2, pack.len() - 1] {
+            let result = Manifest::read_from_bytes(&pack[..len], prefix(), u64::MAX);
+            assert!(result.is_err(), "read a pack truncated to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn corrupt_entry_is_a_typed_error() {
+        let mut pack = bench::fixture(prefix(), 4, 4096).unwrap();
+        for byte in &mut pack[200..240] {
+            *byte ^= 0xff;
+        }
+        let error = Manifest::read_from_bytes(&pack, prefix(), u64::MAX)
+            .err()
+            .expect("read a corrupt pack");
+        assert!(
+            matches!(
+                AlienError::find(&error),
+                Some(AlienError::PackCorrupt { .. })
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn the_index_plan_resolves_to_the_filtered_items() {
+        let mut buffer = std::io::Cursor::new(Vec::new());
+        let mut archive = zip::ZipWriter::new(&mut buffer);
+        let options = zip::write::SimpleFileOptions::default();
+        let prefix_name = prefix().display().to_string();
+        archive
+            .add_directory(format!("{}/", prefix_name), options)
+            .unwrap();
+        archive
+            .add_directory(format!("{}/DATA/UI/", prefix_name), options)
+            .unwrap();
+        for (name, bytes) in [
+            ("DATA/UI/Menu.txt", "menu"),
+            ("DATA/ENV/Level.txt", "level"),
+        ] {
+            archive
+                .start_file(format!("{}/{}", prefix_name, name), options)
+                .unwrap();
+            archive.write_all(bytes.as_bytes()).unwrap();
+        }
+        archive.start_file("readme.txt", options).unwrap();
+        archive.write_all(b"outside the language dir").unwrap();
+        archive.finish().unwrap();
+        let mut manifest =
+            Manifest::read_from_bytes(&buffer.into_inner(), prefix(), u64::MAX).unwrap();
+
+        let plan = manifest
+            .hans_plan(prefix())
+            .into_iter()
+            .map(|(index, striped)| (manifest[index].lowercase_name.clone(), striped))
+            .collect::<Vec<_>>();
+        let filtered = manifest
+            .filter_hans_dir(prefix())
+            .into_iter()
+            .map(|(item, striped)| (item.lowercase_name.clone(), striped))
+            .collect::<Vec<_>>();
+        assert_eq!(plan, filtered);
+        assert_eq!(
+            plan.iter().map(|(_, striped)| striped).collect::<Vec<_>>(),
+            [
+                Path::new("data/ui"),
+                Path::new("data/ui/menu.txt"),
+                Path::new("data/env/level.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn an_unpacked_pack_reads_like_the_zip() {
+        let scratch = crate::installer::tests::scratch_dir("from-dir");
+        let pack = bench::fixture(prefix(), 6, 4096).unwrap();
+        zip::ZipArchive::new(Cursor::new(&pack))
+            .unwrap()
+            .extract(&scratch)
+            .unwrap();
+        let files = |mut manifest: Manifest| {
+            manifest
+                .filter_hans_dir(prefix())
+                .into_iter()
+                .filter(|(item, _)| item.is_file)
+                .map(|(item, striped)| (striped, (item.crc32, item.size)))
+                .collect::<std::collections::BTreeMap<_, _>>()
+        };
+
+        let zipped = files(Manifest::read_from_bytes(&pack, prefix(), u64::MAX).unwrap());
+        assert_eq!(zipped.len(), 6);
+        let unpacked = files(Manifest::from_dir(&scratch, prefix(), u64::MAX).unwrap());
+        assert_eq!(unpacked, zipped);
+        // Over the threshold the files are only checksummed, the sums have to agree all the same
+        let deferred = files(Manifest::from_dir(&scratch, prefix(), 0).unwrap());
+        assert_eq!(deferred, zipped);
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[tokio::test]
+    async fn validation_finds_truncated_flipped_and_missing_files() {
+        let root = crate::installer::tests::scratch_dir("validate-disk");
+        let pack = bench::fixture(prefix(), 6, 4096).unwrap();
+        let mut manifest = Manifest::read_from_bytes(&pack, prefix(), u64::MAX).unwrap();
+        let mut files = Vec::new();
+        for (item, striped) in manifest.filter_hans_dir(prefix()) {
+            if item.is_file {
+                let path = root.join(&striped);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, &item.bytes).unwrap();
+                files.push(striped);
+            }
+        }
+        files.sort();
+        let file = |index: usize| root.join(&files[index]);
+        let mut truncated = std::fs::read(file(0)).unwrap();
+        truncated.pop();
+        std::fs::write(file(0), truncated).unwrap();
+        let mut flipped = std::fs::read(file(1)).unwrap();
+        flipped[100] ^= 0x01;
+        std::fs::write(file(1), flipped).unwrap();
+        std::fs::remove_file(file(2)).unwrap();
+
+        let resolve = |striped: &Path| root.join(striped);
+        let report = manifest
+            .validate_against_disk(prefix(), &resolve, 2, None)
+            .await
+            .unwrap();
+        assert_eq!(report.matched, 3);
+        assert_eq!(report.mismatched, files[..2]);
+        assert_eq!(report.missing, files[2..3]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn reads_the_default_backup_through_a_file_handle() {
+        use crate::installer::tests::{fixture_installer, original, original_name};
+        let installer = fixture_installer("backup-handle", 4, 64);
+        let mut manifest = installer.load_pack().await.unwrap();
+        crate::installer::tests::stage_and_install(&installer, &mut manifest)
+            .await
+            .unwrap();
+
+        let backup = Manifest::read_from_backup_dir(installer.backup_dir()).unwrap();
+        for index in 0..4 {
+            let item = backup
+                .iter()
+                .find(|item| item.lowercase_name == Path::new(&original_name(index)))
+                .unwrap();
+            assert_eq!(item.bytes, original(index));
+        }
+        let backup_zip = backups::default_backup(installer.backup_dir())
+            .unwrap()
+            .unwrap();
+        std::fs::write(&backup_zip, b"not a zip").unwrap();
+        let error = Manifest::read_from_backup_path(&backup_zip).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("is not a zip archive"),
+            "{:#}",
+            error
+        );
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[test]
+    fn a_decomposed_path_matches_its_composed_entry() {
+        let composed = Path::new("DATA/Caf\u{e9}.txt");
+        let decomposed = Path::new("data/cafe\u{301}.txt");
+        assert_ne!(composed.as_os_str().len(), decomposed.as_os_str().len());
+        assert_eq!(path_key(composed), path_key(decomposed));
+        let optional = crate::optional::OptionalFiles::new(["data/caf\u{e9}.txt"]).unwrap();
+        assert!(optional.matching(decomposed).is_some());
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut archive = zip::ZipWriter::new(&mut buffer);
+        let name = prefix().join(decomposed).display().to_string();
+        archive
+            .start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        archive.write_all(b"caf\xc3\xa9").unwrap();
+        archive.finish().unwrap();
+        let manifest = Manifest::read_from_bytes(&buffer.into_inner(), prefix(), u64::MAX).unwrap();
+        let plan = manifest.hans_plan(prefix());
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].1, path_key(composed));
+    }
+}
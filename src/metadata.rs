@@ -0,0 +1,151 @@
+use crate::manifest::Manifest;
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use zip::ZipArchive;
+
+pub static METADATA_ENTRY: &str = "alien-metadata.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BackupMetadata {
+    pub created: Vec<PathBuf>,
+    pub overwritten: Vec<PathBuf>,
+    #[serde(default)]
+    pub already_patched: Vec<PathBuf>,
+    #[serde(default)]
+    pub encrypted: bool,
+    #[serde(default)]
+    pub game_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub taken_at: Option<SystemTime>,
+    #[serde(default)]
+    pub pack_version: Option<String>,
+    #[serde(default)]
+    pub original: bool,
+    /// SHA-256 of each overwritten original, hex encoded
+    #[serde(default)]
+    pub checksums: BTreeMap<PathBuf, String>,
+    /// Exact modification time of each overwritten original in a zip backup, whose entries only
+    /// carry a DOS time to two seconds. Older backups lack it and fall back to the entry's time.
+    #[serde(default)]
+    pub modified: BTreeMap<PathBuf, SystemTime>,
+}
+
+impl BackupMetadata {
+    pub fn record_modified(&mut self, striped: &Path, modified: Option<SystemTime>) {
+        match modified {
+            Some(modified) => self.modified.insert(striped.to_path_buf(), modified),
+            None => self.modified.remove(striped),
+        };
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn read_from_archive<T: Read + Seek>(archive: &mut ZipArchive<T>) -> Result<Option<Self>> {
+        let file = match archive.by_name(METADATA_ENTRY) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let metadata = serde_json::from_reader(file).context("Failed to parse backup metadata")?;
+        Ok(Some(metadata))
+    }
+
+    pub fn read_from_path(path: &Path) -> Result<Option<Self>> {
+        if path.is_dir() {
+            let path = path.join(METADATA_ENTRY);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let bytes = std::fs::read(&path)
+                .with_context(|| format!("Failed to read [{}]", path.display()))?;
+            let metadata =
+                serde_json::from_slice(&bytes).context("Failed to parse backup metadata")?;
+            return Ok(Some(metadata));
+        }
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open backup [{}]", path.display()))?;
+        let mut archive = ZipArchive::new(std::io::BufReader::new(file))?;
+        BackupMetadata::read_from_archive(&mut archive)
+    }
+
+    pub fn describe(&self) -> String {
+        let taken_at = self
+            .taken_at
+            .map(|taken_at| time::OffsetDateTime::from(taken_at).to_string())
+            .unwrap_or_else(|| "unknown date".to_string());
+        let game_dir = self
+            .game_dir
+            .as_ref()
+            .map(|game_dir| game_dir.display().to_string())
+            .unwrap_or_else(|| "unknown game dir".to_string());
+        format!(
+            "taken at {} from [{}], {} overwritten and {} created files",
+            taken_at,
+            game_dir,
+            self.overwritten.len(),
+            self.created.len()
+        )
+    }
+
+    /// Text listing of the backup in the `[F] path` form of a manifest, with the size and
+    /// SHA-256 of each original and the files the restore deletes
+    pub fn listing(&self, sizes: &BTreeMap<PathBuf, u64>) -> String {
+        let mut listing = format!("# Backup {}\n", self.describe());
+        for path in &self.overwritten {
+            let size = sizes
+                .get(path)
+                .map(|size| format!("{} bytes", size))
+                .unwrap_or_else(|| "unknown size".to_string());
+            let checksum = self.checksums.get(path).map(String::as_str).unwrap_or("-");
+            listing.push_str(&format!(
+                "[F] {}  {}  sha256 {}\n",
+                path.display(),
+                size,
+                checksum
+            ));
+        }
+        for path in &self.created {
+            listing.push_str(&format!("[C] {}  created by the pack\n", path.display()));
+        }
+        for path in &self.already_patched {
+            listing.push_str(&format!(
+                "[P] {}  already patched, not backed up\n",
+                path.display()
+            ));
+        }
+        listing
+    }
+
+    pub fn find_in(manifest: &Manifest) -> Result<Option<Self>> {
+        match manifest
+            .iter()
+            .find(|item| item.path == Path::new(METADATA_ENTRY))
+        {
+            Some(item) => Ok(Some(
+                serde_json::from_slice(&item.bytes).context("Failed to parse backup metadata")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub fn take_from(manifest: &mut Manifest) -> Result<Option<Self>> {
+        let index = match manifest
+            .iter()
+            .position(|item| item.path == Path::new(METADATA_ENTRY))
+        {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let item = manifest.remove(index);
+        let metadata =
+            serde_json::from_slice(&item.bytes).context("Failed to parse backup metadata")?;
+        Ok(Some(metadata))
+    }
+}
@@ -0,0 +1,104 @@
+use crate::manifest;
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Patterns of pack files the game may lack, matched ignoring case and separators.
+/// `*` stays within a directory, `**` spans directories, plain paths match exactly.
+pub struct OptionalFiles {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl OptionalFiles {
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let patterns = patterns
+            .into_iter()
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty() && !pattern.starts_with('#'))
+            .map(normalize)
+            .collect::<Vec<_>>();
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(
+                GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .with_context(|| format!("Invalid optional file pattern [{}]", pattern))?,
+            );
+        }
+        Ok(Self {
+            patterns,
+            set: builder.build()?,
+        })
+    }
+
+    /// The first pattern matching `striped`, if any
+    pub fn matching(&self, striped: &Path) -> Option<&str> {
+        let path = normalize(&striped.display().to_string());
+        self.set
+            .matches(&path)
+            .first()
+            .map(|index| self.patterns[*index].as_str())
+    }
+}
+
+fn normalize(path: &str) -> String {
+    manifest::path_key(Path::new(&path.replace('\\', "/")))
+        .display()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OptionalFiles;
+    use std::path::Path;
+
+    fn matching<'a>(optional: &'a OptionalFiles, path: &str) -> Option<&'a str> {
+        optional.matching(Path::new(path))
+    }
+
+    #[test]
+    fn exact_entries_keep_matching() {
+        let optional = OptionalFiles::new(["data/ui/fonts_cn.gfx"]).unwrap();
+        assert_eq!(
+            matching(&optional, "data/ui/fonts_cn.gfx"),
+            Some("data/ui/fonts_cn.gfx")
+        );
+        assert_eq!(matching(&optional, "data/ui/fonts_cn.gfx.bak"), None);
+        assert_eq!(matching(&optional, "data/ui"), None);
+    }
+
+    #[test]
+    fn matches_ignoring_case_and_separators() {
+        let optional = OptionalFiles::new(["Data\\UI\\Fonts_CN.gfx"]).unwrap();
+        assert!(matching(&optional, "DATA/ui/fonts_cn.GFX").is_some());
+    }
+
+    #[test]
+    fn a_single_star_stays_within_its_directory() {
+        let optional = OptionalFiles::new(["data/dlc/*/text/*.txt"]).unwrap();
+        assert!(matching(&optional, "data/dlc/salvage/text/m0101.txt").is_some());
+        assert!(matching(&optional, "data/dlc/salvage/text/english/m0101.txt").is_none());
+        assert!(matching(&optional, "data/dlc/salvage/text/m0101.bin").is_none());
+    }
+
+    #[test]
+    fn a_double_star_spans_directories() {
+        let optional = OptionalFiles::new(["data/dlc/**/*.txt"]).unwrap();
+        assert_eq!(
+            matching(&optional, "data/dlc/salvage/text/english/m0101.txt"),
+            Some("data/dlc/**/*.txt")
+        );
+        assert!(matching(&optional, "data/env/m0101.txt").is_none());
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_not_patterns() {
+        let optional = OptionalFiles::new("# dlc\n\n  data/a.txt  \n".lines()).unwrap();
+        assert!(matching(&optional, "data/a.txt").is_some());
+        assert!(matching(&optional, "# dlc").is_none());
+        assert!(OptionalFiles::new(["data/[a.txt"]).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+static PASSPHRASE_ENV: &str = "ALIEN_BACKUP_PASSWORD";
+
+pub fn backup_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Backup passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(eyre!("Backup passphrase must not be empty"));
+    }
+    if confirm && rpassword::prompt_password("Confirm passphrase: ")? != passphrase {
+        return Err(eyre!("Passphrases do not match"));
+    }
+    Ok(passphrase)
+}
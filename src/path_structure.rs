@@ -1,41 +1,36 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-static CARGO_MANIFEST_DIR: &str = env!("CARGO_MANIFEST_DIR");
+pub static LANGUAGE_ZIP_DATA: &[u8] = include_bytes!("../assets/language/language.zip");
 
-pub(crate) static LANGUAGE_ZIP_DATA: &[u8] = include_bytes!("../assets/language/language.zip");
+/// SHA-256 of the language pack, computed by build.rs
+pub static LANGUAGE_ZIP_SHA256: &str = env!("LANGUAGE_ZIP_SHA256");
 
-pub fn project_dir() -> &'static Path {
-    Path::new(CARGO_MANIFEST_DIR)
-}
-
-pub fn assets_dir() -> &'static Path {
-    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
-}
-
-pub fn backup_dir() -> &'static Path {
-    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/backup"))
-}
-
-pub fn language_zip() -> &'static Path {
-    Path::new("language.zip")
-}
-
-pub fn temporary_dir() -> &'static Path {
-    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "temporary"))
-}
-
-pub fn language_dir() -> &'static Path {
-    Path::new("language")
+pub fn data_dir() -> &'static Path {
+    Path::new("data")
 }
 
-pub fn hans_dir() -> &'static Path {
-    Path::new("language/zh_cn_hans")
+/// Sibling of the game dir, so committing the stage is a rename on the same volume
+pub fn stage_dir(game_dir: &Path) -> PathBuf {
+    game_dir.with_extension("alien-stage")
 }
 
-pub fn data_dir() -> &'static Path {
-    Path::new("data")
+pub fn steam_apps_dir(game_dir: &Path) -> Option<&Path> {
+    game_dir.ancestors().nth(3)
 }
 
-pub fn alien_isolation_dir() -> &'static Path {
-    Path::new("/Users/bppleman/Library/Application Support/Steam/steamapps/common/Alien Isolation/AlienIsolationData")
+/// Whether `dir` holds the game's data tree, `DATA` with `UI` and `ENV` below it in any case
+pub fn looks_like_game_dir(dir: &Path) -> bool {
+    let find = |dir: &Path, name: &str| {
+        std::fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+            let matches = entry
+                .file_name()
+                .to_string_lossy()
+                .eq_ignore_ascii_case(name);
+            (matches && entry.path().is_dir()).then(|| entry.path())
+        })
+    };
+    match find(dir, &data_dir().display().to_string()) {
+        Some(data) => find(&data, "ui").is_some() && find(&data, "env").is_some(),
+        None => false,
+    }
 }
@@ -0,0 +1,77 @@
+use std::fs::Permissions;
+use std::future::Future;
+use std::path::Path;
+
+/// Mode bits to record in the backup, none on Windows
+#[cfg(unix)]
+pub fn mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn mode(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Puts the mode bits recorded in the backup back on a restored file
+#[cfg(unix)]
+pub fn restore_mode(path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => std::fs::set_permissions(path, Permissions::from_mode(mode & 0o7777)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn restore_mode(_path: &Path, _mode: Option<u32>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Runs `operation` on `path`, retrying once with the read-only flag cleared when it is denied.
+/// With `restore` the original permissions are put back afterwards, as for overwrites. Without
+/// `fix` the denial is returned as is.
+pub async fn retry_writable<T, F, Fut>(
+    path: &Path,
+    restore: bool,
+    fix: bool,
+    operation: F,
+) -> std::io::Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = std::io::Result<T>>,
+{
+    let error = match operation().await {
+        Err(error) if error.kind() == std::io::ErrorKind::PermissionDenied && fix => error,
+        result => return result,
+    };
+    let original = match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.permissions().readonly() => metadata.permissions(),
+        _ => return Err(error),
+    };
+    tracing::warn!("Clearing the read-only flag of [{}]", path.display());
+    tokio::fs::set_permissions(path, writable(&original)).await?;
+    let result = operation().await;
+    if restore {
+        let restored = tokio::fs::set_permissions(path, original).await;
+        if result.is_ok() {
+            restored?;
+        }
+    }
+    result
+}
+
+#[cfg(unix)]
+fn writable(permissions: &Permissions) -> Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    Permissions::from_mode(permissions.mode() | 0o200)
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::permissions_set_readonly_false)]
+fn writable(permissions: &Permissions) -> Permissions {
+    let mut permissions = permissions.clone();
+    permissions.set_readonly(false);
+    permissions
+}
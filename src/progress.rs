@@ -0,0 +1,37 @@
+use crate::report::Phase;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Hears about the phases of an install or restore. Phases start and end in the order they
+/// run, a phase ends before the next starts. Files are reported from the concurrent tasks as
+/// they finish, so in completion order and from any thread.
+pub trait ProgressReporter: Send + Sync {
+    fn on_phase_start(&self, _phase: &'static str, _files: usize) {}
+    fn on_file_done(&self, _phase: &'static str, _path: &Path, _bytes: u64) {}
+    fn on_phase_end(&self, _phase: &'static str, _stats: &Phase) {}
+}
+
+/// The reporter calls as data, for sinks that forward them elsewhere
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    PhaseStart {
+        phase: &'static str,
+        files: usize,
+    },
+    FileDone {
+        phase: &'static str,
+        path: PathBuf,
+        bytes: u64,
+    },
+    PhaseEnd {
+        phase: &'static str,
+        stats: Phase,
+    },
+}
+
+/// Reporter of an installer nobody listens to
+#[derive(Debug, Default)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {}
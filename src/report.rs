@@ -0,0 +1,520 @@
+use crate::state::InstallState;
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Serialize)]
+pub struct RestoreReport {
+    pub restored: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    /// Files changed by something other than alien since the install, left in place
+    pub skipped: Vec<PathBuf>,
+}
+
+impl RestoreReport {
+    pub fn describe(&self) -> String {
+        let mut summary = format!(
+            "restored {} files, removed {} files",
+            self.restored.len(),
+            self.removed.len()
+        );
+        if !self.skipped.is_empty() {
+            summary.push_str(&format!(
+                ", skipped {} files modified since the install (pass --overwrite-modified to restore them)",
+                self.skipped.len()
+            ));
+        }
+        summary
+    }
+
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write restore manifest [{}]", path.display()))?;
+        tracing::info!("Wrote restore manifest to [{}]", path.display());
+        Ok(())
+    }
+}
+
+/// Pack files that sync found changed in the game since the install
+#[derive(Debug, Default, Serialize)]
+pub struct SyncReport {
+    /// The original the backup already holds, patched again as it is
+    pub backup_original: Vec<PathBuf>,
+    /// An original Steam updated since the backup, refreshed in the backup before patching
+    pub newer_original: Vec<PathBuf>,
+    pub refreshed: usize,
+    pub patched: usize,
+}
+
+impl SyncReport {
+    /// One line per changed file and its class
+    pub fn table(&self) -> String {
+        let classified = [
+            ("backup-era original", &self.backup_original),
+            ("newer original", &self.newer_original),
+        ];
+        classified
+            .iter()
+            .flat_map(|(class, paths)| {
+                paths
+                    .iter()
+                    .map(move |path| format!("{:<20} {}", class, path.display()))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn describe(&self) -> String {
+        if self.newer_original.is_empty() {
+            return format!(
+                "No originals were updated since the install, {} files are not patched",
+                self.backup_original.len()
+            );
+        }
+        format!(
+            "Refreshed {} backup entries, patched {} files again",
+            self.refreshed, self.patched
+        )
+    }
+}
+
+const MISSING_EXAMPLES: usize = 3;
+
+/// Pack files the game lacks grouped by their top-level directory, with a few examples each.
+/// Usually a sign of a wrong or incomplete game dir.
+pub fn describe_missing(missing: &[PathBuf]) -> String {
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    for path in missing {
+        let group = path
+            .components()
+            .next()
+            .map(|component| PathBuf::from(component.as_os_str()))
+            .unwrap_or_default();
+        groups.entry(group).or_default().push(path.as_path());
+    }
+    let mut lines = vec![format!(
+        "{} files of the pack are missing from the game:",
+        missing.len()
+    )];
+    for (group, paths) in groups {
+        lines.push(format!("  {}/ ({} files)", group.display(), paths.len()));
+        for path in paths.iter().take(MISSING_EXAMPLES) {
+            lines.push(format!("    {}", path.display()));
+        }
+        if paths.len() > MISSING_EXAMPLES {
+            lines.push(format!(
+                "    ... and {} more",
+                paths.len() - MISSING_EXAMPLES
+            ));
+        }
+    }
+    lines.push(
+        "This usually means the game directory is wrong or incomplete, check --game-dir"
+            .to_string(),
+    );
+    lines.join("\n")
+}
+
+pub const ENGLISH_EXIT_CODE: i32 = 4;
+pub const MIXED_EXIT_CODE: i32 = 5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GameLanguage {
+    Chinese,
+    English,
+    Mixed,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationReport {
+    pub matched: usize,
+    pub mismatched: Vec<PathBuf>,
+    pub missing: Vec<PathBuf>,
+}
+
+impl ValidationReport {
+    pub fn language(&self) -> GameLanguage {
+        if self.mismatched.is_empty() && self.missing.is_empty() {
+            GameLanguage::Chinese
+        } else if self.matched == 0 {
+            GameLanguage::English
+        } else {
+            GameLanguage::Mixed
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.language() {
+            GameLanguage::Chinese => 0,
+            GameLanguage::English => ENGLISH_EXIT_CODE,
+            GameLanguage::Mixed => MIXED_EXIT_CODE,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let verdict = match self.language() {
+            GameLanguage::Chinese => "fully installed",
+            GameLanguage::English => "not installed",
+            GameLanguage::Mixed => "partially installed or corrupt, run `alien repair`",
+        };
+        format!(
+            "{} matched, {} mismatched, {} missing: {}",
+            self.matched,
+            self.mismatched.len(),
+            self.missing.len(),
+            verdict
+        )
+    }
+}
+
+/// Which source each pack file on disk currently matches
+#[derive(Debug, Default, Serialize)]
+pub struct InstallClassification {
+    /// Files matching the embedded pack
+    pub chinese: Vec<PathBuf>,
+    /// Files matching the backup, or absent when the pack created them
+    pub english: Vec<PathBuf>,
+    /// Files matching neither, e.g. replaced by a game update or a Steam verify
+    pub neither: Vec<PathBuf>,
+}
+
+impl InstallClassification {
+    fn total(&self) -> usize {
+        self.chinese.len() + self.english.len() + self.neither.len()
+    }
+
+    fn percent(&self, count: usize) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => count as f64 * 100.0 / total as f64,
+        }
+    }
+
+    pub fn language(&self) -> GameLanguage {
+        if self.chinese.len() == self.total() {
+            GameLanguage::Chinese
+        } else if self.english.len() == self.total() {
+            GameLanguage::English
+        } else {
+            GameLanguage::Mixed
+        }
+    }
+
+    /// The command that brings the game back to a single language, if it needs one
+    pub fn recommendation(&self) -> Option<&'static str> {
+        match self.language() {
+            GameLanguage::Chinese => None,
+            GameLanguage::English => Some("alien zh"),
+            GameLanguage::Mixed if !self.neither.is_empty() => Some("alien sync"),
+            GameLanguage::Mixed if self.chinese.len() >= self.english.len() => Some("alien repair"),
+            GameLanguage::Mixed => Some("alien en"),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut summary = format!(
+            "{:.1}% Chinese, {:.1}% English, {:.1}% neither of {} files",
+            self.percent(self.chinese.len()),
+            self.percent(self.english.len()),
+            self.percent(self.neither.len()),
+            self.total()
+        );
+        if let Some(command) = self.recommendation() {
+            summary.push_str(&format!(", run `{}`", command));
+        }
+        summary
+    }
+}
+
+/// What the last install recorded for a game dir and what its files match now
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    #[serde(skip)]
+    pub game_dir: PathBuf,
+    pub state: Option<InstallState>,
+    /// Pack files checked against the disk, only when an install is recorded
+    pub files: Option<ValidationReport>,
+    pub classification: InstallClassification,
+}
+
+impl StatusReport {
+    pub fn describe(&self) -> String {
+        let mut lines = match &self.state {
+            Some(state) => vec![format!("[{}]", self.game_dir.display()), state.describe()],
+            None => vec![format!(
+                "No install recorded for [{}]",
+                self.game_dir.display()
+            )],
+        };
+        if let Some(files) = &self.files {
+            lines.push(files.describe());
+        }
+        lines.push(self.classification.describe());
+        lines.join("\n")
+    }
+}
+
+/// Everything alien knows about one game file
+#[derive(Debug, Serialize)]
+pub struct WhichReport {
+    pub path: PathBuf,
+    pub in_pack: bool,
+    pub optional: bool,
+    pub on_disk: bool,
+    /// Whether the file on disk matches the pack, unknown when either side lacks it
+    pub patched: Option<bool>,
+}
+
+impl WhichReport {
+    pub fn describe(&self) -> String {
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        let patched = match self.patched {
+            Some(patched) => yes_no(patched),
+            None => "n/a",
+        };
+        format!(
+            "{}\n  in pack:   {}\n  optional:  {}\n  on disk:   {}\n  patched:   {}",
+            self.path.display(),
+            yes_no(self.in_pack),
+            yes_no(self.optional),
+            yes_no(self.on_disk),
+            patched
+        )
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BackupVerifyReport {
+    pub will_restore: Vec<PathBuf>,
+    pub identical: Vec<PathBuf>,
+    pub missing: Vec<PathBuf>,
+    /// Entries holding the Chinese file of the pack instead of an English original
+    pub poisoned: Vec<PathBuf>,
+}
+
+impl BackupVerifyReport {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} entries will be restored, {} already identical, {} targets missing, {} poisoned",
+            self.will_restore.len(),
+            self.identical.len(),
+            self.missing.len(),
+            self.poisoned.len()
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffEntry {
+    pub path: PathBuf,
+    pub size: Option<u64>,
+    pub reference_size: Option<u64>,
+}
+
+impl DiffEntry {
+    pub fn describe(&self) -> String {
+        match (self.size, self.reference_size) {
+            (Some(size), Some(reference_size)) => format!(
+                "{} ({} -> {} bytes)",
+                self.path.display(),
+                reference_size,
+                size
+            ),
+            (Some(size), None) | (None, Some(size)) => {
+                format!("{} ({} bytes)", self.path.display(), size)
+            }
+            (None, None) => self.path.display().to_string(),
+        }
+    }
+}
+
+/// Differences between the pack's files and a reference tree
+#[derive(Debug, Default, Serialize)]
+pub struct TreeDiff {
+    /// Pack files the reference lacks
+    pub added: Vec<DiffEntry>,
+    /// Reference files the pack lacks, only collected for an explicit reference tree
+    pub removed: Vec<DiffEntry>,
+    pub changed: Vec<DiffEntry>,
+    pub identical: usize,
+}
+
+impl TreeDiff {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} added, {} removed, {} changed, {} identical",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.identical
+        )
+    }
+}
+
+/// Identifies the binary and its embedded pack, for bug reports
+#[derive(Debug, Serialize)]
+pub struct Info {
+    pub version: &'static str,
+    pub pack_sha256: &'static str,
+    pub pack_files: usize,
+    pub target: &'static str,
+    pub git_commit: &'static str,
+}
+
+impl Info {
+    pub fn table(&self) -> String {
+        [
+            ("version", self.version.to_string()),
+            ("pack sha256", self.pack_sha256.to_string()),
+            ("pack files", self.pack_files.to_string()),
+            ("target", self.target.to_string()),
+            ("git commit", self.git_commit.to_string()),
+        ]
+        .iter()
+        .map(|(key, value)| format!("{:<12} {}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+}
+
+/// Settings the run resolved from flags, environment, config and detection
+#[derive(Debug, Serialize)]
+pub struct Explain {
+    pub game_dir: PathBuf,
+    pub backup_dir: PathBuf,
+    pub config: Option<PathBuf>,
+    pub language: String,
+    pub language_prefix: PathBuf,
+    pub pack: String,
+    pub optional_files: String,
+    pub jobs: usize,
+    pub io_limit: usize,
+}
+
+impl Explain {
+    pub fn table(&self) -> String {
+        let config = self
+            .config
+            .as_ref()
+            .map(|config| config.display().to_string())
+            .unwrap_or_else(|| "none".to_string());
+        [
+            ("game dir", self.game_dir.display().to_string()),
+            ("backup dir", self.backup_dir.display().to_string()),
+            ("config", config),
+            ("language", self.language.clone()),
+            (
+                "language prefix",
+                self.language_prefix.display().to_string(),
+            ),
+            ("pack", self.pack.clone()),
+            ("optional files", self.optional_files.clone()),
+            ("jobs", self.jobs.to_string()),
+            ("files open at once", self.io_limit.to_string()),
+        ]
+        .iter()
+        .map(|(key, value)| format!("{:<19} {}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+}
+
+/// Wall time and bytes of one phase
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Phase {
+    pub elapsed: Duration,
+    pub bytes: u64,
+    pub mb_per_sec: Option<f64>,
+}
+
+/// Started where a phase begins, `finish` records it once the phase's bytes are known
+pub struct PhaseTimer(Instant);
+
+impl PhaseTimer {
+    pub fn start() -> Self {
+        PhaseTimer(Instant::now())
+    }
+
+    pub fn finish(self, bytes: u64) -> Phase {
+        let elapsed = self.0.elapsed();
+        let mb_per_sec = (bytes > 0 && !elapsed.is_zero())
+            .then(|| bytes as f64 / 1_000_000.0 / elapsed.as_secs_f64());
+        Phase {
+            elapsed,
+            bytes,
+            mb_per_sec,
+        }
+    }
+}
+
+/// Each phase of the run, for comparing runs across machines
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Timings {
+    pub read_manifest: Option<Phase>,
+    pub filter: Option<Phase>,
+    pub stage: Option<Phase>,
+    pub check: Option<Phase>,
+    /// Reading and deflating the originals, overlapped across `--jobs` workers
+    pub backup_compress: Option<Phase>,
+    pub backup_write: Option<Phase>,
+    /// The whole backup, including checksums and the listing
+    pub backup: Option<Phase>,
+    pub write: Option<Phase>,
+    /// Hashing the game files against the pack, for repair, verify and status
+    pub validate: Option<Phase>,
+    /// Time all tasks together spent queued for --max-open-files
+    pub open_files_wait: Option<Duration>,
+    /// Starting the async runtime, timed by `bench --runtimes`
+    pub runtime_start: Option<Duration>,
+}
+
+impl Timings {
+    pub fn phases(&self) -> [(&'static str, Option<Phase>); 9] {
+        [
+            ("read manifest", self.read_manifest),
+            ("filter", self.filter),
+            ("validate", self.validate),
+            ("stage", self.stage),
+            ("check", self.check),
+            ("backup compress", self.backup_compress),
+            ("backup write", self.backup_write),
+            ("backup", self.backup),
+            ("write", self.write),
+        ]
+    }
+
+    pub fn table(&self) -> String {
+        let mut table = self
+            .phases()
+            .iter()
+            .map(|(name, phase)| match phase {
+                Some(phase) => format!(
+                    "{:<16}{:>12.1?}{:>14}{:>14}",
+                    name,
+                    phase.elapsed,
+                    match phase.bytes {
+                        0 => "-".to_string(),
+                        bytes => format!("{} B", bytes),
+                    },
+                    match phase.mb_per_sec {
+                        Some(mb_per_sec) => format!("{:.1} MB/s", mb_per_sec),
+                        None => "-".to_string(),
+                    }
+                ),
+                None => format!("{:<16}{:>12}{:>14}{:>14}", name, "-", "-", "-"),
+            })
+            .collect::<Vec<_>>();
+        if let Some(waited) = self.open_files_wait {
+            table.push(format!("{:<16}{:>12.1?}", "open file wait", waited));
+        }
+        if let Some(runtime_start) = self.runtime_start {
+            table.push(format!("{:<16}{:>12.1?}", "runtime start", runtime_start));
+        }
+        table.join("\n")
+    }
+}
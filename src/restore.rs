@@ -0,0 +1,783 @@
+use crate::error::AlienError;
+use crate::installer::{restore_modified_time, Installer};
+use crate::manifest::{self, FileState, Manifest, ManifestItem};
+use crate::metadata::{self, BackupMetadata};
+use crate::report::{PhaseTimer, RestoreReport};
+use crate::state::{InstallState, StateFile};
+use crate::{backups, permissions, steam, tasks};
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+impl Installer {
+    /// Restores the English files from `from` or the default backup and removes the files of
+    /// the pack `needs_remove` that have no English original
+    pub async fn restore(
+        &self,
+        needs_remove: Manifest,
+        from: Option<&Path>,
+    ) -> Result<RestoreReport, AlienError> {
+        self.check()?;
+        let alien_isolation_dir = self.game_dir.as_path();
+        let base = self
+            .options
+            .relative_to
+            .as_deref()
+            .unwrap_or(alien_isolation_dir);
+        let recorded = StateFile::load(&self.state_dir)?.get(base).cloned();
+        if recorded.as_ref().is_some_and(|state| state.no_backup) {
+            tracing::warn!(
+                "The last install skipped the backup, use Steam's \"Verify integrity of game files\" to restore English"
+            );
+        }
+        let (backup, manifest) = self.index_backup(from).await?;
+        let bases = BackupMetadata::find_in(&manifest)?
+            .and_then(|metadata| metadata.game_dir)
+            .into_iter()
+            .chain(recorded.as_ref().and_then(|state| state.base.clone()));
+        for recorded_base in bases {
+            self.check_base(&recorded_base, base, self.options.relative_to.is_some());
+        }
+        let check_english = !self.options.force && self.selection.is_none();
+        if check_english && self.is_already_english(&manifest, &needs_remove).await {
+            tracing::warn!("Already English, nothing to restore. Pass --force to restore anyway");
+            return Ok(RestoreReport::default());
+        }
+        let report = self
+            .english(&backup, manifest, needs_remove, recorded.clone())
+            .await?;
+        StateFile::forget(&self.state_dir, base)?;
+        if let (Some(selection), Some(mut state)) = (&self.selection, recorded) {
+            state.created.retain(|striped| !selection.contains(striped));
+            state
+                .overwritten
+                .retain(|striped| !selection.contains(striped));
+            state
+                .files
+                .retain(|striped, _| !selection.contains(striped));
+            state.base = Some(alien_isolation_dir.to_path_buf());
+            StateFile::record(&self.state_dir, alien_isolation_dir, state)?;
+        }
+        Ok(report)
+    }
+
+    pub(crate) fn check_base(&self, recorded: &Path, base: &Path, explicit: bool) {
+        let alien_isolation_dir = self.game_dir.as_path();
+        if recorded == alien_isolation_dir {
+            return;
+        }
+        if explicit && recorded == base {
+            tracing::info!(
+                "Restoring paths recorded for [{}] into [{}]",
+                recorded.display(),
+                alien_isolation_dir.display()
+            );
+        } else {
+            tracing::warn!(
+                "Backup was taken from [{}] but restores into [{}], pass --relative-to if the game was moved",
+                recorded.display(),
+                alien_isolation_dir.display()
+            );
+        }
+    }
+
+    pub(crate) async fn is_already_english(&self, backup: &Manifest, pack: &Manifest) -> bool {
+        const SAMPLE_SIZE: usize = 16;
+        let originals = backup
+            .iter()
+            .filter(|item| item.is_file && item.path != Path::new(metadata::METADATA_ENTRY))
+            .collect::<Vec<_>>();
+        let step = (originals.len() / SAMPLE_SIZE).max(1);
+        for item in originals.into_iter().step_by(step).take(SAMPLE_SIZE) {
+            match item.check_file(&self.game_path(&item.lowercase_name)).await {
+                Ok(FileState::Matches) => {}
+                _ => return false,
+            }
+        }
+        let installed = pack
+            .hans_plan(self.prefix())
+            .into_iter()
+            .filter(|(index, _)| pack[*index].is_file)
+            .collect::<Vec<_>>();
+        let step = (installed.len() / SAMPLE_SIZE).max(1);
+        for (index, striped) in installed.into_iter().step_by(step).take(SAMPLE_SIZE) {
+            if let Ok(FileState::Matches) = pack[index].check_file(&self.game_path(&striped)).await
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Without a backup, only deletes the files the pack installs
+    pub async fn delete_installed(
+        &self,
+        needs_remove: Manifest,
+    ) -> Result<RestoreReport, AlienError> {
+        self.check()?;
+        let instant = std::time::Instant::now();
+        let alien_isolation_dir = self.game_dir.as_path();
+        let plan = needs_remove
+            .hans_plan(self.prefix())
+            .into_iter()
+            .filter(|(index, _)| needs_remove[*index].is_file)
+            .map(|(_, striped)| striped)
+            .collect::<Vec<_>>();
+        self.confirm(
+            &format!(
+                "Deleting {} installed files without a backup, overwritten English files cannot be restored this way. Continue?",
+                plan.len()
+            ))?;
+        let removed = self.remove_game_files(plan).await?;
+        StateFile::forget(&self.state_dir, alien_isolation_dir)?;
+        tracing::info!("Deleted installed files take {:?}", instant.elapsed());
+        tracing::warn!(
+            "Overwritten files are still Chinese. In Steam, right click Alien: Isolation > Properties > Installed Files > \"Verify integrity of game files\" to download the English originals."
+        );
+        Ok(RestoreReport {
+            removed,
+            ..Default::default()
+        })
+    }
+
+    /// Without a backup, removes the created files and lets Steam re-download the originals
+    pub async fn restore_from_steam(&self) -> Result<RestoreReport, AlienError> {
+        self.check()?;
+        let alien_isolation_dir = self.game_dir.as_path();
+        let created = StateFile::load(&self.state_dir)?
+            .get(alien_isolation_dir)
+            .map(|state| state.created.clone())
+            .unwrap_or_default();
+        self.confirm(
+            &format!(
+                "Removing {} created files and asking Steam to re-download the English originals. Continue?",
+                created.len()
+            ))?;
+        let removed = self.remove_game_files(created).await?;
+        StateFile::forget(&self.state_dir, alien_isolation_dir)?;
+        let url = steam::validate_url();
+        tracing::warn!("Verify the game files in Steam with {}", url);
+        if let Err(error) = steam::open_validate() {
+            tracing::warn!(
+                "{:#}, open the URL or use \"Verify integrity of game files\" manually",
+                error
+            );
+        }
+        Ok(RestoreReport {
+            removed,
+            ..Default::default()
+        })
+    }
+
+    pub(crate) async fn remove_game_files(&self, paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+        let result = self
+            .join_all(paths.into_iter().map(|striped| async move {
+                let path = self.resolve_link(&self.game_path(&striped))?;
+                match permissions::retry_writable(
+                    &path,
+                    false,
+                    self.options.fix_permissions,
+                    || tokio::fs::remove_file(&path),
+                )
+                .await
+                {
+                    Ok(()) => Ok(Some(striped)),
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(error) => {
+                        Err(error).with_context(|| format!("Failed to remove [{}]", path.display()))
+                    }
+                }
+            }))
+            .await;
+        Ok(result
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    /// Restores the originals in `manifest`, the index of `backup`, and removes the pack's files
+    pub(crate) async fn english(
+        &self,
+        backup: &Path,
+        mut manifest: Manifest,
+        needs_remove: Manifest,
+        recorded: Option<InstallState>,
+    ) -> Result<RestoreReport> {
+        let instant = std::time::Instant::now();
+        tracing::info!("Restore to English");
+        let metadata = BackupMetadata::take_from(&mut manifest)?;
+        let modified = metadata
+            .as_ref()
+            .map(|metadata| metadata.modified.clone())
+            .unwrap_or_default();
+        let created_dirs = recorded
+            .as_ref()
+            .map(|state| state.created_dirs.clone())
+            .unwrap_or_default();
+        let skipped = match &recorded {
+            Some(state) if !self.options.overwrite_modified => {
+                self.modified_since_install(&state.files).await
+            }
+            _ => Vec::new(),
+        };
+        for striped in &skipped {
+            tracing::warn!(
+                "Skipping [{}], it was modified after the install",
+                striped.display()
+            );
+        }
+        // Covered by the backup whether or not this restore writes them, skipped and unselected
+        // files are kept as they are rather than missing from it
+        let backed_up = manifest
+            .iter()
+            .filter(|item| item.is_file)
+            .map(|item| item.lowercase_name.clone())
+            .collect::<HashSet<_>>();
+        manifest.retain(|item| {
+            !skipped.contains(&item.lowercase_name) && self.is_selected(&item.lowercase_name)
+        });
+        if let Some(metadata) = &metadata {
+            check_backup_covers(
+                metadata
+                    .overwritten
+                    .iter()
+                    .filter(|striped| !backed_up.contains(*striped)),
+                "Backup is missing overwritten files recorded in its metadata",
+            )?;
+        }
+        let removals = match (recorded, metadata) {
+            (Some(state), _) if !state.created.is_empty() || !state.overwritten.is_empty() => {
+                tracing::info!(
+                    "Install record lists {} created and {} overwritten files",
+                    state.created.len(),
+                    state.overwritten.len()
+                );
+                state
+                    .created
+                    .into_iter()
+                    .chain(
+                        state
+                            .overwritten
+                            .into_iter()
+                            .filter(|striped| !backed_up.contains(striped)),
+                    )
+                    .collect::<Vec<_>>()
+            }
+            (_, Some(metadata)) => {
+                tracing::info!(
+                    "Backup records {} created and {} overwritten files",
+                    metadata.created.len(),
+                    metadata.overwritten.len()
+                );
+                if !metadata.already_patched.is_empty() {
+                    tracing::warn!(
+                        "{} files were already Chinese at backup time and will be deleted, verify game files in Steam to restore their English originals",
+                        metadata.already_patched.len()
+                    );
+                }
+                metadata
+                    .created
+                    .into_iter()
+                    .chain(metadata.already_patched)
+                    .collect::<Vec<_>>()
+            }
+            (_, None) => {
+                let removals = needs_remove
+                    .hans_plan(self.prefix())
+                    .into_iter()
+                    .filter(|(index, _)| needs_remove[*index].is_file)
+                    .collect::<Vec<_>>();
+                check_backup_covers(
+                    removals
+                        .iter()
+                        .filter(|(index, striped)| {
+                            !backed_up.contains(striped) && !needs_remove[*index].optional
+                        })
+                        .map(|(_, striped)| striped),
+                    "Files about to be deleted are neither in the backup nor created by the pack",
+                )?;
+                removals.into_iter().map(|(_, striped)| striped).collect()
+            }
+        };
+        // English files go back first, overwriting their Chinese counterparts in place, and only
+        // then are the files without an English original removed. The game thus has a complete
+        // set of files at every point an interruption could leave it in.
+        // Only zip entries are decompressed as they are written, clone trees come with their bytes
+        let streamed = !backups::is_clone_tree(backup);
+        let phase = PhaseTimer::start();
+        self.reporter.on_phase_start(
+            "restore",
+            manifest.iter().filter(|item| item.is_file).count(),
+        );
+        let result = self
+            .join_all(
+                manifest
+                    .iter_mut()
+                    .filter(|item| !streamed || item.is_dir)
+                    .map(|item| self.restore_item(item)),
+            )
+            .await;
+        result.into_iter().collect::<Result<Vec<_>>>()?;
+        if streamed {
+            self.stream_restore(backup, &manifest, &modified).await?;
+        }
+        self.reporter.on_phase_end("restore", &phase.finish(0));
+        if self.cancel.is_cancelled() {
+            return Err(AlienError::Cancelled {
+                what: "the restore before removing the files the pack created".to_string(),
+            }
+            .into());
+        }
+        let result = self
+            .join_all(
+                removals
+                    .iter()
+                    .filter(|striped| !skipped.contains(striped) && !backed_up.contains(*striped))
+                    .filter(|striped| self.is_selected(striped))
+                    .map(|striped| (self.game_path(striped), striped))
+                    .map(|(path, striped)| async move {
+                        if let Ok(metadata) = tokio::fs::metadata(&path).await {
+                            if metadata.is_file() {
+                                let path = self.resolve_link(&path)?;
+                                permissions::retry_writable(
+                                    &path,
+                                    false,
+                                    self.options.fix_permissions,
+                                    || tokio::fs::remove_file(&path),
+                                )
+                                .await
+                                .with_context(|| {
+                                    format!("Failed to remove [{}]", path.display())
+                                })?;
+                                return Ok(Some(striped.to_path_buf()));
+                            }
+                        }
+                        Ok(None)
+                    }),
+            )
+            .await;
+        let removed = result
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        self.remove_empty_dirs(created_dirs);
+
+        let restored = manifest
+            .iter()
+            .filter(|item| item.is_file)
+            .map(|item| item.lowercase_name.clone())
+            .collect::<Vec<_>>();
+
+        tracing::info!("Restored to English take {:?}", instant.elapsed());
+        Ok(RestoreReport {
+            restored,
+            removed,
+            skipped,
+        })
+    }
+
+    pub(crate) async fn restore_item(&self, item: &mut ManifestItem) -> Result<()> {
+        let path = self.game_path(&item.lowercase_name);
+        self.write_file(item, path.clone()).await?;
+        if self.options.preserve_timestamps {
+            restore_modified_time(item, &path)?;
+        }
+        let mode = item.mode.filter(|_| self.options.preserve_permissions);
+        permissions::restore_mode(&path, mode)
+            .with_context(|| format!("Failed to restore mode of [{}]", path.display()))?;
+        if item.is_file {
+            self.reporter
+                .on_file_done("restore", &item.lowercase_name, item.size);
+        }
+        Ok(())
+    }
+
+    /// Restores the files of `manifest` from the backup zip, decompressing one entry at a time on
+    /// the blocking pool while at most `io_limit` entries wait to be written, so a restore never
+    /// holds the whole backup in memory. Entries get their exact time from `modified`, the
+    /// metadata's record of it, where it has one.
+    pub(crate) async fn stream_restore(
+        &self,
+        backup: &Path,
+        manifest: &Manifest,
+        modified: &BTreeMap<PathBuf, SystemTime>,
+    ) -> Result<()> {
+        let names = manifest
+            .iter()
+            .filter(|item| item.is_file)
+            .map(|item| item.lowercase_name.clone())
+            .collect::<HashSet<_>>();
+        let (sender, receiver) = tokio::sync::mpsc::channel(self.options.io_limit);
+        let backup = backup.to_path_buf();
+        let read = tasks::blocking("stream the backup", move || {
+            Manifest::stream_backup(&backup, &names, |item| {
+                sender
+                    .blocking_send(item)
+                    .map_err(|_| AlienError::RestoreStopped.into())
+            })
+        });
+        let writes = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
+        .map(|mut item| async move {
+            if let Some(exact) = modified.get(&item.lowercase_name) {
+                item.modified = Some(*exact);
+            }
+            self.restore_item(&mut item).await
+        })
+        .buffer_unordered(self.options.io_limit)
+        .collect::<Vec<_>>();
+        let (read, written) = tokio::join!(read, writes);
+        written.into_iter().collect::<Result<Vec<_>>>()?;
+        read
+    }
+
+    pub(crate) fn remove_empty_dirs(&self, mut created_dirs: Vec<PathBuf>) {
+        created_dirs.sort_by_key(|striped| std::cmp::Reverse(striped.components().count()));
+        for striped in created_dirs {
+            let path = self.game_path(&striped);
+            let is_empty = match std::fs::read_dir(&path) {
+                Ok(mut entries) => entries.next().is_none(),
+                Err(_) => continue,
+            };
+            if !is_empty {
+                tracing::info!("Keeping [{}], it is not empty", path.display());
+                continue;
+            }
+            match std::fs::remove_dir(&path) {
+                Ok(()) => tracing::info!("Removed empty directory [{}]", path.display()),
+                Err(error) => tracing::warn!("Failed to remove [{}]: {}", path.display(), error),
+            }
+        }
+    }
+
+    pub(crate) async fn modified_since_install(
+        &self,
+        files: &BTreeMap<PathBuf, u32>,
+    ) -> Vec<PathBuf> {
+        futures::stream::iter(files.iter().map(|(striped, crc32)| async move {
+            match manifest::crc32_file(self.game_path(striped)).await {
+                Ok(current) if current != *crc32 => Some(striped.clone()),
+                _ => None,
+            }
+        }))
+        .buffered(self.options.io_limit)
+        .filter_map(|modified| async move { modified })
+        .collect()
+        .await
+    }
+}
+
+fn check_backup_covers<'a>(
+    unaccounted: impl Iterator<Item = &'a PathBuf>,
+    reason: &'static str,
+) -> Result<(), AlienError> {
+    let paths = unaccounted.cloned().collect::<Vec<_>>();
+    if paths.is_empty() {
+        return Ok(());
+    }
+    Err(AlienError::BackupIncomplete { reason, paths })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::AlienError;
+    use crate::installer::tests::{
+        cancel_after, fixture_installer, original, original_name, scratch_dir, stage_and_install,
+    };
+    use crate::installer::{Installer, OnMissing, Options, PackSource};
+    use std::collections::HashMap;
+    use std::io::{Cursor, Write};
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    #[tokio::test]
+    async fn restores_exact_modification_times() {
+        let installer = fixture_installer("exact-mtime", 4, 64);
+        // An odd second with a fraction, which a DOS time in the zip would round away
+        let modified = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_001, 123_456_789);
+        let path = installer.game_dir().join(original_name(1));
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(modified)).unwrap();
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+
+        let manifest = installer.load_pack().await.unwrap();
+        installer.restore(manifest, None).await.unwrap();
+        let restored = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let drift = restored
+            .duration_since(modified)
+            .unwrap_or_else(|error| error.duration());
+        assert!(
+            drift < Duration::from_millis(1),
+            "mtime drifted by {:?}",
+            drift
+        );
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    /// Installs a pack of 16 files over the fixture, the one at index 3 created by the pack, and
+    /// returns the pack's bytes of each
+    async fn installed(name: &str) -> (Installer, HashMap<PathBuf, Vec<u8>>) {
+        let mut installer = fixture_installer(name, 16, 64);
+        installer.options.io_limit = 4;
+        installer.options.on_missing = OnMissing::Skip;
+        std::fs::remove_file(installer.game_dir().join(original_name(3))).unwrap();
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        let pack = manifest
+            .filter_hans_dir(installer.prefix())
+            .into_iter()
+            .filter(|(item, _)| item.is_file)
+            .map(|(item, striped)| (striped, item.bytes.clone()))
+            .collect();
+        (installer, pack)
+    }
+
+    /// Every file of the pack is in the game, as its English original or as the pack's
+    fn assert_complete(installer: &Installer, pack: &HashMap<PathBuf, Vec<u8>>) {
+        for index in 0..16 {
+            let striped = PathBuf::from(original_name(index));
+            let bytes = std::fs::read(installer.game_dir().join(&striped)).unwrap();
+            assert!(
+                bytes == original(index) || bytes == pack[&striped],
+                "[{}] is neither English nor Chinese",
+                striped.display()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn a_restore_interrupted_before_the_removals_leaves_the_game_complete() {
+        let (installer, pack) = installed("cancel-removals").await;
+        let installer = cancel_after(installer, "restore", None);
+
+        let manifest = installer.load_pack().await.unwrap();
+        let error = installer.restore(manifest, None).await.unwrap_err();
+        assert!(matches!(error, AlienError::Cancelled { .. }));
+        assert_complete(&installer, &pack);
+        for index in (0..16).filter(|index| *index != 3) {
+            let restored = std::fs::read(installer.game_dir().join(original_name(index))).unwrap();
+            assert_eq!(restored, original(index));
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_restore_interrupted_mid_write_leaves_the_game_complete() {
+        let (installer, pack) = installed("cancel-restore").await;
+        let installer = cancel_after(installer, "restore", Some(4));
+
+        let manifest = installer.load_pack().await.unwrap();
+        let error = installer.restore(manifest, None).await.unwrap_err();
+        assert!(matches!(error, AlienError::Cancelled { .. }));
+        assert_complete(&installer, &pack);
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_file_modified_after_the_install_is_kept() {
+        let (installer, _) = installed("modified-since").await;
+        let modified = installer.game_dir().join(original_name(5));
+        std::fs::write(&modified, b"edited by the user").unwrap();
+
+        let manifest = installer.load_pack().await.unwrap();
+        let report = installer.restore(manifest, None).await.unwrap();
+        assert_eq!(report.skipped, vec![PathBuf::from(original_name(5))]);
+        assert_eq!(std::fs::read(&modified).unwrap(), b"edited by the user");
+        for index in (0..16).filter(|index| *index != 3 && *index != 5) {
+            let restored = std::fs::read(installer.game_dir().join(original_name(index))).unwrap();
+            assert_eq!(restored, original(index));
+        }
+        assert!(!installer.game_dir().join(original_name(3)).exists());
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_selective_restore_leaves_the_rest_patched() {
+        let (mut installer, pack) = installed("selective").await;
+        let manifest = installer.load_pack().await.unwrap();
+        let list = format!("{}\n{}\n", original_name(2), original_name(3));
+        installer.select_files(&list, &manifest, false).unwrap();
+
+        let report = installer.restore(manifest, None).await.unwrap();
+        assert_eq!(report.removed, vec![PathBuf::from(original_name(3))]);
+        let game_dir = installer.game_dir();
+        assert_eq!(
+            std::fs::read(game_dir.join(original_name(2))).unwrap(),
+            original(2)
+        );
+        assert!(!game_dir.join(original_name(3)).exists());
+        for index in (0..16).filter(|index| *index != 2 && *index != 3) {
+            let striped = PathBuf::from(original_name(index));
+            assert_eq!(
+                std::fs::read(game_dir.join(&striped)).unwrap(),
+                pack[&striped]
+            );
+        }
+        let _ = std::fs::remove_dir_all(game_dir.parent().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_directory_symlink_mid_path_is_patched_and_restored_through() {
+        let mut installer = fixture_installer("symlinked-dir", 8, 64);
+        installer.options.on_missing = OnMissing::Skip;
+        std::fs::remove_file(installer.game_dir().join(original_name(3))).unwrap();
+        let bench = installer
+            .game_dir()
+            .join(original_name(0))
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let drive = installer.game_dir().parent().unwrap().join("drive");
+        std::fs::create_dir_all(&drive).unwrap();
+        std::fs::rename(&bench, drive.join("bench")).unwrap();
+        std::os::unix::fs::symlink(drive.join("bench"), &bench).unwrap();
+        let on_drive = |index: usize| drive.join(format!("bench/{:06}.txt", index));
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        assert_ne!(std::fs::read(on_drive(0)).unwrap(), original(0));
+        assert!(on_drive(3).exists());
+
+        let manifest = installer.load_pack().await.unwrap();
+        let report = installer.restore(manifest, None).await.unwrap();
+        assert_eq!(report.removed, vec![PathBuf::from(original_name(3))]);
+        assert!(std::fs::symlink_metadata(&bench)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(!on_drive(3).exists());
+        for index in (0..8).filter(|index| *index != 3) {
+            assert_eq!(std::fs::read(on_drive(index)).unwrap(), original(index));
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn restoring_without_a_backup_fails_before_touching_the_game() {
+        let installer = fixture_installer("no-backup-restore", 4, 64);
+        let manifest = installer.load_pack().await.unwrap();
+
+        let error = installer.restore(manifest, None).await.unwrap_err();
+        assert!(matches!(error, AlienError::BackupMissing(_)));
+        assert_eq!(error.exit_code(), crate::backups::NO_BACKUP_EXIT_CODE);
+        assert!(
+            error.to_string().contains("nothing to restore"),
+            "{}",
+            error
+        );
+        assert!(error.to_string().contains("--from-steam"), "{}", error);
+        for index in 0..4 {
+            let path = installer.game_dir().join(original_name(index));
+            assert_eq!(std::fs::read(&path).unwrap(), original(index));
+        }
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_decomposed_name_on_disk_is_patched_and_restored_in_place() {
+        let scratch = scratch_dir("decomposed-name");
+        let options = Options {
+            safe_mode: false,
+            assume_yes: true,
+            ..Options::default()
+        };
+        // The pack names the file composed, the disk holds it decomposed like macOS does
+        let composed = "data/caf\u{e9}.txt";
+        let decomposed = scratch.join("game/data/cafe\u{301}.txt");
+        std::fs::create_dir_all(decomposed.parent().unwrap()).unwrap();
+        std::fs::write(&decomposed, original(0)).unwrap();
+        let mut pack = Cursor::new(Vec::new());
+        let mut archive = ZipWriter::new(&mut pack);
+        let name = options.language_prefix.join(composed);
+        archive
+            .start_file(name.display().to_string(), SimpleFileOptions::default())
+            .unwrap();
+        archive.write_all(b"translated").unwrap();
+        archive.finish().unwrap();
+        let installer = Installer::new(
+            scratch.join("game"),
+            scratch.join("backups"),
+            PackSource::Memory(pack.into_inner().into()),
+        )
+        .with_options(options);
+
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        assert_eq!(std::fs::read(&decomposed).unwrap(), b"translated");
+        let names = || {
+            std::fs::read_dir(decomposed.parent().unwrap())
+                .unwrap()
+                .count()
+        };
+        assert_eq!(names(), 1);
+
+        let manifest = installer.load_pack().await.unwrap();
+        installer.restore(manifest, None).await.unwrap();
+        assert_eq!(std::fs::read(&decomposed).unwrap(), original(0));
+        assert_eq!(names(), 1);
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[tokio::test]
+    async fn an_already_english_game_is_left_alone_unless_forced() {
+        let mut installer = fixture_installer("already-english", 8, 64);
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        // Like Steam's verify putting the originals back behind alien's back
+        let verified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        for index in 0..8 {
+            let path = installer.game_dir().join(original_name(index));
+            std::fs::write(&path, original(index)).unwrap();
+            filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(verified))
+                .unwrap();
+        }
+
+        let manifest = installer.load_pack().await.unwrap();
+        let report = installer.restore(manifest, None).await.unwrap();
+        assert!(report.restored.is_empty() && report.removed.is_empty());
+        for index in 0..8 {
+            let path = installer.game_dir().join(original_name(index));
+            assert_eq!(
+                std::fs::metadata(&path).unwrap().modified().unwrap(),
+                verified
+            );
+        }
+
+        installer.options.force = true;
+        let manifest = installer.load_pack().await.unwrap();
+        let report = installer.restore(manifest, None).await.unwrap();
+        // Forced, the restore runs and finds every file changed since the install
+        assert_eq!(report.skipped.len(), 8);
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restores_the_backed_up_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let installer = fixture_installer("file-mode", 4, 64);
+        let path = installer.game_dir().join(original_name(1));
+        let mode =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o750)).unwrap();
+        let mut manifest = installer.load_pack().await.unwrap();
+        stage_and_install(&installer, &mut manifest).await.unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let manifest = installer.load_pack().await.unwrap();
+        installer.restore(manifest, None).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), original(1));
+        assert_eq!(mode(&path), 0o750);
+        let _ = std::fs::remove_dir_all(installer.game_dir().parent().unwrap());
+    }
+}
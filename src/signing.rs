@@ -0,0 +1,124 @@
+use crate::hashing;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Public keys of the translation team, hex encoded
+static EMBEDDED_KEYS: &[&str] = &[];
+
+fn trusted_keys(extra_keys: &[String]) -> Result<Vec<VerifyingKey>> {
+    EMBEDDED_KEYS
+        .iter()
+        .copied()
+        .chain(extra_keys.iter().map(String::as_str))
+        .map(|hex| {
+            VerifyingKey::from_bytes(&from_hex(hex)?)
+                .with_context(|| format!("Invalid trusted key {}", hex))
+        })
+        .collect()
+}
+
+/// Detached signature next to the pack, `<pack>.sig`
+pub fn signature_path(pack: &Path) -> PathBuf {
+    let mut name = pack.as_os_str().to_os_string();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+/// SHA-256 of the pack read from `reader`, which is what its signature covers so a pack is
+/// checked a chunk at a time rather than held in memory
+pub fn pack_digest(reader: impl Read) -> std::io::Result<[u8; 32]> {
+    hashing::hash_reader(reader)
+}
+
+/// Checks `digest`, the [pack_digest] of the pack at `pack`, against its detached signature. A
+/// missing signature is only tolerated with `allow_unsigned`, a bad one never is. `extra_keys`
+/// are trusted on top of the embedded ones.
+pub fn verify_pack(
+    pack: &Path,
+    digest: &[u8; 32],
+    allow_unsigned: bool,
+    extra_keys: &[String],
+) -> Result<()> {
+    let signature_path = signature_path(pack);
+    let text = match std::fs::read_to_string(&signature_path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound && allow_unsigned => {
+            tracing::warn!(
+                "!!! [{}] is NOT signed, installing it anyway because of --allow-unsigned !!!",
+                pack.display()
+            );
+            return Ok(());
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Err(eyre!(
+                "[{}] has no signature [{}], pass --allow-unsigned to install it anyway",
+                pack.display(),
+                signature_path.display()
+            ))
+        }
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("Failed to read [{}]", signature_path.display()))
+        }
+    };
+    let signature = Signature::from_bytes(
+        &from_hex(text.trim())
+            .with_context(|| format!("[{}] is not a signature", signature_path.display()))?,
+    );
+    let keys = trusted_keys(extra_keys)?;
+    match keys
+        .iter()
+        .find(|key| key.verify(digest, &signature).is_ok())
+    {
+        Some(key) => {
+            tracing::info!(
+                "[{}] is signed by {}",
+                pack.display(),
+                to_hex(key.as_bytes())
+            );
+            Ok(())
+        }
+        None => Err(eyre!(
+            "[{}] is not signed by any of the {} trusted keys",
+            pack.display(),
+            keys.len()
+        )),
+    }
+}
+
+/// Writes `<pack>.sig` with the hex encoded secret key in `key`, returning the public key
+pub fn sign_pack(pack: &Path, key: &Path) -> Result<String> {
+    let text = std::fs::read_to_string(key)
+        .with_context(|| format!("Failed to read key [{}]", key.display()))?;
+    let signing_key = SigningKey::from_bytes(
+        &from_hex(text.trim()).with_context(|| format!("[{}] is not a key", key.display()))?,
+    );
+    let digest = std::fs::File::open(pack)
+        .and_then(pack_digest)
+        .with_context(|| format!("Failed to read [{}]", pack.display()))?;
+    let signature = signing_key.sign(&digest);
+    let signature_path = signature_path(pack);
+    std::fs::write(&signature_path, to_hex(&signature.to_bytes()))
+        .with_context(|| format!("Failed to write [{}]", signature_path.display()))?;
+    tracing::info!("Wrote [{}]", signature_path.display());
+    Ok(to_hex(signing_key.verifying_key().as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex<const N: usize>(text: &str) -> Result<[u8; N]> {
+    if text.len() != N * 2 || !text.is_ascii() {
+        return Err(eyre!("Expected {} hex digits, found {}", N * 2, text.len()));
+    }
+    let mut bytes = [0; N];
+    for (byte, pair) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair)?;
+        *byte = u8::from_str_radix(pair, 16).with_context(|| format!("Invalid hex {}", pair))?;
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,243 @@
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateFile {
+    #[serde(default)]
+    pub installs: BTreeMap<PathBuf, InstallState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallState {
+    pub installed_at: SystemTime,
+    #[serde(default)]
+    pub backup: Option<PathBuf>,
+    #[serde(default)]
+    pub no_backup: bool,
+    #[serde(default)]
+    pub pack_version: Option<String>,
+    #[serde(default)]
+    pub in_progress: bool,
+    #[serde(default)]
+    pub created: Vec<PathBuf>,
+    #[serde(default)]
+    pub overwritten: Vec<PathBuf>,
+    /// Directories the install created, removed again by the restore once empty
+    #[serde(default)]
+    pub created_dirs: Vec<PathBuf>,
+    /// AlienIsolationData dir the recorded paths are relative to
+    #[serde(default)]
+    pub base: Option<PathBuf>,
+    /// CRC32 of every installed file, used to patch only what changed in the next pack
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, u32>,
+}
+
+impl InstallState {
+    pub fn describe(&self) -> String {
+        let backup = match (&self.backup, self.no_backup) {
+            (Some(backup), _) => backup.display().to_string(),
+            (None, true) => "none, installed with --no-backup".to_string(),
+            (None, false) => "none".to_string(),
+        };
+        format!(
+            "pack {} installed at {}{}\nbackup: {}\n{} created and {} overwritten files",
+            self.pack_version.as_deref().unwrap_or("unknown"),
+            time::OffsetDateTime::from(self.installed_at),
+            if self.in_progress {
+                " (interrupted)"
+            } else {
+                ""
+            },
+            backup,
+            self.created.len(),
+            self.overwritten.len()
+        )
+    }
+}
+
+impl StateFile {
+    /// Where the CLI keeps the state, the hash cache and the progress journal
+    pub fn default_dir() -> Option<PathBuf> {
+        directories::ProjectDirs::from("", "", "alien").map(|dirs| dirs.data_dir().to_path_buf())
+    }
+
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join("alien-state.json")
+    }
+
+    pub fn load(dir: &Path) -> Result<StateFile> {
+        let path = StateFile::path(dir);
+        if !path.exists() {
+            return Ok(StateFile::default());
+        }
+        let bytes = std::fs::read(&path)
+            .with_context(|| format!("Failed to read state [{}]", path.display()))?;
+        serde_json::from_slice(&bytes)
+            .with_context(|| format!("Failed to parse state [{}]", path.display()))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = StateFile::path(dir);
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write state [{}]", path.display()))?;
+        tracing::info!("Saved state to [{}]", path.display());
+        Ok(())
+    }
+
+    pub fn get(&self, game_dir: &Path) -> Option<&InstallState> {
+        self.installs.get(game_dir)
+    }
+
+    pub fn record(dir: &Path, game_dir: &Path, state: InstallState) -> Result<()> {
+        let mut file = StateFile::load(dir)?;
+        file.installs.insert(game_dir.to_path_buf(), state);
+        file.save(dir)
+    }
+
+    pub fn forget(dir: &Path, game_dir: &Path) -> Result<()> {
+        let mut file = StateFile::load(dir)?;
+        if file.installs.remove(game_dir).is_some() {
+            file.save(dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Size, mtime and CRC32 of game files seen by earlier runs, so unchanged files are not re-read
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    game_dir: PathBuf,
+    pack_version: String,
+    #[serde(default)]
+    entries: BTreeMap<PathBuf, CachedHash>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+struct CachedHash {
+    size: u64,
+    modified: SystemTime,
+    crc32: u32,
+}
+
+impl HashCache {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join("alien-hash-cache.json")
+    }
+
+    /// Loads the cache, starting over when it belongs to another game dir or pack version
+    pub fn load(dir: &Path, game_dir: &Path, pack_version: &str) -> HashCache {
+        let fresh = HashCache {
+            game_dir: game_dir.to_path_buf(),
+            pack_version: pack_version.to_string(),
+            entries: BTreeMap::new(),
+        };
+        let bytes = match std::fs::read(HashCache::path(dir)) {
+            Ok(bytes) => bytes,
+            Err(_) => return fresh,
+        };
+        match serde_json::from_slice::<HashCache>(&bytes) {
+            Ok(cache) if cache.game_dir == game_dir && cache.pack_version == pack_version => cache,
+            Ok(_) => {
+                tracing::info!("Game dir or pack changed, discarding the hash cache");
+                fresh
+            }
+            Err(error) => {
+                tracing::warn!("Discarding unreadable hash cache: {}", error);
+                fresh
+            }
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = HashCache::path(dir);
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write hash cache [{}]", path.display()))
+    }
+
+    pub fn get(&self, striped: &Path, size: u64, modified: SystemTime) -> Option<u32> {
+        self.entries
+            .get(striped)
+            .filter(|cached| cached.size == size && cached.modified == modified)
+            .map(|cached| cached.crc32)
+    }
+
+    pub fn insert(&mut self, striped: PathBuf, size: u64, modified: SystemTime, crc32: u32) {
+        self.entries.insert(
+            striped,
+            CachedHash {
+                size,
+                modified,
+                crc32,
+            },
+        );
+    }
+}
+
+pub struct ProgressJournal {
+    file: Option<Mutex<std::fs::File>>,
+}
+
+impl ProgressJournal {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join("alien-progress.txt")
+    }
+
+    pub fn open(dir: &Path, append: bool) -> Result<ProgressJournal> {
+        let path = ProgressJournal::path(dir);
+        std::fs::create_dir_all(dir)?;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(&path)
+            .with_context(|| format!("Failed to open progress journal [{}]", path.display()))?;
+        Ok(ProgressJournal {
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    pub fn record(&self, striped: &Path) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(error) = writeln!(file, "{}", striped.display()) {
+            tracing::warn!(
+                "Failed to record progress for [{}]: {}",
+                striped.display(),
+                error
+            );
+        }
+    }
+
+    pub fn read(dir: &Path) -> Result<Vec<PathBuf>> {
+        let path = ProgressJournal::path(dir);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = std::fs::File::open(&path)?;
+        BufReader::new(file)
+            .lines()
+            .map(|line| Ok(PathBuf::from(line?)))
+            .collect()
+    }
+
+    pub fn remove(dir: &Path) -> Result<()> {
+        let path = ProgressJournal::path(dir);
+        match path.exists() {
+            true => Ok(std::fs::remove_file(path)?),
+            false => Ok(()),
+        }
+    }
+}
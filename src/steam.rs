@@ -0,0 +1,270 @@
+use crate::path_structure;
+use color_eyre::eyre::{eyre, Context};
+use color_eyre::Result;
+use std::path::{Path, PathBuf};
+
+pub static ALIEN_ISOLATION_APP_ID: u32 = 214490;
+
+const STATE_FULLY_INSTALLED: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Vdf {
+    Value(String),
+    Object(Vec<(String, Vdf)>),
+}
+
+impl Vdf {
+    pub fn parse(text: &str) -> Result<Vdf> {
+        let mut tokens = tokenize(text)?.into_iter();
+        let entries = parse_object(&mut tokens, false)?;
+        Ok(Vdf::Object(entries))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Vdf> {
+        match self {
+            Vdf::Value(_) => None,
+            Vdf::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Vdf::Value(value) => Some(value),
+            Vdf::Object(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppManifest {
+    pub app_id: u32,
+    pub name: String,
+    pub state_flags: u32,
+    pub install_dir: String,
+}
+
+impl AppManifest {
+    pub fn parse(text: &str) -> Result<AppManifest> {
+        let vdf = Vdf::parse(text)?;
+        let app_state = vdf
+            .get("AppState")
+            .ok_or(eyre!("appmanifest has no AppState"))?;
+        let field = |key: &str| {
+            app_state
+                .get(key)
+                .and_then(Vdf::as_str)
+                .ok_or(eyre!("appmanifest has no {}", key))
+        };
+        Ok(AppManifest {
+            app_id: field("appid")?.parse().context("Invalid appid")?,
+            name: field("name")?.to_string(),
+            state_flags: field("StateFlags")?.parse().context("Invalid StateFlags")?,
+            install_dir: field("installdir")?.to_string(),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<AppManifest> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read [{}]", path.display()))?;
+        AppManifest::parse(&text).with_context(|| format!("Failed to parse [{}]", path.display()))
+    }
+
+    pub fn is_fully_installed(&self) -> bool {
+        self.state_flags == STATE_FULLY_INSTALLED
+    }
+}
+
+pub fn app_manifest_path(game_dir: &Path) -> Option<PathBuf> {
+    path_structure::steam_apps_dir(game_dir)
+        .map(|dir| dir.join(format!("appmanifest_{}.acf", ALIEN_ISOLATION_APP_ID)))
+}
+
+pub fn read_app_manifest(game_dir: &Path) -> Result<Option<AppManifest>> {
+    match app_manifest_path(game_dir) {
+        Some(path) if path.exists() => Ok(Some(AppManifest::read(&path)?)),
+        _ => Ok(None),
+    }
+}
+
+pub fn ensure_installed(game_dir: &Path) -> Result<()> {
+    match read_app_manifest(game_dir)? {
+        Some(manifest) if !manifest.is_fully_installed() => Err(eyre!(
+            "Alien Isolation is installed but still downloading (StateFlags {}); finish the download before patching.",
+            manifest.state_flags
+        )),
+        Some(_) => Ok(()),
+        None => {
+            tracing::warn!("No Steam appmanifest found, skipping the installation state check");
+            Ok(())
+        }
+    }
+}
+
+pub fn validate_url() -> String {
+    format!("steam://validate/{}", ALIEN_ISOLATION_APP_ID)
+}
+
+/// Hands the validate URL to the Steam client, which re-downloads every file that differs
+/// from its depot. Files the pack added are not in the depot and are left in place.
+pub fn open_validate() -> Result<()> {
+    let url = validate_url();
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    let status = command
+        .arg(&url)
+        .status()
+        .with_context(|| format!("Failed to open [{}]", url))?;
+    if !status.success() {
+        return Err(eyre!("Opening [{}] exited with {}", url, status));
+    }
+    Ok(())
+}
+
+enum Token {
+    Str(String),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some(escaped) => value.push(escaped),
+                            None => return Err(eyre!("Unterminated escape in VDF")),
+                        },
+                        Some(ch) => value.push(ch),
+                        None => return Err(eyre!("Unterminated string in VDF")),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for ch in chars.by_ref() {
+                    if ch == '\n' {
+                        break;
+                    }
+                }
+            }
+            ch if ch.is_whitespace() => {}
+            ch => return Err(eyre!("Unexpected character [{}] in VDF", ch)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_object(
+    tokens: &mut impl Iterator<Item = Token>,
+    nested: bool,
+) -> Result<Vec<(String, Vdf)>> {
+    let mut entries = Vec::new();
+    loop {
+        let key = match tokens.next() {
+            Some(Token::Str(key)) => key,
+            Some(Token::Close) if nested => return Ok(entries),
+            None if !nested => return Ok(entries),
+            Some(Token::Close) => return Err(eyre!("Unexpected [}}] in VDF")),
+            Some(Token::Open) => return Err(eyre!("Unexpected [{{] in VDF")),
+            None => return Err(eyre!("Unexpected end of VDF")),
+        };
+        let value = match tokens.next() {
+            Some(Token::Str(value)) => Vdf::Value(value),
+            Some(Token::Open) => Vdf::Object(parse_object(tokens, true)?),
+            _ => return Err(eyre!("Missing value for [{}] in VDF", key)),
+        };
+        entries.push((key, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ensure_installed, validate_url, AppManifest, ALIEN_ISOLATION_APP_ID};
+    use crate::installer::tests::scratch_dir;
+
+    fn app_manifest(state_flags: u32) -> String {
+        format!(
+            r#""AppState"
+{{
+	"appid"		"214490"
+	"Universe"		"1"
+	"name"		"Alien: Isolation"
+	"StateFlags"		"{}"
+	"installdir"		"Alien Isolation"
+	// Steam writes nested sections too
+	"UserConfig"
+	{{
+		"language"		"english"
+	}}
+}}
+"#,
+            state_flags
+        )
+    }
+
+    #[test]
+    fn parses_an_installed_app_manifest() {
+        let manifest = AppManifest::parse(&app_manifest(4)).unwrap();
+        assert_eq!(
+            manifest,
+            AppManifest {
+                app_id: ALIEN_ISOLATION_APP_ID,
+                name: "Alien: Isolation".to_string(),
+                state_flags: 4,
+                install_dir: "Alien Isolation".to_string(),
+            }
+        );
+        assert!(manifest.is_fully_installed());
+    }
+
+    #[test]
+    fn parses_a_downloading_app_manifest() {
+        let manifest = AppManifest::parse(&app_manifest(1026)).unwrap();
+        assert_eq!(manifest.state_flags, 1026);
+        assert!(!manifest.is_fully_installed());
+    }
+
+    #[test]
+    fn refuses_a_game_still_downloading() {
+        let steam_apps = scratch_dir("app-manifest").join("steamapps");
+        let game_dir = steam_apps
+            .join("common")
+            .join("Alien Isolation")
+            .join("AlienIsolationData");
+        std::fs::create_dir_all(&game_dir).unwrap();
+        let path = steam_apps.join(format!("appmanifest_{}.acf", ALIEN_ISOLATION_APP_ID));
+
+        std::fs::write(&path, app_manifest(1026)).unwrap();
+        let error = ensure_installed(&game_dir).unwrap_err();
+        assert!(error.to_string().contains("still downloading"), "{}", error);
+        std::fs::write(&path, app_manifest(4)).unwrap();
+        ensure_installed(&game_dir).unwrap();
+        let _ = std::fs::remove_dir_all(steam_apps.parent().unwrap());
+    }
+
+    #[test]
+    fn the_validate_url_names_the_game() {
+        assert_eq!(validate_url(), "steam://validate/214490");
+        assert!(validate_url().ends_with(&ALIEN_ISOLATION_APP_ID.to_string()));
+    }
+}
@@ -0,0 +1,113 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::path::{Path, PathBuf};
+
+/// Path to modify for a file under `root`, through symlinked components to the file they point
+/// at. Without `follow` a file reached via a symlink is refused instead. Removals go through
+/// [resolve_link] instead.
+pub fn resolve(root: &Path, path: &Path, follow: bool) -> Result<PathBuf> {
+    let relative = match path.strip_prefix(root) {
+        Ok(_) if root.as_os_str().is_empty() => return Ok(path.to_path_buf()),
+        Ok(relative) => relative,
+        Err(_) => return Ok(path.to_path_buf()),
+    };
+    let mut current = root.to_path_buf();
+    let mut links = Vec::new();
+    for component in relative.components() {
+        current.push(component);
+        match std::fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => links.push(current.clone()),
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    if links.is_empty() {
+        return Ok(path.to_path_buf());
+    }
+    if !follow {
+        return Err(eyre!(
+            "Refusing to modify [{}], it is reached through the symlink [{}]",
+            path.display(),
+            links[0].display()
+        ));
+    }
+    for link in &links {
+        tracing::warn!(
+            "[{}] is reached through the symlink [{}]",
+            path.display(),
+            link.display()
+        );
+    }
+    match std::fs::canonicalize(path) {
+        Ok(target) => Ok(target),
+        Err(_) => Ok(path.to_path_buf()),
+    }
+}
+
+/// Path to remove for a file under `root`, like [resolve] through symlinked directories but
+/// leaving a symlinked file itself as is, so the link goes rather than the file it points at
+pub fn resolve_link(root: &Path, path: &Path, follow: bool) -> Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok(resolve(root, parent, follow)?.join(name)),
+        _ => resolve(root, path, follow),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{resolve, resolve_link};
+    use crate::installer::tests::scratch_dir;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn removing_a_symlinked_file_leaves_its_target() {
+        let scratch = scratch_dir("symlinked-file");
+        let root = scratch.join("game");
+        std::fs::create_dir_all(root.join("data")).unwrap();
+        std::fs::write(scratch.join("target.txt"), "target").unwrap();
+        let link = root.join("data").join("link.txt");
+        symlink(scratch.join("target.txt"), &link).unwrap();
+
+        let path = resolve_link(&root, &link, true).unwrap();
+        assert_eq!(path, link);
+        std::fs::remove_file(&path).unwrap();
+        assert!(std::fs::symlink_metadata(&link).is_err());
+        assert_eq!(
+            std::fs::read(scratch.join("target.txt")).unwrap(),
+            b"target"
+        );
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+
+    #[test]
+    fn a_directory_symlink_mid_path_is_followed_or_refused() {
+        let scratch = scratch_dir("symlinked-dir-mid-path");
+        let root = scratch.join("game");
+        std::fs::create_dir_all(root.join("data")).unwrap();
+        let drive = scratch.join("drive").join("videos");
+        std::fs::create_dir_all(&drive).unwrap();
+        std::fs::write(drive.join("intro.bik"), "intro").unwrap();
+        symlink(drive.join("intro.bik"), drive.join("outro.bik")).unwrap();
+        symlink(&drive, root.join("data").join("videos")).unwrap();
+        let drive = std::fs::canonicalize(&drive).unwrap();
+        let videos = root.join("data").join("videos");
+
+        let intro = videos.join("intro.bik");
+        assert_eq!(
+            resolve(&root, &intro, true).unwrap(),
+            drive.join("intro.bik")
+        );
+        assert_eq!(
+            resolve_link(&root, &intro, true).unwrap(),
+            drive.join("intro.bik")
+        );
+        assert!(resolve(&root, &intro, false).is_err());
+        assert!(resolve_link(&root, &intro, false).is_err());
+
+        let outro = resolve_link(&root, &videos.join("outro.bik"), true).unwrap();
+        assert_eq!(outro, drive.join("outro.bik"));
+        std::fs::remove_file(&outro).unwrap();
+        assert_eq!(std::fs::read(drive.join("intro.bik")).unwrap(), b"intro");
+        let _ = std::fs::remove_dir_all(&scratch);
+    }
+}
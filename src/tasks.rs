@@ -0,0 +1,119 @@
+use color_eyre::eyre::Context;
+use color_eyre::Result;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use std::future::Future;
+
+/// Files open at once by default, well below the 256 descriptors macOS allows a process
+pub const DEFAULT_IO_LIMIT: usize = 32;
+
+/// Runs the futures, at most `limit` at a time, and returns the results in input order, so
+/// collecting them reports the failure that comes first in manifest order no matter which
+/// future failed first in time. With `fail_fast` no further futures are started after the
+/// first failure, those already running still finish so none is dropped half way through a
+/// write, and only the results of the started futures are returned.
+pub async fn join_all<T, F>(
+    futures: impl IntoIterator<Item = F>,
+    limit: usize,
+    fail_fast: bool,
+) -> Vec<Result<T>>
+where
+    F: Future<Output = Result<T>>,
+{
+    let limit = limit.max(1);
+    if !fail_fast {
+        return futures::stream::iter(futures)
+            .buffered(limit)
+            .collect()
+            .await;
+    }
+    let mut futures = futures.into_iter().enumerate();
+    let mut running = FuturesUnordered::new();
+    let mut completed = Vec::new();
+    let mut failed = false;
+    loop {
+        while !failed && running.len() < limit {
+            match futures.next() {
+                Some((index, future)) => running.push(async move { (index, future.await) }),
+                None => break,
+            }
+        }
+        match running.next().await {
+            Some((index, result)) => {
+                if result.is_err() && !failed {
+                    tracing::warn!("Starting no more tasks after a failure");
+                    failed = true;
+                }
+                completed.push((index, result));
+            }
+            None => break,
+        }
+    }
+    completed.sort_by_key(|(index, _)| *index);
+    completed.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Runs synchronous zip or file work on the blocking pool so it does not stall the runtime,
+/// logging how long it took at debug level since it runs per file. Errors keep their context, a
+/// panic becomes an error.
+pub async fn blocking<T, F>(label: &'static str, work: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let instant = std::time::Instant::now();
+    let result = tokio::task::spawn_blocking(work)
+        .await
+        .with_context(|| format!("Blocking task to {} failed", label))?;
+    tracing::debug!("Blocking task to {} take {:?}", label, instant.elapsed());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::eyre;
+
+    #[tokio::test]
+    async fn reports_the_lowest_index_failure() {
+        let futures = (0..6).map(|index| async move {
+            if index == 1 {
+                // Fails last in time, after task 4 has failed
+                for _ in 0..16 {
+                    tokio::task::yield_now().await;
+                }
+                return Err(eyre!("task {}", index));
+            }
+            if index == 4 {
+                return Err(eyre!("task {}", index));
+            }
+            Ok(index)
+        });
+        let results = join_all(futures, 8, false).await;
+        assert_eq!(results.len(), 6);
+        let error = results.into_iter().collect::<Result<Vec<_>>>().unwrap_err();
+        assert_eq!(error.to_string(), "task 1");
+    }
+
+    #[tokio::test]
+    async fn fail_fast_lets_running_tasks_finish() {
+        let finished = std::sync::atomic::AtomicUsize::new(0);
+        let finished = &finished;
+        let futures = (0..8).map(|index| async move {
+            if index == 0 {
+                return Err(eyre!("task {}", index));
+            }
+            for _ in 0..16 {
+                tokio::task::yield_now().await;
+            }
+            finished.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(index)
+        });
+        let results = join_all(futures, 4, true).await;
+        // Tasks 1 to 3 were running when task 0 failed, the rest never started
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_err());
+        assert!(results[1..].iter().all(Result::is_ok));
+        assert_eq!(finished.load(std::sync::atomic::Ordering::Relaxed), 3);
+    }
+}